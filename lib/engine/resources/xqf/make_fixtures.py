@@ -0,0 +1,103 @@
+# 按XQStudio的格式（参考ElephantEye的XQF2PGN）生成xqf.rs测试用的棋谱文件
+# 用法：python3 make_fixtures.py lib/engine/resources/xqf
+import struct, sys
+
+MASK = b"[(C) Copyright Mr. Dong Shiwei.]"
+FIELDS = {"title": 0x50, "event": 0xD0, "date": 0x110, "site": 0x120,
+          "red": 0x130, "black": 0x140, "opening": 0x150, "author": 0x1E0}
+RESULT = {"*": 0, "1-0": 1, "0-1": 2, "1/2-1/2": 3}
+
+def coord(s):
+    return (ord(s[0]) - ord('a')) * 10 + int(s[1])
+
+def s54(x):
+    return (x * x * 54 + 221) & 0xff
+
+def write(path, version, tag_keys, pieces, info, result, root_comment, tree):
+    tag = bytearray(16)
+    tag[0:2] = b"XQ"; tag[2] = version
+    if version >= 11:
+        tag[3:16] = bytes(tag_keys)
+        off_piece = (s54(tag[13]) * tag[13]) & 0xff
+        off_src = (s54(tag[14]) * off_piece) & 0xff
+        off_dst = (s54(tag[15]) * off_src) & 0xff
+        off_comment = (tag[12] * 256 + tag[13]) % 32000 + 767
+        args = [tag[8 + i] | (tag[12 + i] & tag[3]) for i in range(4)]
+        stream = bytes(args[i % 4] & MASK[i] for i in range(32))
+    else:
+        off_piece = off_src = off_dst = off_comment = 0
+        stream = bytes(32)
+    head = bytearray(1024)
+    head[0:16] = tag
+    raw = [coord(p) if p else 0xff for p in pieces]
+    for i in range(32):
+        # 12版以后棋子位置循环移动
+        slot = i if version < 12 else (off_piece + 1 + i) % 32
+        head[16 + i] = (raw[slot] + off_piece) & 0xff
+    head[48 + 3] = RESULT[result]
+    for k, v in info.items():
+        b = v.encode("gbk")
+        head[FIELDS[k]] = len(b)
+        head[FIELDS[k] + 1:FIELDS[k] + 1 + len(b)] = b
+    body = bytearray()
+
+    def emit(src, dst, has_next, has_right, comment):
+        c = comment.encode("gbk") if comment else b""
+        rec = bytearray([(src + 24 + off_src) & 0xff, (dst + 32 + off_dst) & 0xff, 0, 0])
+        if version < 11:
+            rec[2] = (0xf0 if has_next else 0) | (0x0f if has_right else 0)
+            rec += struct.pack("<i", len(c))
+        else:
+            rec[2] = (0x80 if has_next else 0) | (0x40 if has_right else 0) | (0x20 if c else 0)
+            if c:
+                rec += struct.pack("<i", len(c) + off_comment)
+        rec += c
+        body.extend(rec)
+
+    # tree是同一层的着法列表，每个节点为(着法, 注释, 后续着法)
+    def walk(siblings):
+        for i, (mv, comment, children) in enumerate(siblings):
+            emit(coord(mv[:2]), coord(mv[2:]), bool(children), i + 1 < len(siblings), comment)
+            walk(children)
+
+    emit(0, 0, bool(tree), False, root_comment)
+    walk(tree)
+    base = len(head)
+    enc = bytes((b + stream[(base + i) % 32]) & 0xff for i, b in enumerate(body))
+    open(path, "wb").write(bytes(head) + enc)
+
+def chain(moves):
+    # moves是主线上的(着法, 注释, [变着...])，变着从同一步开始
+    if not moves:
+        return []
+    mv, comment, variations = moves[0]
+    return [(mv, comment, chain(moves[1:]))] + [chain(v)[0] for v in variations]
+
+START = ["a0", "b0", "c0", "d0", "e0", "f0", "g0", "h0", "i0", "b2", "h2", "a3", "c3", "e3", "g3", "i3",
+         "a9", "b9", "c9", "d9", "e9", "f9", "g9", "h9", "i9", "b7", "h7", "a6", "c6", "e6", "g6", "i6"]
+
+out = sys.argv[1]
+# 10版，不加密，标准开局，有变着和注释
+write(out + "/plain_v10.xqf", 10, None, START,
+      {"title": "中炮对屏风马", "event": "测试赛", "date": "2024.05.01", "site": "北京",
+       "red": "张三", "black": "李四", "opening": "中炮对屏风马"},
+      "1-0", "中炮对屏风马的常见开局",
+      chain([("h2e2", "中炮", []),
+             ("h9g7", None, [[("h7e7", "顺炮", []), ("h0g2", None, [])]]),
+             ("h0g2", None, []), ("i9h9", None, []), ("i0h0", None, []), ("b9c7", "屏风马\n双方布局完成", [])]))
+# 11版，加密，棋子位置不循环移动
+write(out + "/encrypted_v11.xqf", 11,
+      [0x5d, 0x00, 0x00, 0x00, 0x00, 0x37, 0xb2, 0x04, 0x6e, 0x00, 0x00, 0x00, 0x00],
+      START, {"title": "仙人指路对卒底炮", "red": "王五", "black": "赵六"},
+      "1/2-1/2", None,
+      chain([("c3c4", "仙人指路", []), ("h7c7", "卒底炮", [[("g6g5", "对兵局", [])]]), ("h0g2", None, [])]))
+# 18版，加密，棋子位置循环移动，黑先的残局
+pieces = [None] * 32
+pieces[0], pieces[4], pieces[20], pieces[27] = "a0", "d0", "e9", "e3"
+write(out + "/encrypted_v18.xqf", 18,
+      [0xa7, 0x00, 0x00, 0x00, 0x00, 0x91, 0x2c, 0xf3, 0x58, 0x1e, 0xc4, 0x9d, 0x5f],
+      pieces, {"title": "单车胜单卒", "event": "残局练习", "author": "测试"},
+      "1-0", "黑先，红胜",
+      chain([("e3d3", None, []),
+             ("a0a9", "将军", [[("a0a8", None, [])], [("d0d1", "变着：出帅", [])]]),
+             ("e9e8", None, []), ("a9a8", None, []), ("e8e7", "黑将只能上来", [])]))
@@ -0,0 +1,787 @@
+use crate::board::{mated_value, Board, Player, RecordTable};
+use crate::constant::{MAX_DEPTH, RECORD_SIZE, STARTPOS};
+use crate::engine::{search_book, time_budget, PreLoad};
+use crate::eval::EvalParams;
+use crate::nnue::Network;
+use crate::rules::{judge, GameResult, Reason};
+use std::io::{BufRead, BufReader, Write};
+use std::process::{Child, ChildStdin, Command, Stdio};
+use std::sync::atomic::AtomicBool;
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+// 引擎对战：两个引擎（外部UCCI/UCI程序或者进程内的搜索）用同一开局轮流执红各下一盘，
+// 统计胜和负，计算等级分差和置信区间，用SPRT判断是否可以提前结束
+
+// 等待外部引擎握手和isready的时间
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+// 固定深度对局时等待着法的最长时间
+const DEPTH_TIMEOUT: Duration = Duration::from_secs(600);
+// 限时对局时超过分配的时间这么久还没有着法，就不再等待
+const TIMEOUT_GRACE: Duration = Duration::from_secs(5);
+
+// 外部引擎的协议
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Protocol {
+    Ucci,
+    Uci,
+}
+
+// 时间控制
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TimeControl {
+    Depth(i32),                                    // 每步固定深度
+    MoveTime(Duration),                            // 每步固定时间
+    Clock { base: Duration, increment: Duration }, // 包干时间加每步加时
+}
+
+// 轮到一方走棋时交给引擎的信息
+pub struct GameState<'a> {
+    pub board: &'a Board,    // 当前局面
+    pub fen: &'a str,        // 开局局面
+    pub moves: &'a [String], // 开局局面之后的全部着法
+    pub time_control: TimeControl,
+    pub clocks: [Duration; 2], // 双方的剩余时间，按Player::value()索引
+}
+
+// 引擎给出的着法和走棋方视角的分数，没有着法时认输
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Thought {
+    pub best_move: Option<String>,
+    pub score: Option<i32>,
+}
+
+// 参加对战的引擎
+pub trait Contestant {
+    fn name(&self) -> &str;
+    fn new_game(&mut self) -> Result<(), String>;
+    fn think(&mut self, state: &GameState) -> Result<Thought, String>;
+}
+
+// 通过标准输入输出通信的外部引擎
+pub struct EngineProcess {
+    pub name: String,
+    protocol: Protocol,
+    child: Child,
+    stdin: ChildStdin,
+    lines: Receiver<String>,
+}
+
+impl EngineProcess {
+    // 启动引擎并完成握手，options为启动后发送的setoption
+    pub fn start(
+        command: &str,
+        args: &[String],
+        protocol: Protocol,
+        options: &[(String, String)],
+    ) -> Result<Self, String> {
+        let mut child = Command::new(command)
+            .args(args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()
+            .map_err(|e| format!("{}: {}", command, e))?;
+        let stdin = child.stdin.take().unwrap();
+        let stdout = child.stdout.take().unwrap();
+        // 单独的线程读输出，这样等待着法时可以设置超时
+        let (sender, lines) = mpsc::channel();
+        thread::spawn(move || {
+            for line in BufReader::new(stdout)
+                .lines()
+                .map_while(Result::ok)
+            {
+                if sender.send(line).is_err() {
+                    break;
+                }
+            }
+        });
+        let mut engine = EngineProcess {
+            name: command.to_owned(),
+            protocol,
+            child,
+            stdin,
+            lines,
+        };
+        let (hello, ok) = match protocol {
+            Protocol::Ucci => ("ucci", "ucciok"),
+            Protocol::Uci => ("uci", "uciok"),
+        };
+        engine.send(hello)?;
+        loop {
+            let line = engine.read_line(HANDSHAKE_TIMEOUT)?;
+            if let Some(name) = line.strip_prefix("id name ") {
+                engine.name = name.trim().to_owned();
+            }
+            if line.trim() == ok {
+                break;
+            }
+        }
+        if protocol == Protocol::Ucci {
+            engine.send("setoption usemillisec true")?;
+        }
+        for (name, value) in options {
+            let cmd = match protocol {
+                Protocol::Ucci => format!("setoption {} {}", name, value),
+                Protocol::Uci => format!("setoption name {} value {}", name, value),
+            };
+            engine.send(&cmd)?;
+        }
+        engine.ready()?;
+        Ok(engine)
+    }
+    fn send(&mut self, cmd: &str) -> Result<(), String> {
+        writeln!(self.stdin, "{}", cmd)
+            .and_then(|_| self.stdin.flush())
+            .map_err(|e| format!("{}: {}", self.name, e))
+    }
+    fn read_line(&mut self, timeout: Duration) -> Result<String, String> {
+        self.lines
+            .recv_timeout(timeout)
+            .map_err(|e| match e {
+                RecvTimeoutError::Timeout => format!("{}: 没有响应", self.name),
+                RecvTimeoutError::Disconnected => format!("{}: 已退出", self.name),
+            })
+    }
+    fn ready(&mut self) -> Result<(), String> {
+        self.send("isready")?;
+        while self.read_line(HANDSHAKE_TIMEOUT)?.trim() != "readyok" {}
+        Ok(())
+    }
+    fn go_command(&self, state: &GameState) -> String {
+        let ms = |d: Duration| d.as_millis();
+        let (own, opp) = (
+            state.board.turn.value() as usize,
+            state.board.turn.next().value() as usize,
+        );
+        match (state.time_control, self.protocol) {
+            (TimeControl::Depth(depth), _) => format!("go depth {}", depth),
+            (TimeControl::MoveTime(time), Protocol::Uci) => format!("go movetime {}", ms(time)),
+            // UCCI没有movetime，用只剩一步的包干时间代替
+            (TimeControl::MoveTime(time), Protocol::Ucci) => format!("go time {} movestogo 1", ms(time)),
+            (TimeControl::Clock { increment, .. }, Protocol::Uci) => format!(
+                "go wtime {} btime {} winc {} binc {}",
+                ms(state.clocks[0]),
+                ms(state.clocks[1]),
+                ms(increment),
+                ms(increment)
+            ),
+            (TimeControl::Clock { increment, .. }, Protocol::Ucci) => format!(
+                "go time {} increment {} opptime {} oppincrement {}",
+                ms(state.clocks[own]),
+                ms(increment),
+                ms(state.clocks[opp]),
+                ms(increment)
+            ),
+        }
+    }
+}
+
+impl Contestant for EngineProcess {
+    fn name(&self) -> &str {
+        &self.name
+    }
+    fn new_game(&mut self) -> Result<(), String> {
+        if self.protocol == Protocol::Uci {
+            self.send("ucinewgame")?;
+        }
+        self.ready()
+    }
+    fn think(&mut self, state: &GameState) -> Result<Thought, String> {
+        let mut position = format!("position fen {}", state.fen);
+        if !state.moves.is_empty() {
+            position = format!("{} moves {}", position, state.moves.join(" "));
+        }
+        self.send(&position)?;
+        self.send(&self.go_command(state))?;
+        let timeout = match state.time_control {
+            TimeControl::Depth(_) => DEPTH_TIMEOUT,
+            TimeControl::MoveTime(time) => time + TIMEOUT_GRACE,
+            TimeControl::Clock { .. } => state.clocks[state.board.turn.value() as usize] + TIMEOUT_GRACE,
+        };
+        let deadline = Instant::now() + timeout;
+        let mut thought = Thought::default();
+        loop {
+            let line = self.read_line(deadline.saturating_duration_since(Instant::now()))?;
+            let mut tokens = line.split_whitespace();
+            match tokens.next() {
+                Some("info") => {
+                    if let Some(score) = parse_score(&line) {
+                        thought.score = Some(score);
+                    }
+                }
+                Some("bestmove") => {
+                    thought.best_move = tokens
+                        .next()
+                        .filter(|m| *m != "(none)")
+                        .map(|m| m.to_owned());
+                    return Ok(thought);
+                }
+                Some("nobestmove") => return Ok(thought),
+                _ => {}
+            }
+        }
+    }
+}
+
+impl Drop for EngineProcess {
+    fn drop(&mut self) {
+        let _ = self.send("quit");
+        // 等一会让引擎自己退出，不退出就强行结束
+        for _ in 0..20 {
+            if let Ok(Some(_)) = self.child.try_wait() {
+                return;
+            }
+            thread::sleep(Duration::from_millis(50));
+        }
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+// info行中的分数，支持score N、score cp N和score mate N，杀棋转换为本引擎的杀棋分数
+fn parse_score(line: &str) -> Option<i32> {
+    let mut tokens = line
+        .split_whitespace()
+        .skip_while(|t| *t != "score")
+        .skip(1);
+    match tokens.next()? {
+        "cp" => tokens.next()?.parse().ok(),
+        "mate" => {
+            let n: i32 = tokens.next()?.parse().ok()?;
+            Some(if n > 0 {
+                -mated_value(n * 2 - 1)
+            } else {
+                mated_value(-n * 2)
+            })
+        }
+        value => value.parse().ok(),
+    }
+}
+
+// 进程内的引擎，直接调用本库的搜索
+pub struct InProcess {
+    pub name: String,
+    pub threads: usize,
+    pub eval_params: EvalParams,
+    pub network: Option<Arc<Network>>,
+    pub book: Vec<PreLoad>,
+    records: Arc<RecordTable>,
+}
+
+impl InProcess {
+    pub fn new(name: &str) -> Self {
+        InProcess {
+            name: name.to_owned(),
+            threads: 1,
+            eval_params: EvalParams::default(),
+            network: None,
+            book: vec![],
+            records: Arc::new(RecordTable::new(0)),
+        }
+    }
+}
+
+impl Contestant for InProcess {
+    fn name(&self) -> &str {
+        &self.name
+    }
+    // 每盘棋用新的置换表，第一次搜索时再分配
+    fn new_game(&mut self) -> Result<(), String> {
+        self.records = Arc::new(RecordTable::new(0));
+        Ok(())
+    }
+    fn think(&mut self, state: &GameState) -> Result<Thought, String> {
+        let mut board = state.board.clone();
+        if let Some(m) = search_book(&self.book, &board) {
+            return Ok(Thought {
+                best_move: Some(m),
+                score: None,
+            });
+        }
+        if self.records.is_empty() {
+            self.records = Arc::new(RecordTable::new(RECORD_SIZE as usize));
+        }
+        board.records = self.records.clone();
+        board.stop = Arc::new(AtomicBool::new(false));
+        board.eval_params = self.eval_params.clone();
+        board.set_network(self.network.clone());
+        let (depth, time) = match state.time_control {
+            TimeControl::Depth(depth) => (depth, None),
+            TimeControl::MoveTime(time) => (MAX_DEPTH, Some(time)),
+            TimeControl::Clock { increment, .. } => {
+                let remaining = state.clocks[board.turn.value() as usize];
+                (MAX_DEPTH, Some(time_budget(remaining, increment, None)))
+            }
+        };
+        let (value, best_move) = board.timed_search_with(depth, self.threads, time, |_| {});
+        Ok(Thought {
+            best_move: best_move
+                .filter(|m| m.is_valid())
+                .map(|m| format!("{}{}", m.from.to_string(), m.to.to_string())),
+            score: Some(value),
+        })
+    }
+}
+
+// 开局：起始局面和之后的着法
+#[derive(Clone, Debug, PartialEq)]
+pub struct Opening {
+    pub fen: String,
+    pub moves: Vec<String>,
+}
+
+impl Opening {
+    pub fn startpos() -> Self {
+        Opening {
+            fen: STARTPOS.to_owned(),
+            moves: vec![],
+        }
+    }
+    // 每行一个开局：FEN（可以带fen前缀）或者startpos，后面可以跟moves和ICCS着法，#开头的行为注释
+    pub fn parse(data: &str) -> Result<Vec<Self>, String> {
+        let mut openings = vec![];
+        for (i, line) in data.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let error = |message: String| format!("line {}: {}", i + 1, message);
+            let (position, moves) = line.split_once(" moves ").unwrap_or((line, ""));
+            let fen = match position.trim() {
+                "startpos" => STARTPOS,
+                fen => fen.strip_prefix("fen ").unwrap_or(fen).trim(),
+            };
+            let mut parts = fen.split_whitespace();
+            let valid_fen = parts
+                .next()
+                .is_some_and(|p| p.split('/').count() == 10)
+                && matches!(parts.next(), Some("w" | "r" | "b"));
+            if !valid_fen {
+                return Err(error(format!("invalid fen {}", fen)));
+            }
+            let mut board = Board::from_fen(fen);
+            if board.king_position(Player::Red).is_none() || board.king_position(Player::Black).is_none() {
+                return Err(error(format!("invalid fen {}", fen)));
+            }
+            let fen = board.to_fen();
+            let mut opening = Opening { fen, moves: vec![] };
+            for s in moves.split_whitespace() {
+                match board.parse_move(s) {
+                    Some(m) if board.legal_moves().contains(&m) => board.do_move(&m, true),
+                    _ => return Err(error(format!("illegal move {}", s))),
+                }
+                opening.moves.push(s.to_owned());
+            }
+            openings.push(opening);
+        }
+        Ok(openings)
+    }
+}
+
+// 对局设置
+#[derive(Clone, Debug)]
+pub struct MatchConfig {
+    pub time_control: TimeControl,
+    pub time_margin: Duration,             // 超出分配的时间多少才判超时负
+    pub max_plies: usize,                  // 超过这个步数判和
+    pub resign: Option<(i32, usize)>,      // 一方连续moves步的分数不高于-score时判负
+    pub draw: Option<(usize, i32, usize)>, // 第ply步以后双方连续moves步的分数绝对值都不超过score时判和
+}
+
+impl Default for MatchConfig {
+    fn default() -> Self {
+        MatchConfig {
+            time_control: TimeControl::Clock {
+                base: Duration::from_secs(10),
+                increment: Duration::from_millis(100),
+            },
+            time_margin: Duration::from_millis(200),
+            max_plies: 300,
+            resign: Some((1000, 4)),
+            draw: Some((60, 10, 8)),
+        }
+    }
+}
+
+// 一盘棋的记录
+#[derive(Clone, Debug)]
+pub struct GameRecord {
+    pub red: String,
+    pub black: String,
+    pub fen: String,
+    pub moves: Vec<String>, // 开局局面之后的全部着法，包括开局着法
+    pub result: GameResult,
+    pub reason: String,
+}
+
+// 下一盘棋。引擎出错、超时或者走出不合法的着法时判负
+pub fn play_game(
+    red: &mut dyn Contestant,
+    black: &mut dyn Contestant,
+    opening: &Opening,
+    config: &MatchConfig,
+) -> GameRecord {
+    let mut board = Board::from_fen(&opening.fen);
+    for s in opening.moves.iter() {
+        if let Some(m) = board.parse_move(s) {
+            board.do_move(&m, true);
+        }
+    }
+    let mut record = GameRecord {
+        red: red.name().to_owned(),
+        black: black.name().to_owned(),
+        fen: opening.fen.clone(),
+        moves: opening.moves.clone(),
+        result: GameResult::Draw,
+        reason: String::new(),
+    };
+    let mut engines: [&mut dyn Contestant; 2] = [red, black];
+    let (result, reason) = match engines
+        .iter_mut()
+        .position(|engine| engine.new_game().is_err())
+    {
+        Some(i) => (GameResult::loss(player_of(i)), "引擎出错".to_owned()),
+        None => play_moves(&mut engines, &mut board, &mut record.moves, &opening.fen, config),
+    };
+    record.result = result;
+    record.reason = reason;
+    record
+}
+
+fn player_of(index: usize) -> Player {
+    if index == 0 {
+        Player::Red
+    } else {
+        Player::Black
+    }
+}
+
+fn play_moves(
+    engines: &mut [&mut dyn Contestant; 2],
+    board: &mut Board,
+    moves: &mut Vec<String>,
+    fen: &str,
+    config: &MatchConfig,
+) -> (GameResult, String) {
+    let mut clocks = match config.time_control {
+        TimeControl::Clock { base, .. } => [base; 2],
+        _ => [Duration::ZERO; 2],
+    };
+    // 双方每步报告的分数，用于判负和判和
+    let mut scores: [Vec<Option<i32>>; 2] = [vec![], vec![]];
+    loop {
+        if let Some((result, reason)) = judge(board) {
+            return (result, reason.to_string());
+        }
+        if board.move_history.len() >= config.max_plies {
+            return (GameResult::Draw, "步数超限".to_owned());
+        }
+        let side = board.turn;
+        let i = side.value() as usize;
+        let state = GameState {
+            board,
+            fen,
+            moves,
+            time_control: config.time_control,
+            clocks,
+        };
+        let start = Instant::now();
+        let thought = engines[i].think(&state);
+        let elapsed = start.elapsed();
+        let thought = match thought {
+            Ok(thought) => thought,
+            Err(e) => return (GameResult::loss(side), e),
+        };
+        let allowed = match config.time_control {
+            TimeControl::Depth(_) => None,
+            TimeControl::MoveTime(time) => Some(time),
+            TimeControl::Clock { .. } => Some(clocks[i]),
+        };
+        if allowed.is_some_and(|time| elapsed > time + config.time_margin) {
+            return (GameResult::loss(side), Reason::Timeout.to_string());
+        }
+        if let TimeControl::Clock { increment, .. } = config.time_control {
+            clocks[i] = clocks[i].saturating_sub(elapsed) + increment;
+        }
+        let s = match thought.best_move {
+            Some(s) => s,
+            None => return (GameResult::loss(side), "没有着法".to_owned()),
+        };
+        let m = match board.parse_move(&s) {
+            Some(m) if board.legal_moves().contains(&m) => m,
+            _ => return (GameResult::loss(side), format!("着法不合法 {}", s)),
+        };
+        scores[i].push(thought.score);
+        if let Some((score, count)) = config.resign {
+            if last_scores(&scores[i], count).is_some_and(|v| v.iter().all(|v| *v <= -score)) {
+                return (GameResult::loss(side), Reason::Resignation.to_string());
+            }
+        }
+        if let Some((ply, score, count)) = config.draw {
+            let quiet = |v: &[Option<i32>]| last_scores(v, count).is_some_and(|v| v.iter().all(|v| v.abs() <= score));
+            if board.move_history.len() >= ply && quiet(&scores[0]) && quiet(&scores[1]) {
+                return (GameResult::Draw, "判和".to_owned());
+            }
+        }
+        board.do_move(&m, true);
+        moves.push(s);
+    }
+}
+
+// 最近count步的分数，不够count步或者其中有一步没有分数时返回None
+fn last_scores(scores: &[Option<i32>], count: usize) -> Option<Vec<i32>> {
+    if count == 0 || scores.len() < count {
+        return None;
+    }
+    scores[scores.len() - count..]
+        .iter()
+        .copied()
+        .collect()
+}
+
+// 第一个引擎的胜和负
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct MatchStats {
+    pub wins: usize,
+    pub draws: usize,
+    pub losses: usize,
+}
+
+impl MatchStats {
+    pub fn add(&mut self, score: f64) {
+        if score > 0.5 {
+            self.wins += 1;
+        } else if score < 0.5 {
+            self.losses += 1;
+        } else {
+            self.draws += 1;
+        }
+    }
+    pub fn games(&self) -> usize {
+        self.wins + self.draws + self.losses
+    }
+    // 平均每盘得分
+    pub fn score(&self) -> f64 {
+        (self.wins as f64 + self.draws as f64 / 2.0) / self.games().max(1) as f64
+    }
+    // 每盘得分的方差
+    fn variance(&self) -> f64 {
+        let n = self.games().max(1) as f64;
+        let s = self.score();
+        (self.wins as f64 * (1.0 - s).powi(2) + self.draws as f64 * (0.5 - s).powi(2) + self.losses as f64 * s.powi(2))
+            / n
+    }
+    // 等级分差和95%置信区间的半宽
+    pub fn elo(&self) -> (f64, f64) {
+        let n = self.games() as f64;
+        if n == 0.0 {
+            return (0.0, f64::INFINITY);
+        }
+        let s = self.score();
+        let deviation = 1.96 * (self.variance() / n).sqrt();
+        let (low, high) = (elo_of(s - deviation), elo_of(s + deviation));
+        (elo_of(s), (high - low) / 2.0)
+    }
+    // 假设等级分差为elo1和elo0的对数似然比（正态近似）
+    pub fn llr(&self, elo0: f64, elo1: f64) -> f64 {
+        let variance = self.variance();
+        if self.games() == 0 || variance <= 0.0 {
+            return 0.0;
+        }
+        let (s0, s1) = (score_of(elo0), score_of(elo1));
+        self.games() as f64 * (s1 - s0) * (2.0 * self.score() - s0 - s1) / (2.0 * variance)
+    }
+}
+
+// 得分率对应的等级分差
+fn elo_of(score: f64) -> f64 {
+    if score <= 0.0 {
+        f64::NEG_INFINITY
+    } else if score >= 1.0 {
+        f64::INFINITY
+    } else {
+        400.0 * (score / (1.0 - score)).log10()
+    }
+}
+
+// 等级分差对应的期望得分率
+fn score_of(elo: f64) -> f64 {
+    1.0 / (1.0 + 10f64.powf(-elo / 400.0))
+}
+
+// 序贯概率比检验：H0为等级分差elo0，H1为elo1，alpha和beta为两类错误的概率
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Sprt {
+    pub elo0: f64,
+    pub elo1: f64,
+    pub alpha: f64,
+    pub beta: f64,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SprtResult {
+    Continue,
+    AcceptH0,
+    AcceptH1,
+}
+
+impl Sprt {
+    pub fn new(elo0: f64, elo1: f64) -> Self {
+        Sprt {
+            elo0,
+            elo1,
+            alpha: 0.05,
+            beta: 0.05,
+        }
+    }
+    // 对数似然比的上下界
+    pub fn bounds(&self) -> (f64, f64) {
+        (
+            (self.beta / (1.0 - self.alpha)).ln(),
+            ((1.0 - self.beta) / self.alpha).ln(),
+        )
+    }
+    pub fn judge(&self, stats: &MatchStats) -> SprtResult {
+        let llr = stats.llr(self.elo0, self.elo1);
+        let (lower, upper) = self.bounds();
+        if llr >= upper {
+            SprtResult::AcceptH1
+        } else if llr <= lower {
+            SprtResult::AcceptH0
+        } else {
+            SprtResult::Continue
+        }
+    }
+}
+
+// 进行最多games盘对局，第2k和2k+1盘用同一个开局并交换先后手。
+// 每下完一盘调用on_game，设置了SPRT时每对对局后检验，得出结论就提前结束
+pub fn run_match(
+    engines: &mut [Box<dyn Contestant>; 2],
+    openings: &[Opening],
+    config: &MatchConfig,
+    games: usize,
+    sprt: Option<&Sprt>,
+    mut on_game: impl FnMut(&GameRecord, &MatchStats),
+) -> MatchStats {
+    let default_openings = [Opening::startpos()];
+    let openings = if openings.is_empty() {
+        &default_openings[..]
+    } else {
+        openings
+    };
+    let mut stats = MatchStats::default();
+    for game in 0..games {
+        let opening = &openings[(game / 2) % openings.len()];
+        let [first, second] = engines;
+        let swapped = game % 2 == 1;
+        let record = if swapped {
+            play_game(second.as_mut(), first.as_mut(), opening, config)
+        } else {
+            play_game(first.as_mut(), second.as_mut(), opening, config)
+        };
+        let score = record.result.score();
+        stats.add(if swapped { 1.0 - score } else { score });
+        on_game(&record, &stats);
+        if swapped && sprt.is_some_and(|sprt| sprt.judge(&stats) != SprtResult::Continue) {
+            break;
+        }
+    }
+    stats
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::arena::*;
+
+    #[test]
+    fn test_parse_score() {
+        assert_eq!(parse_score("info depth 5 score 35 pv h2e2"), Some(35));
+        assert_eq!(parse_score("info depth 5 score cp -20 nodes 100"), Some(-20));
+        assert_eq!(parse_score("info depth 5 score mate 2 pv h2e2"), Some(-mated_value(3)));
+        assert_eq!(parse_score("info depth 5 score mate -1"), Some(mated_value(2)));
+        assert_eq!(parse_score("info depth 5 nodes 100"), None);
+    }
+
+    #[test]
+    fn test_opening() {
+        let data = "
+            # 注释
+            startpos moves h2e2 h9g7
+            fen 4k4/9/9/9/9/9/9/9/9/3R1K3 w - - 0 1
+        ";
+        let openings = Opening::parse(data).unwrap();
+        assert_eq!(openings.len(), 2);
+        assert_eq!(openings[0].fen, STARTPOS);
+        assert_eq!(openings[0].moves, vec!["h2e2", "h9g7"]);
+        assert!(openings[1].moves.is_empty());
+        assert!(Opening::parse("startpos moves h2h8").is_err());
+        assert!(Opening::parse("rnbakabnr").is_err());
+    }
+
+    #[test]
+    fn test_stats() {
+        let mut stats = MatchStats::default();
+        for score in [1.0, 0.5, 0.0, 0.5] {
+            stats.add(score);
+        }
+        let (elo, error) = stats.elo();
+        assert_eq!(elo, 0.0);
+        assert!(error > 0.0);
+        // 胜多负少时等级分差为正，对H1更有利
+        stats.wins += 20;
+        assert!(stats.elo().0 > 0.0);
+        assert!(stats.llr(0.0, 10.0) > 0.0);
+        let sprt = Sprt::new(0.0, 10.0);
+        assert_eq!(sprt.judge(&MatchStats::default()), SprtResult::Continue);
+        let losing = MatchStats {
+            wins: 10,
+            draws: 100,
+            losses: 200,
+        };
+        assert_eq!(sprt.judge(&losing), SprtResult::AcceptH0);
+    }
+
+    #[test]
+    fn test_play_game() {
+        let config = MatchConfig {
+            time_control: TimeControl::Depth(1),
+            max_plies: 20,
+            ..Default::default()
+        };
+        let mut engines: [Box<dyn Contestant>; 2] = [Box::new(InProcess::new("a")), Box::new(InProcess::new("b"))];
+        // 红方一步杀
+        let openings = Opening::parse("fen 4k4/R8/9/9/9/9/9/1R7/9/5K3 w - - 0 1").unwrap();
+        let mut records = vec![];
+        let stats = run_match(&mut engines, &openings, &config, 2, None, |record, _| {
+            records.push(record.clone())
+        });
+        assert_eq!(stats.games(), 2);
+        // 交换先后手
+        assert_eq!((records[0].red.as_str(), records[1].red.as_str()), ("a", "b"));
+        assert_eq!(records[0].result, GameResult::RedWin);
+        assert_eq!(records[0].moves, vec!["b2b9"]);
+        assert_eq!(records[0].reason, "将死");
+        assert_eq!(stats.wins, 1);
+        assert_eq!(stats.losses, 1);
+    }
+
+    #[test]
+    fn test_time_control() {
+        let config = MatchConfig {
+            time_control: TimeControl::MoveTime(Duration::from_millis(20)),
+            max_plies: 6,
+            ..Default::default()
+        };
+        let (mut red, mut black) = (InProcess::new("red"), InProcess::new("black"));
+        let record = play_game(&mut red, &mut black, &Opening::startpos(), &config);
+        assert_eq!(record.moves.len(), 6);
+        assert_eq!(record.result, GameResult::Draw);
+        for (i, s) in record.moves.iter().enumerate() {
+            assert!(s.len() == 4, "move {} {}", i, s);
+        }
+    }
+}
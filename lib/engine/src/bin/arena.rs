@@ -0,0 +1,190 @@
+extern crate engine;
+
+use engine::arena::{
+    run_match, Contestant, EngineProcess, InProcess, MatchConfig, Opening, Protocol, Sprt, SprtResult, TimeControl,
+};
+use engine::cli::{fail, parse, Args};
+use engine::engine::load_book;
+use engine::eval::EvalParams;
+use engine::nnue::Network;
+use std::fs;
+use std::sync::Arc;
+use std::time::Duration;
+
+const USAGE: &str = "usage: arena --engine <spec> --engine <spec> [--games <n>] [--openings <file>] [--depth <n> | --movetime <ms> | --tc <seconds>+<increment>] [--margin <ms>] [--max-plies <n>] [--resign <score> <moves>] [--draw <ply> <score> <moves>] [--sprt <elo0> <elo1>] [--alpha <x>] [--beta <x>]
+engine spec: comma separated key=value, e.g.
+  cmd=./pikafish,proto=uci,option.Threads=2,name=pikafish
+  builtin,threads=2,evalfile=eval_params.toml,nnuefile=nnue.bin,book=false,name=dev";
+
+fn main() {
+    let mut specs = vec![];
+    let mut games = 2;
+    let mut openings_file = None;
+    let mut config = MatchConfig::default();
+    let (mut sprt_elo, mut alpha, mut beta) = (None, None, None);
+
+    let mut args = Args::new(USAGE);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--engine" => specs.push(args.value()),
+            "--games" => games = args.parse(),
+            "--openings" => openings_file = Some(args.value()),
+            "--depth" => config.time_control = TimeControl::Depth(args.parse()),
+            "--movetime" => config.time_control = TimeControl::MoveTime(Duration::from_millis(args.parse())),
+            "--tc" => config.time_control = parse_tc(&args.value()),
+            "--margin" => config.time_margin = Duration::from_millis(args.parse()),
+            "--max-plies" => config.max_plies = args.parse(),
+            "--resign" => config.resign = Some((args.parse(), args.parse())),
+            "--draw" => config.draw = Some((args.parse(), args.parse(), args.parse())),
+            "--sprt" => sprt_elo = Some((args.parse(), args.parse())),
+            "--alpha" => alpha = Some(args.parse()),
+            "--beta" => beta = Some(args.parse()),
+            _ => args.usage(),
+        }
+    }
+    if specs.len() != 2 {
+        args.usage();
+    }
+    // 先用SPRT的默认参数，再用命令行给出的值，和选项的先后顺序无关
+    let sprt = (sprt_elo.is_some() || alpha.is_some() || beta.is_some()).then(|| {
+        let (elo0, elo1) = sprt_elo.unwrap_or((0.0, 5.0));
+        let mut sprt = Sprt::new(elo0, elo1);
+        sprt.alpha = alpha.unwrap_or(sprt.alpha);
+        sprt.beta = beta.unwrap_or(sprt.beta);
+        sprt
+    });
+
+    let openings = match openings_file {
+        Some(file) => {
+            let data = fs::read_to_string(&file).unwrap_or_else(|e| fail(&format!("{}: {}", file, e)));
+            Opening::parse(&data).unwrap_or_else(|e| fail(&format!("{}: {}", file, e)))
+        }
+        None => vec![Opening::startpos()],
+    };
+    let mut engines: [Box<dyn Contestant>; 2] = [create_engine(&specs[0]), create_engine(&specs[1])];
+    println!(
+        "{} vs {}, {} openings, {:?}",
+        engines[0].name(),
+        engines[1].name(),
+        openings.len(),
+        config.time_control
+    );
+
+    let stats = run_match(
+        &mut engines,
+        &openings,
+        &config,
+        games,
+        sprt.as_ref(),
+        |record, stats| {
+            let (elo, error) = stats.elo();
+            println!(
+                "game {}: {} vs {} {} ({}), +{} ={} -{}, elo {:.1} +/- {:.1}",
+                stats.games(),
+                record.red,
+                record.black,
+                record.result,
+                record.reason,
+                stats.wins,
+                stats.draws,
+                stats.losses,
+                elo,
+                error
+            );
+        },
+    );
+
+    let (elo, error) = stats.elo();
+    println!(
+        "{} vs {}: +{} ={} -{}, score {:.3}, elo {:.1} +/- {:.1}",
+        engines[0].name(),
+        engines[1].name(),
+        stats.wins,
+        stats.draws,
+        stats.losses,
+        stats.score(),
+        elo,
+        error
+    );
+    if let Some(sprt) = sprt {
+        let (lower, upper) = sprt.bounds();
+        let result = match sprt.judge(&stats) {
+            SprtResult::AcceptH0 => "H0 accepted",
+            SprtResult::AcceptH1 => "H1 accepted",
+            SprtResult::Continue => "inconclusive",
+        };
+        println!(
+            "SPRT elo0 {} elo1 {}: llr {:.2} ({:.2}, {:.2}) {}",
+            sprt.elo0,
+            sprt.elo1,
+            stats.llr(sprt.elo0, sprt.elo1),
+            lower,
+            upper,
+            result
+        );
+    }
+}
+
+// cmd=为外部引擎，builtin为本库的搜索
+fn create_engine(spec: &str) -> Box<dyn Contestant> {
+    let mut command = None;
+    let mut args = vec![];
+    let mut protocol = Protocol::Ucci;
+    let mut options = vec![];
+    let mut name = None;
+    let mut builtin = InProcess::new("builtin");
+    builtin.book = load_book(include_str!("../../BOOK.DAT"));
+    for item in spec.split(',') {
+        let (key, value) = item.split_once('=').unwrap_or((item, ""));
+        match key {
+            "builtin" => {}
+            "cmd" => command = Some(value.to_owned()),
+            "arg" => args.push(value.to_owned()),
+            "proto" => {
+                protocol = match value {
+                    "ucci" => Protocol::Ucci,
+                    "uci" => Protocol::Uci,
+                    _ => fail(&format!("unknown protocol {}", value)),
+                }
+            }
+            "name" => name = Some(value.to_owned()),
+            "threads" => builtin.threads = parse(value),
+            "evalfile" => builtin.eval_params = EvalParams::load(value).unwrap_or_else(|e| fail(&e)),
+            "nnuefile" if !cfg!(feature = "nnue") => fail("nnue is not compiled in"),
+            "nnuefile" => builtin.network = Some(Arc::new(Network::load(value).unwrap_or_else(|e| fail(&e)))),
+            "book" => {
+                if value == "false" {
+                    builtin.book = vec![];
+                }
+            }
+            _ => match key.strip_prefix("option.") {
+                Some(option) => options.push((option.to_owned(), value.to_owned())),
+                None => fail(&format!("unknown engine setting {}", key)),
+            },
+        }
+    }
+    match command {
+        Some(command) => {
+            let mut engine = EngineProcess::start(&command, &args, protocol, &options).unwrap_or_else(|e| fail(&e));
+            if let Some(name) = name {
+                engine.name = name;
+            }
+            Box::new(engine)
+        }
+        None => {
+            if let Some(name) = name {
+                builtin.name = name;
+            }
+            Box::new(builtin)
+        }
+    }
+}
+
+// 包干时间和加时，以秒为单位，如60+0.5
+fn parse_tc(s: &str) -> TimeControl {
+    let (base, increment) = s.split_once('+').unwrap_or((s, "0"));
+    TimeControl::Clock {
+        base: Duration::from_secs_f64(parse(base)),
+        increment: Duration::from_secs_f64(parse(increment)),
+    }
+}
@@ -0,0 +1,78 @@
+extern crate engine;
+
+use engine::cli::{fail, Args};
+use engine::engine::score_string;
+use engine::epd::{run_suite, EpdPosition};
+use std::fs;
+use std::time::{Duration, Instant};
+
+const USAGE: &str = "usage: epd [<file>] [--depth <n>] [--time <ms>] [--threads <n>]";
+
+fn main() {
+    let mut file = None;
+    let mut depth = None;
+    let mut time = None;
+    let mut threads = 1;
+
+    let mut args = Args::new(USAGE);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--depth" => depth = Some(args.parse()),
+            "--time" => time = Some(Duration::from_millis(args.parse())),
+            "--threads" => threads = args.parse(),
+            _ if file.is_none() && !arg.starts_with("--") => file = Some(arg),
+            _ => args.usage(),
+        }
+    }
+    // 限时的时候深度不限
+    let depth = depth.unwrap_or(if time.is_some() { 64 } else { 8 });
+
+    // 不指定文件时用自带的测试题
+    let data = match &file {
+        Some(file) => fs::read_to_string(file).unwrap_or_else(|e| fail(&format!("{}: {}", file, e))),
+        None => include_str!("../../TACTICS.EPD").to_owned(),
+    };
+    let positions = EpdPosition::parse(&data).unwrap_or_else(|e| fail(&e));
+
+    let start = Instant::now();
+    let mut total_time = Duration::ZERO;
+    let solved = run_suite(&positions, depth, time, threads, |position, result| {
+        let status = match result.solved {
+            Some((depth, time, nodes)) => {
+                total_time += time;
+                format!("solved at depth {} in {} ms, {} nodes", depth, time.as_millis(), nodes)
+            }
+            None => "not solved".to_owned(),
+        };
+        let expected = [
+            ("bm", position.best_moves.join(" ")),
+            ("am", position.avoid_moves.join(" ")),
+            (
+                "dm",
+                position
+                    .mate
+                    .map_or(String::new(), |n| n.to_string()),
+            ),
+        ]
+        .iter()
+        .filter(|(_, v)| !v.is_empty())
+        .map(|(op, v)| format!("{} {}", op, v))
+        .collect::<Vec<String>>()
+        .join(", ");
+        println!(
+            "{}: {} score {} ({}) {}",
+            result.id,
+            result.best_move.as_deref().unwrap_or("none"),
+            score_string(result.value),
+            expected,
+            status
+        );
+    });
+    println!(
+        "solved {}/{}, total {} ms, average time to solution {} ms",
+        solved,
+        positions.len(),
+        start.elapsed().as_millis(),
+        total_time.as_millis() / solved.max(1) as u128
+    );
+}
@@ -0,0 +1,61 @@
+extern crate engine;
+
+use engine::cli::{fail, Args};
+use engine::engine::load_book;
+use engine::selfplay::{generate, record_line, SelfPlayConfig};
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::thread;
+
+const USAGE: &str = "usage: selfplay [--games <n>] [--threads <n>] [--depth <n>] [--nodes <n>] [--random-plies <n>] [--max-plies <n>] [--seed <n>] [--jieqi] [--no-book] [--out <file>]";
+
+fn main() {
+    let mut config = SelfPlayConfig::default();
+    let mut games = 100;
+    let mut threads = thread::available_parallelism().map_or(1, |n| n.get());
+    let mut seed = 0;
+    let mut out_file = String::from("selfplay.bin");
+
+    let mut args = Args::new(USAGE);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--games" => games = args.parse(),
+            "--threads" => threads = args.parse(),
+            "--depth" => config.depth = args.parse(),
+            "--nodes" => config.nodes = Some(args.parse()),
+            "--random-plies" => config.random_plies = args.parse(),
+            "--max-plies" => config.max_plies = args.parse(),
+            "--seed" => seed = args.parse(),
+            "--jieqi" => config.jieqi = true,
+            "--no-book" => config.use_book = false,
+            "--out" => out_file = args.value(),
+            _ => args.usage(),
+        }
+    }
+
+    let book = load_book(include_str!("../../BOOK.DAT"));
+    // .bin为二进制记录，其他为文本
+    let binary = out_file.ends_with(".bin");
+    let file = File::create(&out_file).unwrap_or_else(|e| fail(&format!("{}: {}", out_file, e)));
+    let mut writer = BufWriter::new(file);
+    let (mut finished, mut positions) = (0, 0);
+    generate(&config, &book, games, threads, seed, |records| {
+        for record in records.iter() {
+            let written = if binary {
+                writer.write_all(&record.to_bytes())
+            } else {
+                writeln!(writer, "{}", record_line(record))
+            };
+            if let Err(e) = written {
+                fail(&format!("{}: {}", out_file, e));
+            }
+        }
+        finished += 1;
+        positions += records.len();
+        println!("game {}/{} positions {}", finished, games, positions);
+    });
+    if let Err(e) = writer.flush() {
+        fail(&format!("{}: {}", out_file, e));
+    }
+    println!("saved to {}", out_file);
+}
@@ -0,0 +1,51 @@
+extern crate engine;
+
+use engine::cli::{fail, Args};
+use engine::tablebase::{Material, Tablebase};
+use std::path::PathBuf;
+use std::thread;
+use std::time::Instant;
+
+const USAGE: &str = "usage: tablebase <material>... [--dir <dir>] [--threads <n>]
+material: pieces of each side without the other king, e.g. KRvKNAA KCPvKA";
+
+fn main() {
+    let mut materials = vec![];
+    let mut dir = PathBuf::from("tablebase");
+    let mut threads = thread::available_parallelism().map_or(1, |n| n.get());
+
+    let mut args = Args::new(USAGE);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--dir" => dir = PathBuf::from(args.value()),
+            "--threads" => threads = args.parse(),
+            _ if !arg.starts_with("--") => materials.push(Material::parse(&arg).unwrap_or_else(|e| fail(&e))),
+            _ => args.usage(),
+        }
+    }
+    if materials.is_empty() {
+        args.usage();
+    }
+
+    // 吃子后的表先生成，已经在目录中的表直接读入
+    let mut tablebase = Tablebase::new(Some(dir));
+    let mut start = Instant::now();
+    for material in materials {
+        tablebase
+            .generate(&material, threads, &mut |table| {
+                let (wins, draws, losses) = table.stats();
+                println!(
+                    "{}: {} positions, win {} draw {} loss {}, longest mate {} plies, {} ms",
+                    table.material.name(),
+                    table.len(),
+                    wins,
+                    draws,
+                    losses,
+                    table.longest(),
+                    start.elapsed().as_millis()
+                );
+                start = Instant::now();
+            })
+            .unwrap_or_else(|e| fail(&e));
+    }
+}
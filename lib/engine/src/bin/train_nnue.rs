@@ -0,0 +1,58 @@
+extern crate engine;
+
+use engine::board::Board;
+use engine::cli::{fail, Args};
+use engine::nnue::{Network, Trainer};
+use engine::selfplay::load_positions;
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use rand::SeedableRng;
+
+const USAGE: &str = "usage: train_nnue <positions> [--init <file>] [--out <file>] [--hidden <n>] [--epochs <n>] [--lr <x>] [--seed <n>]";
+
+fn main() {
+    let mut positions_file = None;
+    let mut init_file = None;
+    let mut out_file = String::from("nnue.bin");
+    let mut hidden = 32;
+    let mut epochs = 10;
+    let mut learning_rate = 0.01;
+    let mut seed = 0;
+
+    let mut args = Args::new(USAGE);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--init" => init_file = Some(args.value()),
+            "--out" => out_file = args.value(),
+            "--hidden" => hidden = args.parse(),
+            "--epochs" => epochs = args.parse(),
+            "--lr" => learning_rate = args.parse(),
+            "--seed" => seed = args.parse(),
+            _ if positions_file.is_none() && !arg.starts_with("--") => positions_file = Some(arg),
+            _ => args.usage(),
+        }
+    }
+    let positions_file = positions_file.unwrap_or_else(|| args.usage());
+
+    let mut positions = load_positions(&positions_file).unwrap_or_else(|e| fail(&e));
+    println!("{} positions", positions.len());
+
+    let mut trainer = match init_file {
+        Some(file) => Trainer::from_network(&Network::load(&file).unwrap_or_else(|e| fail(&e))),
+        None => Trainer::new(hidden, seed),
+    };
+    trainer.learning_rate = learning_rate;
+    let mut rng = StdRng::seed_from_u64(seed);
+    for epoch in 1..=epochs {
+        positions.shuffle(&mut rng);
+        let loss: f64 = positions
+            .iter()
+            .map(|(fen, result)| trainer.train(&Board::from_fen(fen), *result))
+            .sum();
+        println!("epoch {} loss = {:.6}", epoch, loss / positions.len().max(1) as f64);
+        if let Err(e) = trainer.quantize().save(&out_file) {
+            fail(&e);
+        }
+    }
+    println!("saved to {}", out_file);
+}
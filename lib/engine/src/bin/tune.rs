@@ -0,0 +1,60 @@
+extern crate engine;
+
+use engine::board::Board;
+use engine::cli::{fail, Args};
+use engine::eval::EvalParams;
+use engine::selfplay::load_positions;
+use engine::tune::{QuietPosition, Tuner};
+use std::thread;
+
+const USAGE: &str = "usage: tune <positions> [--params <file>] [--out <file>] [--iterations <n>] [--step <n>] [--threads <n>] [--no-tables]";
+
+fn main() {
+    let mut positions_file = None;
+    let mut params_file = None;
+    let mut out_file = String::from("eval_params.toml");
+    let mut iterations = 100;
+    let mut step = 1;
+    let mut threads = thread::available_parallelism().map_or(1, |n| n.get());
+    let mut tables = true;
+
+    let mut args = Args::new(USAGE);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--params" => params_file = Some(args.value()),
+            "--out" => out_file = args.value(),
+            "--iterations" => iterations = args.parse(),
+            "--step" => step = args.parse(),
+            "--threads" => threads = args.parse(),
+            "--no-tables" => tables = false,
+            _ if positions_file.is_none() && !arg.starts_with("--") => positions_file = Some(arg),
+            _ => args.usage(),
+        }
+    }
+    let positions_file = positions_file.unwrap_or_else(|| args.usage());
+
+    let params = match params_file {
+        Some(file) => EvalParams::load(&file).unwrap_or_else(|e| fail(&e)),
+        None => EvalParams::default(),
+    };
+    let positions: Vec<QuietPosition> = load_positions(&positions_file)
+        .unwrap_or_else(|e| fail(&e))
+        .into_iter()
+        .filter_map(|(fen, result)| QuietPosition::resolve(&Board::from_fen(&fen), result))
+        .collect();
+    println!("{} quiet positions", positions.len());
+
+    let mut tuner = Tuner::new(positions, threads);
+    tuner.tables = tables;
+    let k = tuner.find_k(&params);
+    println!("k = {:.3}, error = {:.6}", k, tuner.error(&params));
+
+    // 每轮都写出参数文件，中途停止也不会丢失结果
+    tuner.tune(&params, iterations, step, |iteration, error, params| {
+        println!("iteration {} error = {:.6}", iteration, error);
+        if let Err(e) = params.save(&out_file) {
+            fail(&e);
+        }
+    });
+    println!("saved to {}", out_file);
+}
@@ -2,8 +2,16 @@ use rand::rngs::StdRng;
 use rand::{Rng, SeedableRng};
 use std::vec;
 
-use crate::constant::{FEN_MAP, KILL, MAX, MAX_DEPTH, MIN, RECORD_SIZE, ZOBRIST_TABLE, ZOBRIST_TABLE_LOCK};
+use crate::constant::{
+    ASPIRATION_WINDOW, FEN_MAP, KILL, MATE_BOUND, MAX, MAX_DEPTH, MIN, RECORD_SIZE, ZOBRIST_TABLE, ZOBRIST_TABLE_LOCK,
+};
+use crate::eval::{EvalParams, Score};
+use crate::movesort::{Heuristics, MoveSorter};
+use crate::nnue::Nnue;
+use crate::tablebase::Tablebase;
 use std::collections::HashSet;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
 
 pub const BOARD_WIDTH: i32 = 9;
 pub const BOARD_HEIGHT: i32 = 10;
@@ -86,6 +94,18 @@ impl ChessType {
             ChessType::Pawn => 2,
         }
     }
+    // 静态交换评估用的子力价值
+    pub fn see_value(&self) -> i32 {
+        match self {
+            ChessType::King => 1000,
+            ChessType::Advisor => 20,
+            ChessType::Bishop => 20,
+            ChessType::Knight => 90,
+            ChessType::Rook => 200,
+            ChessType::Cannon => 95,
+            ChessType::Pawn => 10,
+        }
+    }
 
     // pub fn move_value(&self) -> i32 {
     //     match self {
@@ -247,25 +267,109 @@ pub struct Record {
     pub best_move: Option<Move>,
     pub zobrist_lock: u64,
     pub turn: Player,
+    pub flag: RecordFlag,
+}
+
+// 迭代加深每一层的搜索结果
+#[derive(Clone, Debug)]
+pub struct DepthResult {
+    pub depth: i32,
+    pub value: i32,
+    pub best_move: Move,
+    pub pv: Vec<Move>, // 主要变例
+    pub nodes: i32,    // 到这一层为止搜索的叶子节点数
 }
 
+// 置换表，多线程搜索时各线程共享
+pub struct RecordTable {
+    records: Vec<Mutex<Option<Record>>>,
+}
+
+impl RecordTable {
+    // size需要是2的幂
+    pub fn new(size: usize) -> Self {
+        RecordTable {
+            records: (0..size).map(|_| Mutex::new(None)).collect(),
+        }
+    }
+    pub fn is_empty(&self) -> bool {
+        self.records.is_empty()
+    }
+    pub fn find(&self, zobrist_value: u64, zobrist_lock: u64, turn: Player) -> Option<Record> {
+        if self.records.is_empty() {
+            return None;
+        }
+        let index = (zobrist_value & (self.records.len() - 1) as u64) as usize;
+        match &*self.records[index].lock().unwrap() {
+            Some(record) if record.zobrist_lock == zobrist_lock && record.turn == turn => Some(record.clone()),
+            _ => None,
+        }
+    }
+    pub fn add(&self, zobrist_value: u64, record: Record) {
+        if self.records.is_empty() {
+            return;
+        }
+        let index = (zobrist_value & (self.records.len() - 1) as u64) as usize;
+        let mut slot = self.records[index].lock().unwrap();
+        if let Some(old_record) = &*slot {
+            // 同一局面用搜索深度较大的覆盖，不同局面直接替换
+            if old_record.zobrist_lock == record.zobrist_lock && record.depth < old_record.depth {
+                return;
+            }
+        }
+        *slot = Some(record);
+    }
+    pub fn clear(&self) {
+        for record in self.records.iter() {
+            *record.lock().unwrap() = None;
+        }
+    }
+}
+
+// 置换表记录的分数类型
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum RecordFlag {
+    Exact, // 准确值
+    Lower, // 产生截断，实际分数不低于记录值
+    Upper, // 没有超过alpha，实际分数不高于记录值
+}
+
+#[derive(Clone)]
 pub struct Board {
     // 9×10的棋盘，红方在下，黑方在上
     pub chesses: [[Chess; BOARD_WIDTH as usize]; BOARD_HEIGHT as usize],
     // 是否揭开过
     pub chesses_status: [[Chess; BOARD_WIDTH as usize]; BOARD_HEIGHT as usize],
+    // 每步棋走之前起点和终点的状态，悔棋时恢复
+    pub status_history: Vec<(Chess, Chess)>,
     pub turn: Player,
     pub counter: i32,
     pub gen_counter: i32,
     pub move_history: Vec<Move>,
+    // 悔棋退回的着法，重走时从后往前走，走了新的着法后清空
+    pub redo_moves: Vec<Move>,
     pub best_moves_last: Vec<Move>,
-    pub records: Vec<Option<Record>>,
+    // 多PV搜索时根节点不再搜索的着法
+    pub excluded_moves: Vec<Move>,
+    pub records: Arc<RecordTable>,
+    pub heuristics: Heuristics,
+    pub eval_params: EvalParams,
+    // 神经网络评价，没有加载网络时为None
+    pub nnue: Option<Nnue>,
+    // 残局库，子数少时在搜索中直接查出胜负
+    pub tablebase: Option<Arc<Tablebase>>,
+    // 多线程搜索时由主线程通知停止
+    pub stop: Arc<AtomicBool>,
     pub zobrist_value: u64,
     pub zobrist_value_lock: u64,
     pub distance: i32,
-    pub select_pos: Position,
+    // 界面上选中的棋子和它可以走到的位置，局面变化后清除
+    pub select_pos: Option<Position>,
+    pub select_targets: Vec<Position>,
     pub jieqi: bool,
     pub robot: bool,
+    // 人机对战时电脑执的一方
+    pub robot_side: Player,
 }
 
 // 棋子是否在棋盘内
@@ -288,100 +392,50 @@ pub fn in_palace(pos: Position, player: Player) -> bool {
     }
 }
 
-const KING_VALUE_TABLE: [[i32; BOARD_WIDTH as usize]; BOARD_HEIGHT as usize] = [
-    [0, 0, 0, 0, 0, 0, 0, 0, 0],
-    [0, 0, 0, 0, 0, 0, 0, 0, 0],
-    [0, 0, 0, 0, 0, 0, 0, 0, 0],
-    [0, 0, 0, 0, 0, 0, 0, 0, 0],
-    [0, 0, 0, 0, 0, 0, 0, 0, 0],
-    [0, 0, 0, 0, 0, 0, 0, 0, 0],
-    [0, 0, 0, 0, 0, 0, 0, 0, 0],
-    [0, 0, 0, 1, 1, 1, 0, 0, 0],
-    [0, 0, 0, 2, 2, 2, 0, 0, 0],
-    [0, 0, 0, 11, 15, 11, 0, 0, 0],
-];
-
-const ADVISOR_VALUE_TABLE: [[i32; BOARD_WIDTH as usize]; BOARD_HEIGHT as usize] = [
-    [0, 0, 0, 0, 0, 0, 0, 0, 0],
-    [0, 0, 0, 0, 0, 0, 0, 0, 0],
-    [0, 0, 0, 0, 0, 0, 0, 0, 0],
-    [0, 0, 0, 0, 0, 0, 0, 0, 0],
-    [0, 0, 0, 0, 0, 0, 0, 0, 0],
-    [0, 0, 0, 0, 0, 0, 0, 0, 0],
-    [0, 0, 0, 0, 0, 0, 0, 0, 0],
-    [0, 0, 0, 20, 0, 20, 0, 0, 0],
-    [0, 0, 0, 0, 23, 0, 0, 0, 0],
-    [0, 0, 0, 20, 0, 20, 0, 0, 0],
-];
-
-const BISHOP_VALUE_TABLE: [[i32; BOARD_WIDTH as usize]; BOARD_HEIGHT as usize] = [
-    [0, 0, 0, 0, 0, 0, 0, 0, 0],
-    [0, 0, 0, 0, 0, 0, 0, 0, 0],
-    [0, 0, 0, 0, 0, 0, 0, 0, 0],
-    [0, 0, 0, 0, 0, 0, 0, 0, 0],
-    [0, 0, 0, 0, 0, 0, 0, 0, 0],
-    [0, 0, 20, 0, 0, 0, 20, 0, 0],
-    [0, 0, 0, 0, 0, 0, 0, 0, 0],
-    [18, 0, 0, 0, 23, 0, 0, 0, 18],
-    [0, 0, 0, 0, 0, 0, 0, 0, 0],
-    [0, 0, 20, 0, 0, 0, 20, 0, 0],
-];
-
-const ROOK_VALUE_TABLE: [[i32; BOARD_WIDTH as usize]; BOARD_HEIGHT as usize] = [
-    [206, 208, 207, 213, 214, 213, 207, 208, 206],
-    [206, 212, 209, 216, 233, 216, 209, 212, 206],
-    [206, 208, 207, 214, 216, 214, 207, 208, 206],
-    [206, 213, 213, 216, 216, 216, 213, 213, 206],
-    [208, 211, 211, 214, 215, 214, 211, 211, 208],
-    [208, 212, 212, 214, 215, 214, 212, 212, 208],
-    [204, 209, 204, 212, 214, 212, 204, 209, 204],
-    [198, 208, 204, 212, 212, 212, 204, 208, 198],
-    [200, 208, 206, 212, 200, 212, 206, 208, 200],
-    [194, 206, 204, 212, 200, 212, 204, 206, 194],
-];
-
-const KNIGHT_VALUE_TABLE: [[i32; BOARD_WIDTH as usize]; BOARD_HEIGHT as usize] = [
-    [90, 90, 90, 96, 90, 96, 90, 90, 90],
-    [90, 96, 103, 97, 94, 97, 103, 96, 90],
-    [92, 98, 99, 103, 99, 103, 99, 98, 92],
-    [93, 108, 100, 107, 100, 107, 100, 108, 93],
-    [90, 100, 99, 103, 104, 103, 99, 100, 90],
-    [90, 98, 101, 102, 103, 102, 101, 98, 90],
-    [92, 94, 98, 95, 98, 95, 98, 94, 92],
-    [93, 92, 94, 95, 92, 95, 94, 92, 93],
-    [85, 90, 92, 93, 78, 93, 92, 90, 85],
-    [88, 85, 90, 88, 90, 88, 90, 85, 88],
-];
-
-const CANNON_VALUE_TABLE: [[i32; BOARD_WIDTH as usize]; BOARD_HEIGHT as usize] = [
-    [100, 100, 96, 91, 90, 91, 96, 100, 100],
-    [98, 98, 96, 92, 89, 92, 96, 98, 98],
-    [97, 97, 96, 91, 92, 91, 96, 97, 97],
-    [96, 99, 99, 98, 100, 98, 99, 99, 96],
-    [96, 96, 96, 96, 100, 96, 96, 96, 96],
-    [95, 96, 99, 96, 100, 96, 99, 96, 95],
-    [96, 96, 96, 96, 96, 96, 96, 96, 96],
-    [97, 96, 100, 99, 101, 99, 100, 96, 97],
-    [96, 97, 98, 98, 98, 98, 98, 97, 96],
-    [96, 96, 97, 99, 99, 99, 97, 96, 96],
-];
-
-const PAWN_VALUE_TABLE: [[i32; BOARD_WIDTH as usize]; BOARD_HEIGHT as usize] = [
-    [9, 9, 9, 11, 13, 11, 9, 9, 9],
-    [19, 24, 34, 42, 44, 42, 34, 24, 19],
-    [19, 24, 32, 37, 37, 37, 32, 24, 19],
-    [19, 23, 27, 29, 30, 29, 27, 23, 19],
-    [14, 18, 20, 27, 29, 27, 20, 18, 14],
-    [7, 0, 13, 0, 16, 0, 13, 0, 7],
-    [7, 0, 7, 0, 15, 0, 7, 0, 7],
-    [0, 0, 0, 0, 0, 0, 0, 0, 0],
-    [0, 0, 0, 0, 0, 0, 0, 0, 0],
-    [0, 0, 0, 0, 0, 0, 0, 0, 0],
-];
-
-const INITIATIVE_BONUS: i32 = 3;
-
-const RECORD_NONE: Option<Record> = None;
+// 在距离根节点ply步时被将死的分数
+pub fn mated_value(ply: i32) -> i32 {
+    KILL + ply
+}
+
+// 是否为杀棋分数
+pub fn is_mate_value(value: i32) -> bool {
+    value.abs() >= MATE_BOUND
+}
+
+// 杀棋分数存入置换表时改为相对当前局面的步数，取出时再换回相对根节点的步数，
+// 这样同一局面在不同深度命中时杀棋步数仍然正确
+pub fn value_to_record(value: i32, ply: i32) -> i32 {
+    if value >= MATE_BOUND {
+        value + ply
+    } else if value <= -MATE_BOUND {
+        value - ply
+    } else {
+        value
+    }
+}
+
+pub fn value_from_record(value: i32, ply: i32) -> i32 {
+    if value >= MATE_BOUND {
+        value - ply
+    } else if value <= -MATE_BOUND {
+        value + ply
+    } else {
+        value
+    }
+}
+
+// 杀棋分数换算成回合数，正数表示N回合后将死对方，负数表示N回合后被将死
+pub fn mate_moves(value: i32) -> Option<i32> {
+    if !is_mate_value(value) {
+        return None;
+    }
+    if value > 0 {
+        Some((-KILL - value + 1) / 2)
+    } else {
+        Some(-(value - KILL + 1) / 2)
+    }
+}
+
 impl Board {
     pub fn init(jieqi: bool, robot: bool) -> Self {
         let black_chess: Vec<ChessType> = if jieqi {
@@ -735,14 +789,24 @@ impl Board {
             counter: 0,
             gen_counter: 0,
             move_history: vec![],
+            redo_moves: vec![],
+            status_history: vec![],
             best_moves_last: vec![],
-            records: vec![],
+            excluded_moves: vec![],
+            records: Arc::new(RecordTable::new(0)),
+            heuristics: Heuristics::new(),
+            eval_params: EvalParams::default(),
+            nnue: None,
+            tablebase: None,
+            stop: Arc::new(AtomicBool::new(false)),
             zobrist_value: 0,
             zobrist_value_lock: 0,
             distance: 0,
-            select_pos: Position { row: 1, col: 1 },
+            select_pos: None,
+            select_targets: vec![],
             jieqi: jieqi,
             robot: robot,
+            robot_side: Player::Black,
         };
         board.zobrist_value = ZOBRIST_TABLE.calc_chesses(&board.chesses);
         board.zobrist_value_lock = ZOBRIST_TABLE_LOCK.calc_chesses(&board.chesses);
@@ -791,14 +855,24 @@ impl Board {
             counter: 0,
             gen_counter: 0,
             move_history: vec![],
+            redo_moves: vec![],
+            status_history: vec![],
             best_moves_last: vec![],
-            records: vec![],
+            excluded_moves: vec![],
+            records: Arc::new(RecordTable::new(0)),
+            heuristics: Heuristics::new(),
+            eval_params: EvalParams::default(),
+            nnue: None,
+            tablebase: None,
+            stop: Arc::new(AtomicBool::new(false)),
             zobrist_value: 0,
             zobrist_value_lock: 0,
             distance: 0,
-            select_pos: Position { row: 1, col: 1 },
+            select_pos: None,
+            select_targets: vec![],
             jieqi: false,
             robot: false,
+            robot_side: Player::Black,
         }
     }
     pub fn from_fen(fen: &str) -> Self {
@@ -828,11 +902,49 @@ impl Board {
         }
         board
     }
+    // 生成FEN串，揭棋中未翻开的棋子按实际棋子输出
+    pub fn to_fen(&self) -> String {
+        let mut rows = vec![];
+        for i in 0..BOARD_HEIGHT {
+            let mut row = String::new();
+            let mut empty = 0;
+            for j in 0..BOARD_WIDTH {
+                let chess = self.chess_at(Position::new(i, j));
+                match FEN_MAP.iter().find(|(_, c)| **c == chess) {
+                    Some((ch, _)) => {
+                        if empty > 0 {
+                            row.push_str(&empty.to_string());
+                            empty = 0;
+                        }
+                        row.push(*ch);
+                    }
+                    None => empty += 1,
+                }
+            }
+            if empty > 0 {
+                row.push_str(&empty.to_string());
+            }
+            rows.push(row);
+        }
+        let turn = if self.turn == Player::Red { "w" } else { "b" };
+        format!("{} {} - - 0 1", rows.join("/"), turn)
+    }
     pub fn apply_move(&mut self, m: &Move, update_status: bool) {
         let chess = self.chess_at(m.from);
         // println!("enter apply_move {} {}", m.to.row, m.to.col);
+        #[cfg(feature = "nnue")]
+        if let Some(nnue) = &mut self.nnue {
+            nnue.apply_move(m.from, m.to, chess, self.chesses[m.to.row as usize][m.to.col as usize]);
+        }
+        let status = (self.chess_status_at(m.from), self.chess_status_at(m.to));
+        self.status_history.push(status);
         self.set_chess(m.to, chess, update_status);
         self.set_chess(m.from, Chess::None, update_status);
+        if !update_status {
+            // 不翻开时棋子带着原来的状态走，被吃掉的棋子的状态不再保留
+            self.chesses_status[m.to.row as usize][m.to.col as usize] = status.0;
+            self.chesses_status[m.from.row as usize][m.from.col as usize] = Chess::None;
+        }
         self.zobrist_value = ZOBRIST_TABLE.apply_move(self.zobrist_value, m);
         self.zobrist_value_lock = ZOBRIST_TABLE_LOCK.apply_move(self.zobrist_value_lock, m);
         self.turn = m.player.next();
@@ -845,8 +957,16 @@ impl Board {
     pub fn undo_move(&mut self, m: &Move) {
         // println!("enter undo_move {} {}", m.to.row, m.to.col);
         let chess = self.chess_at(m.to);
+        #[cfg(feature = "nnue")]
+        if let Some(nnue) = &mut self.nnue {
+            nnue.undo_move(m.from, m.to, chess, m.capture);
+        }
         self.set_chess(m.from, chess, false);
         self.set_chess(m.to, m.capture, false);
+        if let Some((from_status, to_status)) = self.status_history.pop() {
+            self.chesses_status[m.from.row as usize][m.from.col as usize] = from_status;
+            self.chesses_status[m.to.row as usize][m.to.col as usize] = to_status;
+        }
         self.zobrist_value = ZOBRIST_TABLE.undo_move(self.zobrist_value, m);
         self.zobrist_value_lock = ZOBRIST_TABLE_LOCK.undo_move(self.zobrist_value_lock, m);
         self.turn = m.player;
@@ -854,33 +974,133 @@ impl Board {
         self.move_history.pop();
     }
     pub fn click(&mut self, pos: (i32, i32)) {
-        let selected = self.select(pos);
-        if !selected && self.chess_at(self.select_pos).player() == Some(self.turn) {
-            self.move_to(self.select_pos, pos.into());
+        // 人机对战时轮到电脑走，人不能替电脑走棋
+        if self.robot && self.turn == self.robot_side {
+            return;
+        }
+        if self.select(pos) {
+            return;
+        }
+        if let Some(from) = self.select_pos {
+            if self.select_targets.contains(&pos.into()) {
+                self.move_unchecked(from, pos.into());
+            }
         }
     }
+    pub fn clear_selection(&mut self) {
+        self.select_pos = None;
+        self.select_targets.clear();
+    }
     pub fn robot_move(&mut self) -> bool {
         if !(self.robot) {
             return false;
         }
-        if self.turn == Player::Red {
+        if self.turn != self.robot_side {
             return false;
         }
 
+        // 对局已经结束时没有着法，由界面判断胜负
         let (_value, best_move) = self.iterative_deepening(3);
-        if let Some(m) = best_move {
-            if m.is_valid() {
+        match best_move {
+            Some(m) if m.is_valid() => {
                 self.do_move(&m, self.jieqi);
-                return true;
+                self.redo_moves.clear();
+                self.clear_selection();
+                true
+            }
+            _ => false,
+        }
+    }
+    // 悔棋，人机对战时连同电脑的着法一起退回，直到轮到人走
+    pub fn undo(&mut self) -> bool {
+        let mut undone = false;
+        while let Some(m) = self.move_history.last().cloned() {
+            self.undo_move(&m);
+            self.redo_moves.push(m);
+            undone = true;
+            if !self.robot || self.turn != self.robot_side {
+                break;
             }
         }
-        unreachable!();
+        self.clear_selection();
+        undone
+    }
+    // 重走悔掉的着法，揭棋的棋子重新翻开
+    pub fn redo(&mut self) -> bool {
+        let mut redone = false;
+        while let Some(m) = self.redo_moves.pop() {
+            self.do_move(&m, self.jieqi);
+            redone = true;
+            if !self.robot || self.turn != self.robot_side {
+                break;
+            }
+        }
+        self.clear_selection();
+        redone
+    }
+    // 整盘棋的着法：已经走的加上悔掉还能重走的
+    pub fn game_moves(&self) -> Vec<Move> {
+        let mut moves = self.move_history.clone();
+        moves.extend(self.redo_moves.iter().rev().cloned());
+        moves
+    }
+    // 复盘时走到第ply步之后的局面，不论人机都一步一步退回或重走
+    pub fn goto_ply(&mut self, ply: usize) {
+        while self.move_history.len() > ply {
+            let m = self.move_history.last().cloned().unwrap();
+            self.undo_move(&m);
+            self.redo_moves.push(m);
+        }
+        while self.move_history.len() < ply {
+            match self.redo_moves.pop() {
+                Some(m) => self.do_move(&m, self.jieqi),
+                None => break,
+            }
+        }
+        self.clear_selection();
+    }
+    // 双方被吃掉的棋子，按红黑的顺序
+    pub fn captured_chesses(&self) -> [Vec<Chess>; 2] {
+        let mut captured = [vec![], vec![]];
+        for m in self.move_history.iter() {
+            if let Some(player) = m.capture.player() {
+                captured[player.value() as usize].push(m.capture);
+            }
+        }
+        captured
+    }
+    // from处的棋子可以走到的位置，走完不能被将军
+    pub fn legal_targets(&mut self, from: Position) -> Vec<Position> {
+        if self.king_position(self.turn).is_none() || self.chess_at(from).player() != Some(self.turn) {
+            return vec![];
+        }
+        self.generate_move_at(from, false)
+            .into_iter()
+            .filter(|m| {
+                self.do_move(m, false);
+                let legal = !self.is_checked(self.turn.next());
+                self.undo_move(m);
+                legal
+            })
+            .map(|m| m.to)
+            .collect()
+    }
+    // 走棋方被将军时返回将帅的位置
+    pub fn checked_king(&self) -> Option<Position> {
+        let king = self.king_position(self.turn)?;
+        if self.is_checked(self.turn) {
+            Some(king)
+        } else {
+            None
+        }
     }
     pub fn select(&mut self, pos: (i32, i32)) -> bool {
         let chess = self.chess_at(pos.into());
 
+        // 选中时算好可以走到的位置，界面重画时不用再生成着法
         if chess.player() == Some(self.turn) {
-            self.select_pos = pos.into();
+            self.select_pos = Some(pos.into());
+            self.select_targets = self.legal_targets(pos.into());
             return true;
         }
 
@@ -891,30 +1111,25 @@ impl Board {
         from: Position, // 起手位置
         to: Position,   // 落子位置
     ) {
-        // check if the move is legal
-        let current_chess = match self.chess_status_at(from) {
-            Chess::None => match self.chess_at(from) {
-                Chess::None => Chess::None,
-                t => t,
+        // 只能走合法着法，走完不能被将军
+        if self.legal_targets(from).contains(&to) {
+            self.move_unchecked(from, to);
+        }
+    }
+    // 调用方已经确认是合法着法，比如点击选中棋子时算好的select_targets
+    fn move_unchecked(&mut self, from: Position, to: Position) {
+        self.do_move(
+            &Move {
+                player: self.turn,
+                from,
+                to,
+                chess: self.chess_at(from),
+                capture: self.chess_at(to),
             },
-            t => t,
-        };
-        if let Some(ct) = current_chess.chess_type() {
-            let possible_to = self.generate_move_for_chess_type(ct, from);
-            if (possible_to.iter().any(|&t| t == to)) {
-                self.do_move(
-                    &Move {
-                        player: self.turn,
-                        from,
-                        to,
-                        chess: self.chess_at(from),
-                        capture: self.chess_at(to),
-                    },
-                    self.jieqi,
-                );
-                self.get_lost_chess();
-            }
-        };
+            self.jieqi,
+        );
+        self.redo_moves.clear();
+        self.clear_selection();
     }
     pub fn get_lost_chess(&self) {
         let mut red_chess_nums = [1, 2, 2, 2, 2, 2, 5];
@@ -987,6 +1202,12 @@ impl Board {
             Chess::None
         }
     }
+    // 揭棋中未翻开的棋子按所在位置的棋子类型走
+    pub fn move_type_at(&self, pos: Position) -> Option<ChessType> {
+        self.chess_status_at(pos)
+            .chess_type()
+            .or(self.chess_at(pos).chess_type())
+    }
     pub fn set_chess(&mut self, pos: Position, chess: Chess, update_status: bool) {
         self.chesses[pos.row as usize][pos.col as usize] = chess;
         if update_status {
@@ -1053,7 +1274,7 @@ impl Board {
         let targets = self.generate_move_for_chess_type(ChessType::Cannon, position_base);
         for pos in targets {
             if self.chess_at(pos).belong_to(player.next()) {
-                if let Some(ChessType::Cannon) = self.chess_at(pos).chess_type() {
+                if let Some(ChessType::Cannon) = self.move_type_at(pos) {
                     return true;
                 }
             }
@@ -1062,7 +1283,7 @@ impl Board {
         let targets = self.generate_move_for_chess_type(ChessType::Rook, position_base);
         for pos in targets {
             if self.chess_at(pos).belong_to(player.next()) {
-                if let Some(ChessType::Rook) = self.chess_at(pos).chess_type() {
+                if let Some(ChessType::Rook) = self.move_type_at(pos) {
                     return true;
                 }
             }
@@ -1088,7 +1309,7 @@ impl Board {
         }
         for pos in targets {
             if self.chess_at(pos).belong_to(player.next()) {
-                if let Some(ChessType::Knight) = self.chess_at(pos).chess_type() {
+                if let Some(ChessType::Knight) = self.move_type_at(pos) {
                     return true;
                 }
             }
@@ -1105,7 +1326,7 @@ impl Board {
             },
         ] {
             if self.chess_at(pos).belong_to(player.next()) {
-                if let Some(ChessType::Pawn) = self.chess_at(pos).chess_type() {
+                if let Some(ChessType::Pawn) = self.move_type_at(pos) {
                     return true;
                 }
             }
@@ -1286,50 +1507,55 @@ impl Board {
         }
         targets
     }
+    // 生成某个位置上棋子的所有着法
+    pub fn generate_move_at(&self, position_base: Position, capture_only: bool) -> Vec<Move> {
+        let mut moves = vec![];
+        let chess = self.chess_at(position_base);
+        let chess_status = self.chess_status_at(position_base);
+        if chess.belong_to(self.turn) {
+            if let Some(ct) = chess.chess_type() {
+                let targets = if let Some(ct_status) = chess_status.chess_type() {
+                    self.generate_move_for_chess_type(ct_status, position_base)
+                } else {
+                    self.generate_move_for_chess_type(ct, position_base)
+                };
+                let move_base = Move {
+                    player: self.turn,
+                    from: position_base,
+                    to: position_base,
+                    chess,
+                    capture: Chess::None,
+                };
+                for target in targets {
+                    let valid = if ct == ChessType::King || ct == ChessType::Advisor {
+                        // 帅和士要在九宫格内
+                        in_palace(target, self.turn)
+                    } else if ct == ChessType::Bishop {
+                        // 象不能过河
+                        in_country(target.row, self.turn) && in_board(target)
+                    } else {
+                        in_board(target)
+                    };
+
+                    if valid {
+                        if !self.chess_at(target).belong_to(self.turn)
+                            && (!capture_only || self.chess_at(target).chess_type().is_some())
+                        {
+                            moves.push(move_base.with_target(target, self.chess_at(target)));
+                        }
+                    }
+                }
+            }
+        }
+        moves
+    }
     pub fn generate_move(&mut self, capture_only: bool) -> Vec<Move> {
         self.gen_counter += 1;
         let mut moves = vec![];
         for i in 0..BOARD_HEIGHT {
             for j in 0..BOARD_WIDTH {
-                let position_base = Position::new(i, j);
                 // 遍历每个行棋方的棋
-                let chess = self.chess_at(position_base);
-                let chess_status = self.chess_status_at(position_base);
-                if chess.belong_to(self.turn) {
-                    if let Some(ct) = chess.chess_type() {
-                        let targets = if let Some(ct_status) = chess_status.chess_type() {
-                            self.generate_move_for_chess_type(ct_status, position_base)
-                        } else {
-                            self.generate_move_for_chess_type(ct, position_base)
-                        };
-                        let move_base = Move {
-                            player: self.turn,
-                            from: position_base,
-                            to: position_base,
-                            chess,
-                            capture: Chess::None,
-                        };
-                        for target in targets {
-                            let valid = if ct == ChessType::King || ct == ChessType::Advisor {
-                                // 帅和士要在九宫格内
-                                in_palace(target, self.turn)
-                            } else if ct == ChessType::Bishop {
-                                // 象不能过河
-                                in_country(target.row, self.turn) && in_board(target)
-                            } else {
-                                in_board(target)
-                            };
-
-                            if valid {
-                                if !self.chess_at(target).belong_to(self.turn)
-                                    && (!capture_only || self.chess_at(target).chess_type().is_some())
-                                {
-                                    moves.push(move_base.with_target(target, self.chess_at(target)));
-                                }
-                            }
-                        }
-                    }
-                }
+                moves.append(&mut self.generate_move_at(Position::new(i, j), capture_only));
             }
         }
         moves.sort_by(|a, b| {
@@ -1338,10 +1564,58 @@ impl Board {
         });
         moves
     }
-    // 简单的评价，双方每个棋子的子力之和的差
+    // ICCS格式的着法，如h2e2，不符合当前局面时返回None
+    pub fn parse_move(&self, s: &str) -> Option<Move> {
+        let b = s.as_bytes();
+        let square = |i: usize| (b'a'..=b'i').contains(&b[i]) && b[i + 1].is_ascii_digit();
+        if b.len() != 4 || !square(0) || !square(2) {
+            return None;
+        }
+        let (from, to): (Position, Position) = (s[..2].into(), s[2..].into());
+        let m = Move {
+            player: self.turn,
+            from,
+            to,
+            chess: self.chess_at(from),
+            capture: self.chess_at(to),
+        };
+        if self.is_pseudo_legal(&m) {
+            Some(m)
+        } else {
+            None
+        }
+    }
+    // 不会送将的着法
+    pub fn legal_moves(&mut self) -> Vec<Move> {
+        self.generate_move(false)
+            .into_iter()
+            .filter(|m| {
+                self.do_move(m, false);
+                let legal = !self.is_checked(self.turn.next());
+                self.undo_move(m);
+                legal
+            })
+            .collect()
+    }
+    // 着法是否符合当前局面（用于检查置换表着法和杀手着法）
+    pub fn is_pseudo_legal(&self, m: &Move) -> bool {
+        m.player == self.turn
+            && in_board(m.from)
+            && in_board(m.to)
+            && self.chess_at(m.from) == m.chess
+            && self.chess_at(m.to) == m.capture
+            && self.generate_move_at(m.from, false).contains(m)
+    }
+    // 双方每个棋子的子力位置分之和的差，加上机动性、将的安全等评价项，
+    // 中局和残局分别计算，再按剩余子力算出的阶段插值
     pub fn evaluate(&self, player: Player) -> i32 {
-        let mut red_score = 0;
-        let mut black_score = 0;
+        // 打开nnue特性并且加载了网络时用神经网络评价
+        #[cfg(feature = "nnue")]
+        if let Some(v) = self.evaluate_nnue(player) {
+            return v;
+        }
+        let mut red_score = Score::default();
+        let mut black_score = Score::default();
         for i in 0..BOARD_HEIGHT as usize {
             for j in 0..BOARD_WIDTH as usize {
                 let chess = self.chess_at(Position::new(i as i32, j as i32));
@@ -1351,15 +1625,11 @@ impl Board {
                     } else {
                         Position::new(i as i32, j as i32)
                     };
-                    let score = match ct {
-                        ChessType::King => KING_VALUE_TABLE[pos.row as usize][pos.col as usize],
-                        ChessType::Advisor => ADVISOR_VALUE_TABLE[pos.row as usize][pos.col as usize],
-                        ChessType::Bishop => BISHOP_VALUE_TABLE[pos.row as usize][pos.col as usize],
-                        ChessType::Knight => KNIGHT_VALUE_TABLE[pos.row as usize][pos.col as usize],
-                        ChessType::Rook => ROOK_VALUE_TABLE[pos.row as usize][pos.col as usize],
-                        ChessType::Cannon => CANNON_VALUE_TABLE[pos.row as usize][pos.col as usize],
-                        ChessType::Pawn => PAWN_VALUE_TABLE[pos.row as usize][pos.col as usize],
-                    };
+                    let table = self.eval_params.table(ct);
+                    let score = Score::new(
+                        table.mg[pos.row as usize][pos.col as usize],
+                        table.eg[pos.row as usize][pos.col as usize],
+                    );
                     if chess.belong_to(Player::Black) {
                         black_score += score
                     } else {
@@ -1368,60 +1638,87 @@ impl Board {
                 }
             }
         }
-        if player == Player::Red {
-            red_score - black_score + INITIATIVE_BONUS
+        let score = if player == Player::Red {
+            red_score - black_score
         } else {
-            black_score - red_score + INITIATIVE_BONUS
-        }
+            black_score - red_score
+        };
+        let value = (score + self.evaluate_features(player)).taper(self.phase()) + self.eval_params.initiative_bonus;
+        // 多子也赢不了的残局缩小局面分
+        self.scale_endgame(value, player)
     }
     pub fn find_record(&self) -> Option<Record> {
-        if let Some(record) = &self.records[(self.zobrist_value & (RECORD_SIZE - 1) as u64) as usize] {
-            if record.zobrist_lock == self.zobrist_value_lock && self.turn == record.turn {
-                Some(record.clone())
-            } else {
-                None
-            }
-        } else {
-            None
-        }
+        self.records
+            .find(self.zobrist_value, self.zobrist_value_lock, self.turn)
     }
     pub fn add_record(&mut self, record: Record) {
-        if let Some(old_record) = &self.records[(self.zobrist_value & (RECORD_SIZE - 1) as u64) as usize] {
-            // 如果已存在，用深度较大的覆盖，depth越小，深度越大
-            if record.depth < old_record.depth {
-                self.records[(self.zobrist_value & (RECORD_SIZE - 1) as u64) as usize] = Some(record);
-            }
-        } else {
-            self.records[(self.zobrist_value & (RECORD_SIZE - 1) as u64) as usize] = Some(record);
+        // 根节点排除了部分着法时，搜索结果不完整
+        if self.distance == 0 && !self.excluded_moves.is_empty() {
+            return;
         }
+        self.records.add(self.zobrist_value, record);
+    }
+    // 多线程搜索时其他线程已经搜索完毕
+    pub fn is_stopped(&self) -> bool {
+        self.stop.load(Ordering::Relaxed)
     }
-    pub fn alpha_beta_pvs(&mut self, depth: i32, mut alpha: i32, beta: i32) -> (i32, Option<Move>) {
-        // if let Some(record) = self.find_record() {
-        //     if record.depth <= depth {
-        //         return (record.value, record.best_move);
-        //     }
-        // }
+    // 置换表着法：优先使用上一轮迭代的主要变例，其次是置换表中记录的最佳着法
+    fn hash_move(&self) -> Option<Move> {
+        let ply = self.distance.max(0) as usize;
+        let path = &self.move_history[self.move_history.len().saturating_sub(ply)..];
+        if self.best_moves_last.len() > ply && self.best_moves_last[..ply] == *path {
+            return Some(self.best_moves_last[ply].clone());
+        }
+        self.find_record()
+            .and_then(|record| record.best_move)
+    }
+    pub fn alpha_beta_pvs(&mut self, depth: i32, mut alpha: i32, mut beta: i32) -> (i32, Option<Move>) {
+        if self.is_stopped() {
+            return (alpha, None);
+        }
+        let ply = self.distance;
+        if ply > 0 {
+            // 杀棋步数裁剪：已经找到更短的杀棋时，不必再搜索
+            alpha = alpha.max(mated_value(ply));
+            beta = beta.min(-mated_value(ply + 1));
+            if alpha >= beta {
+                return (alpha, None);
+            }
+            if let Some(record) = self.find_record() {
+                if record.depth >= depth {
+                    let value = value_from_record(record.value, ply);
+                    match record.flag {
+                        RecordFlag::Exact => return (value, record.best_move),
+                        RecordFlag::Lower if value >= beta => return (value, None),
+                        RecordFlag::Upper if value <= alpha => return (value, None),
+                        _ => (),
+                    }
+                }
+            }
+            // 残局库中有的局面直接返回准确的分数
+            if let Some(outcome) = self.probe_tablebase() {
+                return (outcome.score(ply), None);
+            }
+        }
         if depth == 0 {
             self.counter += 1;
             return (self.quies(alpha, beta), None);
         }
+        let alpha_origin = alpha;
         let mut count = 0; // 记录尝试了多少种着法
 
-        // 优先尝试迭代深度搜索的上一层搜索结果
-        let mut moves = self.generate_move(false);
-        // 如果符合上次搜索的着法线路，那么优先按此线路搜索下去
-        for (i, m) in self.best_moves_last.iter().enumerate() {
-            if let Some(ml) = self.move_history.get(i) {
-                if m != ml {
-                    break;
-                }
-            } else {
-                moves.insert(0, m.clone());
-                break;
-            }
-        }
+        // 分阶段生成着法：置换表着法、吃子、杀手着法、其余着法
+        let mut sorter = MoveSorter::new(
+            self.hash_move(),
+            self.heuristics.killers(self.distance),
+            self.heuristics
+                .counter_move(self.move_history.last()),
+        );
         let mut best_move = None;
-        for m in moves {
+        while let Some(m) = sorter.next(self) {
+            if ply == 0 && self.excluded_moves.contains(&m) {
+                continue;
+            }
             self.do_move(&m, false);
             if self.is_checked(self.turn.next()) {
                 self.undo_move(&m);
@@ -1435,16 +1732,14 @@ impl Board {
             let mut bm = bmt;
             if best_value == MIN || (best_value > alpha && best_value < beta) {
                 let (v, bmt) = self.alpha_beta_pvs(depth - 1, -beta, -alpha);
-                // self.add_record(Record {
-                //     value: -v,
-                //     depth,
-                //     best_move: bmt.clone(),
-                //     zobrist_lock: self.zobrist_value_lock,
-                //     turn: self.turn,
-                // });
                 best_value = -v;
                 bm = bmt;
             }
+            // 搜索被中止，结果不可信，也不能存入置换表
+            if self.is_stopped() {
+                self.undo_move(&m);
+                return (alpha, None);
+            }
 
             // let (v, bmt) = self.alpha_beta(depth - 1, -beta, -alpha);
             // let mut best_value = -v;
@@ -1452,6 +1747,19 @@ impl Board {
 
             if best_value >= beta {
                 self.undo_move(&m);
+                // 非吃子着法产生截断，记入杀手着法和历史表
+                if m.capture.chess_type().is_none() {
+                    self.heuristics
+                        .update(&m, self.move_history.last(), depth, self.distance);
+                }
+                self.add_record(Record {
+                    value: value_to_record(best_value, ply),
+                    depth,
+                    best_move: Some(m),
+                    zobrist_lock: self.zobrist_value_lock,
+                    turn: self.turn,
+                    flag: RecordFlag::Lower,
+                });
                 return (best_value, None);
             }
             if best_value > alpha {
@@ -1462,88 +1770,214 @@ impl Board {
             self.undo_move(&m);
         }
 
-        // 如果尝试的着法数为0,说明已经被绝杀
-        // 深度减分，深度越小，说明越早被将死，局面分应该越低，由于depth是递减的，
-        // 所以深度越小，depth越大，减去depth的局面分就越低
-        return (if count == 0 { KILL - depth } else { alpha }, best_move);
+        // 如果尝试的着法数为0,说明已经被绝杀（困毙也算输）
+        // 按距离根节点的步数加分，越早被将死，局面分越低
+        if count == 0 {
+            return (mated_value(ply), None);
+        }
+        self.add_record(Record {
+            value: value_to_record(alpha, ply),
+            depth,
+            best_move: best_move.clone(),
+            zobrist_lock: self.zobrist_value_lock,
+            turn: self.turn,
+            flag: if alpha > alpha_origin {
+                RecordFlag::Exact
+            } else {
+                RecordFlag::Upper
+            },
+        });
+        return (alpha, best_move);
     }
-    pub fn quies(&mut self, mut alpha: i32, beta: i32) -> i32 {
+    pub fn quies(&mut self, alpha: i32, beta: i32) -> i32 {
+        self.quies_pv(alpha, beta, None)
+    }
+    // 静态搜索，pv不为None时同时记录主要变例
+    pub fn quies_pv(&mut self, mut alpha: i32, beta: i32, mut pv: Option<&mut Vec<Move>>) -> i32 {
+        if let Some(pv) = pv.as_deref_mut() {
+            pv.clear();
+        }
         if self.distance > MAX_DEPTH {
             return self.evaluate(self.turn);
         }
-        let v = self.evaluate(self.turn);
-        if v >= beta {
-            return beta;
-        }
-        if v > alpha {
-            alpha = v
+        // 被将军时不能直接用局面分，要搜索所有应将的着法
+        let in_check = self.is_checked(self.turn);
+        if !in_check {
+            let v = self.evaluate(self.turn);
+            if v >= beta {
+                return beta;
+            }
+            if v > alpha {
+                alpha = v
+            }
         }
-        let moves = if self.is_checked(self.turn.next()) {
+        let moves = if in_check {
             self.generate_move(false)
         } else {
-            self.generate_move(true)
+            // 按静态交换评估排序，亏子的吃子不再搜索
+            let mut captures: Vec<(i32, Move)> = self
+                .generate_move(true)
+                .into_iter()
+                .map(|m| (self.see(&m), m))
+                .filter(|(see, _)| *see >= 0)
+                .collect();
+            captures.sort_by_key(|(see, _)| -see);
+            captures.into_iter().map(|(_, m)| m).collect()
         };
+        let mut count = 0;
+        let mut child = vec![];
         for m in moves {
             self.do_move(&m, false);
             if self.is_checked(self.turn.next()) {
                 self.undo_move(&m);
                 continue;
             }
-            let v = -self.quies(-beta, -alpha);
+            count += 1;
+            let v = -self.quies_pv(-beta, -alpha, pv.is_some().then_some(&mut child));
             self.undo_move(&m);
             if v >= beta {
                 return beta;
             }
             if v > alpha {
                 alpha = v;
+                if let Some(pv) = pv.as_deref_mut() {
+                    pv.clear();
+                    pv.push(m);
+                    pv.append(&mut child);
+                }
             }
         }
+        // 被将军且无着可应，已被将死
+        if in_check && count == 0 {
+            return mated_value(self.distance);
+        }
         return alpha;
     }
     pub fn iterative_deepening(&mut self, max_depth: i32) -> (i32, Option<Move>) {
-        if max_depth > 3 {
-            for depth in 3..max_depth + 1 {
-                // self.records = vec![RECORD_NONE; RECORD_SIZE as usize];
-                let (v, bm) = self.alpha_beta_pvs(depth, MIN, MAX);
-                if depth == max_depth {
-                    println!("第{}层: {:?}", depth, bm);
-                    return (v, bm);
+        self.iterative_deepening_with(max_depth, |_| {})
+    }
+    // 迭代加深搜索，每搜完一层调用一次callback
+    pub fn iterative_deepening_with(
+        &mut self,
+        max_depth: i32,
+        callback: impl FnMut(&DepthResult),
+    ) -> (i32, Option<Move>) {
+        self.iterative_deepening_skip(max_depth, |_| false, callback)
+    }
+    // skip返回true的层不搜索，多线程搜索时辅助线程用来错开搜索的深度
+    pub fn iterative_deepening_skip(
+        &mut self,
+        max_depth: i32,
+        skip: impl Fn(i32) -> bool,
+        mut callback: impl FnMut(&DepthResult),
+    ) -> (i32, Option<Move>) {
+        // distance记录从搜索根节点开始的步数
+        self.distance = 0;
+        self.best_moves_last = vec![];
+        self.heuristics.new_search();
+        if self.records.is_empty() {
+            self.records = Arc::new(RecordTable::new(RECORD_SIZE as usize));
+        }
+        let mut result = (mated_value(0), None);
+        for depth in 1..max_depth.max(1) + 1 {
+            if skip(depth) {
+                continue;
+            }
+            let last_value = if result.1.is_some() { Some(result.0) } else { None };
+            let (value, best_move) = self.aspiration_search(depth, last_value);
+            // 被中止的这一层结果不完整，用上一层的结果，第一层都没搜完时用备用着法
+            if self.is_stopped() {
+                if result.1.is_none() {
+                    if let Some(m) = self.fallback_move() {
+                        result = (self.evaluate(self.turn), Some(m));
+                    }
                 }
-                self.best_moves_last = vec![];
-                self.best_moves_last.reverse();
-                println!("第{}层: {:?}", depth, self.best_moves_last);
+                break;
             }
-        } else {
-            // self.records = vec![RECORD_NONE; RECORD_SIZE as usize];
-            return self.alpha_beta_pvs(max_depth, MIN, MAX);
+            let best_move = match best_move {
+                Some(m) => m,
+                // 无着可走，已经输了
+                None => return (value, None),
+            };
+            let pv = self.principal_variation(&best_move);
+            self.best_moves_last = pv.clone();
+            result = (value, Some(best_move.clone()));
+            callback(&DepthResult {
+                depth,
+                value,
+                best_move,
+                pv,
+                nodes: self.counter,
+            });
+        }
+        result
+    }
+    // 来不及搜索时走的着法：置换表中的着法，没有时用第一个合法着法
+    pub fn fallback_move(&mut self) -> Option<Move> {
+        let legal_moves = self.legal_moves();
+        self.find_record()
+            .and_then(|record| record.best_move)
+            .filter(|m| legal_moves.contains(m))
+            .or_else(|| legal_moves.into_iter().next())
+    }
+    // 渴望窗口搜索：以上一层的分数为中心开一个小窗口，失败时放宽窗口重新搜索
+    fn aspiration_search(&mut self, depth: i32, last_value: Option<i32>) -> (i32, Option<Move>) {
+        let mut delta = ASPIRATION_WINDOW;
+        let (mut alpha, mut beta) = match last_value {
+            Some(v) if !is_mate_value(v) => ((v - delta).max(MIN), (v + delta).min(MAX)),
+            _ => (MIN, MAX),
+        };
+        loop {
+            let (value, best_move) = self.alpha_beta_pvs(depth, alpha, beta);
+            if self.is_stopped() {
+                return (value, best_move);
+            }
+            if value <= alpha && alpha > MIN {
+                alpha = (value - delta).max(MIN);
+            } else if value >= beta && beta < MAX {
+                beta = (value + delta).min(MAX);
+            } else {
+                return (value, best_move);
+            }
+            delta *= 2;
         }
-        (0, None)
     }
 }
 
 #[cfg(test)]
 mod tests {
     use crate::board::*;
+    use crate::eval::EvalParams;
 
     #[test]
     fn test_generate_move() {
-        let mut board = Board::init();
+        let mut board = Board::init(false, false);
         for i in 0..1_000 {
             board.generate_move(false);
         }
-        assert_eq!(Board::init().generate_move(false).len(), 5 + 24 + 4 + 4 + 4 + 2 + 1);
+        assert_eq!(
+            Board::init(false, false)
+                .generate_move(false)
+                .len(),
+            5 + 24 + 4 + 4 + 4 + 2 + 1
+        );
     }
     #[test]
     fn test_is_checked() {
-        let mut board = Board::init();
+        let mut board = Board::init(false, false);
         for _i in 0..10_000 {
             board.is_checked(Player::Red);
         }
-        assert_eq!(Board::init().generate_move(false).len(), 5 + 24 + 4 + 4 + 4 + 2 + 1);
+        assert_eq!(
+            Board::init(false, false)
+                .generate_move(false)
+                .len(),
+            5 + 24 + 4 + 4 + 4 + 2 + 1
+        );
     }
     #[test]
     fn test_move_and_unmove() {
-        let mut board = Board::init();
+        let mut board = Board::init(false, false);
         for _i in 0..8_000 {
             let m = Move {
                 player: Player::Red,
@@ -1555,12 +1989,17 @@ mod tests {
             board.apply_move(&m, false);
             board.undo_move(&m);
         }
-        assert_eq!(Board::init().generate_move(false).len(), 5 + 24 + 4 + 4 + 4 + 2 + 1);
+        assert_eq!(
+            Board::init(false, false)
+                .generate_move(false)
+                .len(),
+            5 + 24 + 4 + 4 + 4 + 2 + 1
+        );
     }
 
     #[test]
     fn test_evaluate() {
-        let mut board = Board::init();
+        let mut board = Board::init(false, false);
         board.apply_move(
             &Move {
                 player: Player::Red,
@@ -1574,21 +2013,254 @@ mod tests {
         for i in 0..10_000 {
             board.evaluate(Player::Red);
         }
+        // 只比较子力位置分
+        board.eval_params = EvalParams::disabled();
         assert_eq!(board.evaluate(Player::Red), 7);
     }
 
     #[test]
     fn test_alpha_beta_pvs() {
-        println!("{:?}", Board::init().alpha_beta_pvs(1, MIN, MAX));
-        // println!("{:?}", Board::init().alpha_beta_pvs(2, MIN, MAX));
-        // println!("{:?}", Board::init().alpha_beta_pvs(3, MIN, MAX));
-        // println!("{:?}", Board::init().alpha_beta_pvs(4, MIN, MAX));
-        // let mut board = Board::init();
+        println!("{:?}", Board::init(false, false).alpha_beta_pvs(1, MIN, MAX));
+        // println!("{:?}", Board::init(false, false).alpha_beta_pvs(2, MIN, MAX));
+        // println!("{:?}", Board::init(false, false).alpha_beta_pvs(3, MIN, MAX));
+        // println!("{:?}", Board::init(false, false).alpha_beta_pvs(4, MIN, MAX));
+        // let mut board = Board::init(false, false);
         // let rst = board.minimax(5, Player::Red, i32::MIN, i32::MAX);
         // let counter = board.counter;
         // println!("{} \n {:?}", counter, rst); // 跳马
         //                                       /* */
-        // println!("{:?}", Board::init().alpha_beta_pvs(6, MIN, MAX)); // 跳马
+        // println!("{:?}", Board::init(false, false).alpha_beta_pvs(6, MIN, MAX)); // 跳马
+    }
+
+    #[test]
+    fn test_jieqi_search_restores_status() {
+        let mut board = Board::init(true, false);
+        let (chesses, chesses_status) = (board.chesses, board.chesses_status);
+        board.iterative_deepening(3);
+        assert_eq!(board.chesses, chesses);
+        assert_eq!(board.chesses_status, chesses_status);
+        assert!(board.status_history.is_empty());
+    }
+
+    #[test]
+    fn test_to_fen() {
+        for fen in [
+            "rnbakabnr/9/1c5c1/p1p1p1p1p/9/9/P1P1P1P1P/1C5C1/9/RNBAKABNR w - - 0 1",
+            "4k4/9/9/9/9/9/9/4p4/9/5K3 b - - 0 1",
+        ] {
+            assert_eq!(Board::from_fen(fen).to_fen(), fen);
+        }
+    }
+
+    #[test]
+    fn test_mate_score() {
+        // 黑卒进一步，红帅困毙
+        let mut board = Board::from_fen("4k4/9/9/9/9/9/9/4p4/9/5K3 b - - 0 1");
+        let (value, best_move) = board.iterative_deepening(3);
+        assert_eq!(value, -mated_value(1));
+        assert_eq!(best_move.unwrap().chess, Chess::Black(ChessType::Pawn));
+        // 换个深度搜索，杀棋分数不变
+        let (value, _) = board.iterative_deepening(5);
+        assert_eq!(value, -mated_value(1));
+    }
+
+    #[test]
+    fn test_quies_mate() {
+        // 双车错杀，红方无着可应
+        let mut board = Board::from_fen("5k3/9/9/9/9/9/9/9/r8/r2K5 w - - 0 1");
+        assert_eq!(board.quies(MIN, MAX), mated_value(0));
+        assert_eq!(board.alpha_beta_pvs(1, MIN, MAX).0, mated_value(0));
+    }
+
+    #[test]
+    fn test_iterative_deepening_callback() {
+        let mut board = Board::from_fen("rnbakabnr/9/1c5c1/p1p1p1p1p/9/9/P1P1P1P1P/1C5C1/9/RNBAKABNR w - - 0 1");
+        let mut depths = vec![];
+        let (value, best_move) = board.iterative_deepening_with(4, |result| {
+            assert_eq!(result.pv[0], result.best_move);
+            depths.push((result.depth, result.value, result.best_move.clone()));
+        });
+        assert_eq!(depths.iter().map(|d| d.0).collect::<Vec<i32>>(), vec![1, 2, 3, 4]);
+        let last = depths.last().unwrap();
+        assert_eq!(last.1, value);
+        assert_eq!(Some(last.2.clone()), best_move);
+    }
+
+    #[test]
+    fn test_iterative_deepening_no_move() {
+        // 红方被将死，没有着法可走
+        let mut board = Board::from_fen("5k3/9/9/9/9/9/9/9/r8/r2K5 w - - 0 1");
+        assert_eq!(board.iterative_deepening(3), (mated_value(0), None));
+        let mut board = Board::from_fen("rnbakabnr/9/1c5c1/p1p1p1p1p/9/9/P1P1P1P1P/1C5C1/9/RNBAKABNR w - - 0 1");
+        assert!(board.iterative_deepening(0).1.is_some());
+    }
+
+    #[test]
+    fn test_iterative_deepening_stopped() {
+        // 第一层没搜完就停止时仍然给出合法的着法
+        let mut board = Board::from_fen("rnbakabnr/9/1c5c1/p1p1p1p1p/9/9/P1P1P1P1P/1C5C1/9/RNBAKABNR w - - 0 1");
+        board
+            .stop
+            .store(true, std::sync::atomic::Ordering::Relaxed);
+        let (_, best_move) = board.iterative_deepening(MAX_DEPTH);
+        assert!(board.legal_moves().contains(&best_move.unwrap()));
+        let lines = board.search_multipv(MAX_DEPTH, 3);
+        assert_eq!(lines.len(), 1);
+    }
+
+    #[test]
+    fn test_record_mate_value() {
+        let value = -mated_value(7);
+        assert!(is_mate_value(value));
+        assert_eq!(value_from_record(value_to_record(value, 3), 3), value);
+        // 在第3层存入的杀棋分数，在第5层取出时要多算两步
+        assert_eq!(value_from_record(value_to_record(value, 3), 5), -mated_value(9));
+        assert_eq!(value_to_record(120, 3), 120);
+    }
+
+    #[test]
+    fn test_undo_redo() {
+        let mut board = Board::init(false, false);
+        let (fen, zobrist) = (board.to_fen(), board.zobrist_value);
+        assert!(!board.undo());
+        // 炮二进七吃马，马2进3
+        board.move_to(Position::new(7, 7), Position::new(0, 7));
+        board.move_to(Position::new(0, 1), Position::new(2, 2));
+        let after = board.to_fen();
+        assert!(board.undo());
+        assert_eq!(board.turn, Player::Black);
+        assert!(board.undo());
+        assert_eq!(board.to_fen(), fen);
+        assert_eq!(board.zobrist_value, zobrist);
+        assert_eq!(board.chess_at(Position::new(0, 7)), Chess::Black(ChessType::Knight));
+        assert!(board.redo());
+        assert!(board.redo());
+        assert!(!board.redo());
+        assert_eq!(board.to_fen(), after);
+        // 悔棋后走了新的着法就不能再重走
+        board.undo();
+        board.move_to(Position::new(0, 1), Position::new(2, 0));
+        assert_eq!(board.move_history.len(), 2);
+        assert!(board.redo_moves.is_empty());
+        // 人机对战时电脑的着法一起退回
+        board.robot = true;
+        assert!(board.undo());
+        assert_eq!(board.move_history.len(), 0);
+        assert_eq!(board.turn, Player::Red);
+        assert!(board.redo());
+        assert_eq!(board.move_history.len(), 2);
+    }
+
+    #[test]
+    fn test_goto_ply() {
+        let mut board = Board::init(false, false);
+        board.robot = true;
+        board.move_to(Position::new(7, 7), Position::new(0, 7));
+        board.move_to(Position::new(0, 8), Position::new(0, 7));
+        board.move_to(Position::new(7, 1), Position::new(0, 1));
+        let moves = board.game_moves();
+        assert_eq!(
+            board.captured_chesses(),
+            [
+                vec![Chess::Red(ChessType::Cannon)],
+                vec![Chess::Black(ChessType::Knight), Chess::Black(ChessType::Knight)]
+            ]
+        );
+        // 复盘时可以停在黑方走棋的局面
+        board.goto_ply(1);
+        assert_eq!(board.turn, Player::Black);
+        assert_eq!(board.game_moves(), moves);
+        assert_eq!(
+            board.captured_chesses(),
+            [vec![], vec![Chess::Black(ChessType::Knight)]]
+        );
+        board.goto_ply(10);
+        assert_eq!(board.move_history, moves);
+        board.goto_ply(0);
+        assert_eq!(board.to_fen(), Board::init(false, false).to_fen());
+    }
+
+    #[test]
+    fn test_legal_targets() {
+        let mut board = Board::init(false, false);
+        let mut targets = board.legal_targets(Position::new(9, 1));
+        targets.sort_by_key(|p| p.col);
+        assert_eq!(targets, vec![Position::new(7, 0), Position::new(7, 2)]);
+        assert!(board
+            .legal_targets(Position::new(0, 1))
+            .is_empty());
+        assert_eq!(board.checked_king(), None);
+        // 被车贴身将军，车不能垫，帅吃车会和将照面，只能左右躲开
+        let mut board = Board::from_fen("4k4/9/9/9/9/9/9/9/4r4/R3K4 w - - 0 1");
+        assert_eq!(board.checked_king(), Some(Position::new(9, 4)));
+        assert_eq!(board.legal_targets(Position::new(9, 0)), vec![]);
+        assert_eq!(board.legal_targets(Position::new(9, 4)).len(), 2);
+        // 将帅已经被吃掉
+        let mut board = Board::from_fen("4k4/9/9/9/9/9/9/9/9/R8 w - - 0 1");
+        assert_eq!(board.checked_king(), None);
+        assert!(board
+            .legal_targets(Position::new(9, 0))
+            .is_empty());
+    }
+
+    #[test]
+    fn test_selection() {
+        let mut board = Board::init(false, false);
+        // 选中红马，点不能走的位置不走棋
+        board.click((1, 9));
+        assert_eq!(board.select_pos, Some(Position::new(9, 1)));
+        assert_eq!(board.select_targets.len(), 2);
+        board.click((1, 8));
+        assert!(board.move_history.is_empty());
+        // 走棋后清除选中
+        board.click((2, 7));
+        assert_eq!(board.move_history.len(), 1);
+        assert_eq!(board.select_pos, None);
+        assert!(board.select_targets.is_empty());
+        // 悔棋后也清除选中
+        board.click((1, 0));
+        assert!(board.select_pos.is_some());
+        assert!(board.undo());
+        assert_eq!(board.select_pos, None);
+    }
+
+    #[test]
+    fn test_click_robot_side() {
+        // 复盘到轮到电脑走的局面，点击不能走电脑的棋子
+        let mut board = Board::init(false, true);
+        board.click((1, 9));
+        board.click((2, 7));
+        assert!(board.robot_move());
+        board.goto_ply(1);
+        assert_eq!(board.turn, board.robot_side);
+        board.click((1, 0));
+        board.click((2, 2));
+        assert_eq!(board.select_pos, None);
+        assert_eq!(board.move_history.len(), 1);
+        // 由电脑接着走
+        assert!(board.robot_move());
+        assert_eq!(board.turn, Player::Red);
+    }
+
+    #[test]
+    fn test_robot_move() {
+        // 电脑执红先走
+        let mut board = Board::init(false, false);
+        board.robot = true;
+        board.robot_side = Player::Red;
+        assert!(board.robot_move());
+        assert!(!board.robot_move());
+        assert_eq!(board.turn, Player::Black);
+        assert!(board.undo());
+        assert_eq!(board.turn, Player::Red);
+        // 被将死时没有着法可走
+        let mut board = Board::from_fen("4k4/3PPP3/9/9/9/9/9/9/9/4K4 b - - 0 1");
+        board.robot = true;
+        assert!(!board.robot_move());
+        // 不能走送将的着法
+        let mut board = Board::from_fen("4k4/9/9/9/9/9/9/9/4r4/R3K4 w - - 0 1");
+        board.move_to(Position::new(9, 0), Position::new(8, 0));
+        assert!(board.move_history.is_empty());
     }
 
     #[test]
@@ -1602,7 +2274,7 @@ mod tests {
         let board = Board::from_fen("rnbakabnr/9/1c5c1/9/9/9/9/1C5C1/9/RNBAKABNR w - - 0 1");
         println!("{:?}", board.chesses);
         println!("{}", board.king_eye_to_eye());
-        let board = Board::init();
+        let board = Board::init(false, false);
         println!("{}", board.king_eye_to_eye());
     }
 }
@@ -0,0 +1,72 @@
+use std::str::FromStr;
+use std::{env, process, vec};
+
+// 命令行工具共用的参数读取，参数有误时打印用法并退出
+pub struct Args {
+    usage: &'static str,
+    args: vec::IntoIter<String>,
+}
+
+impl Args {
+    pub fn new(usage: &'static str) -> Self {
+        Self::from_vec(usage, env::args().skip(1).collect())
+    }
+
+    pub fn from_vec(usage: &'static str, args: Vec<String>) -> Self {
+        Args {
+            usage,
+            args: args.into_iter(),
+        }
+    }
+
+    // 选项后面跟的值
+    pub fn value(&mut self) -> String {
+        let usage = self.usage;
+        self.args.next().unwrap_or_else(|| fail(usage))
+    }
+
+    pub fn parse<T: FromStr>(&mut self) -> T {
+        parse(&self.value())
+    }
+
+    pub fn usage(&self) -> ! {
+        fail(self.usage)
+    }
+}
+
+impl Iterator for Args {
+    type Item = String;
+
+    fn next(&mut self) -> Option<String> {
+        self.args.next()
+    }
+}
+
+pub fn parse<T: FromStr>(s: &str) -> T {
+    s.parse()
+        .unwrap_or_else(|_| fail(&format!("invalid number {}", s)))
+}
+
+pub fn fail(message: &str) -> ! {
+    eprintln!("{}", message);
+    process::exit(1);
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::cli::*;
+
+    #[test]
+    fn test_args() {
+        let args = ["file", "--depth", "12", "--time", "1.5", "--out", "a.txt"];
+        let mut args = Args::from_vec("usage", args.iter().map(|s| s.to_string()).collect());
+        assert_eq!(args.next().as_deref(), Some("file"));
+        assert_eq!(args.next().as_deref(), Some("--depth"));
+        assert_eq!(args.parse::<usize>(), 12);
+        assert_eq!(args.next().as_deref(), Some("--time"));
+        assert_eq!(args.parse::<f64>(), 1.5);
+        assert_eq!(args.next().as_deref(), Some("--out"));
+        assert_eq!(args.value(), "a.txt");
+        assert_eq!(args.next(), None);
+    }
+}
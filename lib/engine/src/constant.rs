@@ -4,8 +4,14 @@ use std::{collections::HashMap, sync::LazyLock};
 pub const MIN: i32 = -99999;
 pub const KILL: i32 = MIN + 100;
 pub const MAX: i32 = 99999;
-pub const RECORD_SIZE: i32 = 0x1FFFFE;
+pub const RECORD_SIZE: i32 = 0x100000;
 pub const MAX_DEPTH: i32 = 64;
+// 绝对值不小于此值的分数为杀棋分数
+pub const MATE_BOUND: i32 = -KILL - MAX_DEPTH * 2;
+// 渴望窗口的初始半宽
+pub const ASPIRATION_WINDOW: i32 = 50;
+// 开局局面的FEN
+pub const STARTPOS: &str = "rnbakabnr/9/1c5c1/p1p1p1p1p/9/9/P1P1P1P1P/1C5C1/9/RNBAKABNR w - - 0 1";
 
 pub static FEN_MAP: LazyLock<HashMap<char, Chess>> = LazyLock::new(|| {
     HashMap::from([
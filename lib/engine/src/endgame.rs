@@ -0,0 +1,263 @@
+use crate::board::{Board, Chess, ChessType, Player, Position, BOARD_HEIGHT, BOARD_WIDTH};
+use std::fmt;
+
+// 残局识别：子力位置分只看子力多少，但很多残局多子也赢不了（如单马对士象全），
+// 识别出常见的子力组合后按比例缩小或者直接判和局面分
+
+// 不缩放时的比例
+pub const SCALE_NORMAL: i32 = 16;
+
+// 一方的子力
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct SideMaterial {
+    pub rooks: i32,
+    pub knights: i32,
+    pub cannons: i32,
+    pub pawns: i32,
+    pub bottom_pawns: i32, // 走到对方底线的兵（老兵），只能横走
+    pub advisors: i32,
+    pub bishops: i32,
+}
+
+impl SideMaterial {
+    // 车马炮兵的个数
+    pub fn attackers(&self) -> i32 {
+        self.rooks + self.knights + self.cannons + self.pawns
+    }
+    // 士象的个数
+    pub fn defenders(&self) -> i32 {
+        self.advisors + self.bishops
+    }
+    fn only(&self, rooks: i32, knights: i32, cannons: i32, pawns: i32) -> bool {
+        (self.rooks, self.knights, self.cannons, self.pawns) == (rooks, knights, cannons, pawns)
+    }
+}
+
+// 双方的子力，按红黑的顺序
+pub fn side_materials(board: &Board) -> [SideMaterial; 2] {
+    let mut sides = [SideMaterial::default(); 2];
+    for i in 0..BOARD_HEIGHT {
+        for j in 0..BOARD_WIDTH {
+            let chess = board.chess_at(Position::new(i, j));
+            let (side, ct) = match (chess.player(), chess.chess_type()) {
+                (Some(player), Some(ct)) => (&mut sides[player.value() as usize], ct),
+                _ => continue,
+            };
+            match ct {
+                ChessType::Rook => side.rooks += 1,
+                ChessType::Knight => side.knights += 1,
+                ChessType::Cannon => side.cannons += 1,
+                ChessType::Pawn => {
+                    side.pawns += 1;
+                    let bottom = if chess == Chess::Red(ChessType::Pawn) {
+                        0
+                    } else {
+                        BOARD_HEIGHT - 1
+                    };
+                    if i == bottom {
+                        side.bottom_pawns += 1;
+                    }
+                }
+                ChessType::Advisor => side.advisors += 1,
+                ChessType::Bishop => side.bishops += 1,
+                ChessType::King => {}
+            }
+        }
+    }
+    sides
+}
+
+// 识别出的残局类型，都是从优势一方来看
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Endgame {
+    NoAttackers,    // 没有车马炮兵，不可能将死对方
+    BottomPawn,     // 只有一个老兵
+    LoneCannon,     // 单炮没有自己的士做炮架，对光将
+    LonePawn,       // 单兵对双士
+    LoneKnight,     // 单马对士象
+    RookVsDefended, // 单车对马或炮士象全
+    EqualAttackers, // 双方车马炮兵相同，只多士象
+}
+
+impl Endgame {
+    // 局面分的缩放比例，满分为SCALE_NORMAL，0为和棋
+    pub fn scale(&self) -> i32 {
+        match self {
+            Endgame::NoAttackers | Endgame::BottomPawn | Endgame::LoneCannon | Endgame::LonePawn => 0,
+            Endgame::LoneKnight | Endgame::RookVsDefended => 2,
+            Endgame::EqualAttackers => 8,
+        }
+    }
+}
+
+impl fmt::Display for Endgame {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let s = match self {
+            Endgame::NoAttackers => "无进攻子力",
+            Endgame::BottomPawn => "单老兵",
+            Endgame::LoneCannon => "单炮无炮架",
+            Endgame::LonePawn => "单兵对双士",
+            Endgame::LoneKnight => "单马对士象",
+            Endgame::RookVsDefended => "单车对马炮士象全",
+            Endgame::EqualAttackers => "进攻子力相同",
+        };
+        write!(f, "{}", s)
+    }
+}
+
+// 识别strong一方占优时的残局类型
+pub fn recognize(board: &Board, strong: Player) -> Option<Endgame> {
+    let sides = side_materials(board);
+    let (s, w) = (sides[strong.value() as usize], sides[strong.next().value() as usize]);
+    if s.attackers() == 0 {
+        return Some(Endgame::NoAttackers);
+    }
+    // 进攻子力多于两个时不再细分
+    if s.attackers() > 2 || w.attackers() > 2 {
+        return None;
+    }
+    if s.only(0, 0, 0, 1) {
+        if s.bottom_pawns == 1 {
+            return Some(Endgame::BottomPawn);
+        }
+        if w.advisors == 2 {
+            return Some(Endgame::LonePawn);
+        }
+    }
+    // 单炮没有士做炮架时连光将也赢不了，有士做炮架时对单士单象也能赢，不缩放
+    if s.only(0, 0, 1, 0) && s.advisors == 0 && w.attackers() == 0 {
+        return Some(Endgame::LoneCannon);
+    }
+    if s.only(0, 1, 0, 0) && w.attackers() == 0 && w.defenders() >= 3 {
+        return Some(Endgame::LoneKnight);
+    }
+    // 单车能赢马双士和炮双士，士象全时才是和棋
+    if s.only(1, 0, 0, 0) && (w.only(0, 1, 0, 0) || w.only(0, 0, 1, 0)) && w.advisors == 2 && w.bishops == 2 {
+        return Some(Endgame::RookVsDefended);
+    }
+    if s.only(w.rooks, w.knights, w.cannons, w.pawns) && s.pawns == 0 {
+        return Some(Endgame::EqualAttackers);
+    }
+    None
+}
+
+impl Board {
+    // 按残局类型缩放player一方的局面分
+    pub fn scale_endgame(&self, value: i32, player: Player) -> i32 {
+        if value == 0 {
+            return value;
+        }
+        let strong = if value > 0 { player } else { player.next() };
+        match recognize(self, strong) {
+            Some(endgame) => value * endgame.scale() / SCALE_NORMAL,
+            None => value,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::board::*;
+    use crate::endgame::*;
+
+    fn check(fen: &str, strong: Player, expected: Option<Endgame>) {
+        let board = Board::from_fen(fen);
+        assert_eq!(recognize(&board, strong), expected, "{}", fen);
+    }
+
+    #[test]
+    fn test_no_attackers() {
+        check(
+            "3akab2/9/4b4/9/9/9/9/9/9/4K4 b - - 0 1",
+            Player::Black,
+            Some(Endgame::NoAttackers),
+        );
+    }
+
+    #[test]
+    fn test_bottom_pawn() {
+        check(
+            "3k4P/9/9/9/9/9/9/9/9/4K4 w - - 0 1",
+            Player::Red,
+            Some(Endgame::BottomPawn),
+        );
+        // 高兵对光将能赢
+        check("3k5/9/4P4/9/9/9/9/9/9/4K4 w - - 0 1", Player::Red, None);
+    }
+
+    #[test]
+    fn test_lone_pawn() {
+        check(
+            "3aka3/9/4P4/9/9/9/9/9/9/4K4 w - - 0 1",
+            Player::Red,
+            Some(Endgame::LonePawn),
+        );
+        check("3k1a3/9/4P4/9/9/9/9/9/9/4K4 w - - 0 1", Player::Red, None);
+    }
+
+    #[test]
+    fn test_lone_cannon() {
+        check(
+            "4k4/9/9/9/9/9/9/4C4/9/4K4 w - - 0 1",
+            Player::Red,
+            Some(Endgame::LoneCannon),
+        );
+        check(
+            "3ak4/9/9/9/9/9/9/4C4/9/4K4 w - - 0 1",
+            Player::Red,
+            Some(Endgame::LoneCannon),
+        );
+        // 炮士对光将、炮双士对单士都能赢
+        check("4k4/9/9/9/9/9/9/4C4/4A4/4K4 w - - 0 1", Player::Red, None);
+        check("3ak4/9/9/9/9/9/9/4C4/4A4/3AK4 w - - 0 1", Player::Red, None);
+    }
+
+    #[test]
+    fn test_lone_knight() {
+        check(
+            "2bakab2/9/9/9/9/9/9/4N4/9/4K4 w - - 0 1",
+            Player::Red,
+            Some(Endgame::LoneKnight),
+        );
+        check("4k4/9/9/9/9/9/9/4N4/9/4K4 w - - 0 1", Player::Red, None);
+    }
+
+    #[test]
+    fn test_rook_vs_defended() {
+        check(
+            "2bakab2/9/4n4/9/9/9/9/9/9/4K3R w - - 0 1",
+            Player::Red,
+            Some(Endgame::RookVsDefended),
+        );
+        check(
+            "2bakab2/9/4c4/9/9/9/9/9/9/4K3R w - - 0 1",
+            Player::Red,
+            Some(Endgame::RookVsDefended),
+        );
+        // 单车胜马双士、炮双士
+        check("3akn3/4a4/9/9/9/9/9/9/9/4K3R w - - 0 1", Player::Red, None);
+        check("3akc3/4a4/9/9/9/9/9/9/9/4K3R w - - 0 1", Player::Red, None);
+    }
+
+    #[test]
+    fn test_equal_attackers() {
+        check(
+            "2bk5/9/9/9/9/9/9/9/r8/3AKAB1R w - - 0 1",
+            Player::Red,
+            Some(Endgame::EqualAttackers),
+        );
+        // 车兵对车不缩放
+        check("3k5/9/9/4P4/9/9/9/9/r8/4K3R w - - 0 1", Player::Red, None);
+    }
+
+    #[test]
+    fn test_scale_evaluate() {
+        // 单马对士象全，多一个马也是和棋
+        let board = Board::from_fen("2bakab2/9/9/9/9/9/9/4N4/9/4K4 w - - 0 1");
+        assert_eq!(board.scale_endgame(400, Player::Red), 50);
+        assert_eq!(board.scale_endgame(-400, Player::Black), -50);
+        let board = Board::from_fen("4k4/9/9/9/9/9/9/4C4/9/4K4 w - - 0 1");
+        assert_eq!(board.evaluate(Player::Red), 0);
+        assert_eq!(board.evaluate(Player::Black), 0);
+    }
+}
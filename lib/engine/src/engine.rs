@@ -1,7 +1,19 @@
-use crate::board::{Board, Move};
+use crate::board::{mate_moves, Board, Move, Player};
+use crate::constant::MAX_DEPTH;
+use crate::eval::EvalParams;
+use crate::nnue::Network;
+use crate::tablebase::Tablebase;
 use getrandom::getrandom;
 use regex::Regex;
 use std::io;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+// 只有go而没有给出深度和时间时每步的用时
+const DEFAULT_MOVE_TIME: Duration = Duration::from_secs(5);
 
 #[derive(Debug)]
 pub struct PreLoad {
@@ -11,57 +23,171 @@ pub struct PreLoad {
     weight: i32,
 }
 
+// 局面分的输出格式，杀棋输出为mate N
+pub fn score_string(value: i32) -> String {
+    match mate_moves(value) {
+        Some(n) => format!("mate {}", n),
+        None => format!("{}", value),
+    }
+}
+
+// 着法序列的输出格式，如b2e2 h9g7
+pub fn pv_string(pv: &[Move]) -> String {
+    pv.iter()
+        .map(|m| format!("{}{}", m.from.to_string(), m.to.to_string()))
+        .collect::<Vec<String>>()
+        .join(" ")
+}
+
+// go命令的搜索限制
+#[derive(Clone, Debug, PartialEq)]
+pub struct SearchLimits {
+    pub depth: i32,
+    pub time: Option<Duration>, // 这一步的用时，None为不限时
+}
+
+impl SearchLimits {
+    pub fn depth(depth: i32) -> Self {
+        SearchLimits { depth, time: None }
+    }
+    // 解析go之后的参数，支持UCCI的depth、time、increment、movestogo和UCI的wtime、btime、winc、binc、movetime，
+    // UCCI的时间默认以秒为单位，设置了usemillisec时以毫秒为单位。
+    // infinite一直搜索到stop，什么限制都没有时按默认用时搜索
+    pub fn parse(param: &str, turn: Player, millisec: bool) -> Self {
+        let ucci_unit = if millisec { 0.001 } else { 1.0 };
+        let (mut depth, mut time, mut increment, mut moves_to_go, mut move_time) = (None, None, 0.0, None, None);
+        let mut infinite = false;
+        let mut tokens = param.split_whitespace();
+        while let Some(token) = tokens.next() {
+            let mut value = || {
+                tokens
+                    .next()
+                    .and_then(|v| v.parse::<f64>().ok())
+                    .filter(|v| *v >= 0.0)
+            };
+            match token {
+                "depth" => depth = value().map(|v| v as i32),
+                "time" => time = value().map(|v| v * ucci_unit),
+                "increment" => increment = value().map_or(0.0, |v| v * ucci_unit),
+                "movestogo" => moves_to_go = value().map(|v| v as u32),
+                "movetime" => move_time = value().map(|v| v * 0.001),
+                "wtime" | "btime" => {
+                    let v = value();
+                    if (token == "wtime") == (turn == Player::Red) {
+                        time = v.map(|v| v * 0.001);
+                    }
+                }
+                "winc" | "binc" => {
+                    let v = value();
+                    if (token == "winc") == (turn == Player::Red) {
+                        increment = v.map_or(0.0, |v| v * 0.001);
+                    }
+                }
+                "opptime" | "oppincrement" => {
+                    value();
+                }
+                "infinite" => infinite = true,
+                // 兼容只给出深度的go 5
+                _ => {
+                    if let Ok(v) = token.parse::<i32>() {
+                        depth = Some(v);
+                    }
+                }
+            }
+        }
+        let time = match (move_time, time) {
+            (Some(t), _) => Some(Duration::from_secs_f64(t)),
+            (None, Some(t)) => Some(time_budget(
+                Duration::from_secs_f64(t),
+                Duration::from_secs_f64(increment),
+                moves_to_go,
+            )),
+            (None, None) if depth.is_none() && !infinite => Some(DEFAULT_MOVE_TIME),
+            (None, None) => None,
+        };
+        SearchLimits {
+            depth: depth.unwrap_or(MAX_DEPTH).clamp(1, MAX_DEPTH),
+            time,
+        }
+    }
+}
+
+// 按剩余时间分配这一步的用时，默认按还要走30步平均分配，加上大部分加秒，最多用剩余时间的一半
+pub fn time_budget(remaining: Duration, increment: Duration, moves_to_go: Option<u32>) -> Duration {
+    let moves = moves_to_go.unwrap_or(30).max(1);
+    (remaining / moves + increment * 3 / 4).min(remaining / 2)
+}
+
 // UCCI引擎
 pub struct UCCIEngine {
     pub board: Board,
     pub book: Vec<PreLoad>,
+    pub threads: usize, // 搜索线程数
+    pub multipv: usize, // 输出的变例数
+    pub eval_params: EvalParams,
+    pub network: Option<Arc<Network>>,                 // 神经网络评价用的网络
+    pub millisec: bool,                                // UCCI的时间是否以毫秒为单位
+    pub tablebase: Option<Arc<Tablebase>>,             // 残局库
+    search: Option<(Arc<AtomicBool>, JoinHandle<()>)>, // 后台搜索的停止标志和线程
+}
+
+// 读入开局库，每行为：着法 权重 FEN
+pub fn load_book(data: &str) -> Vec<PreLoad> {
+    let mut book = vec![];
+    for line in data.split("\n") {
+        if line.len() == 0 {
+            continue;
+        }
+        let mut tokens = line.splitn(3, " ");
+        let m = tokens.next().unwrap();
+        let weight = tokens.next().unwrap();
+        let fen = tokens.next().unwrap();
+        let board = Board::from_fen(fen);
+        book.push(PreLoad {
+            zobrist_value: board.zobrist_value,
+            zobrist_value_check: board.zobrist_value_lock,
+            best_move: m.to_owned(),
+            weight: weight.parse::<i32>().unwrap(),
+        });
+    }
+    book.sort_by(|a, b| a.zobrist_value.cmp(&b.zobrist_value));
+    book
+}
+
+// 在开局库中查找局面，返回ICCS格式的着法
+pub fn search_book(book: &[PreLoad], board: &Board) -> Option<String> {
+    let candidates = book
+        .binary_search_by(|probe| probe.zobrist_value.cmp(&board.zobrist_value))
+        .map(|i| &book[i])
+        .into_iter()
+        .filter(|x| x.zobrist_value_check == board.zobrist_value_lock)
+        .collect::<Vec<&PreLoad>>();
+    if candidates.len() > 0 {
+        let mut buf = [0; 4];
+        getrandom(&mut buf).unwrap();
+        let index = i32::from_be_bytes(buf) % candidates.len() as i32;
+        Some(candidates[index as usize].best_move.clone())
+    } else {
+        None
+    }
 }
 
 impl UCCIEngine {
     pub fn new(book_data: Option<&str>) -> Self {
-        let mut book = vec![];
-        if let Some(data) = book_data {
-            for line in data.split("\n") {
-                if line.len() == 0 {
-                    continue;
-                }
-                let mut tokens = line.splitn(3, " ");
-                let m = tokens.next().unwrap();
-                let weight = tokens.next().unwrap();
-                let fen = tokens.next().unwrap();
-                let board = Board::from_fen(fen);
-                book.push(PreLoad {
-                    zobrist_value: board.zobrist_value,
-                    zobrist_value_check: board.zobrist_value_lock,
-                    best_move: m.to_owned(),
-                    weight: weight.parse::<i32>().unwrap(),
-                });
-            }
-            book.sort_by(|a, b| a.zobrist_value.cmp(&b.zobrist_value));
-            println!("加载开局库完成，共加载{}个局面", book.len());
-            println!("{:?}", book[1000]);
-        }
         UCCIEngine {
             board: Board::init(false, false),
-            book,
+            book: book_data.map_or(vec![], load_book),
+            threads: 1,
+            multipv: 1,
+            eval_params: EvalParams::default(),
+            network: None,
+            millisec: false,
+            tablebase: None,
+            search: None,
         }
     }
     pub fn search_in_book(&self) -> Option<String> {
-        let candidates = self
-            .book
-            .binary_search_by(|probe| probe.zobrist_value.cmp(&self.board.zobrist_value))
-            .map(|i| &self.book[i])
-            .into_iter()
-            .filter(|x| x.zobrist_value_check == self.board.zobrist_value_lock)
-            .collect::<Vec<&PreLoad>>();
-        if candidates.len() > 0 {
-            let mut buf = [0; 4];
-            getrandom(&mut buf).unwrap();
-            let index = i32::from_be_bytes(buf) % candidates.len() as i32;
-            Some(candidates[index as usize].best_move.clone())
-        } else {
-            None
-        }
+        search_book(&self.book, &self.board)
     }
 
     pub fn start(&mut self) {
@@ -70,24 +196,25 @@ impl UCCIEngine {
             io::stdin().read_line(&mut cmd).unwrap();
             cmd = cmd.replace("\n", "");
             if cmd == "quit" {
+                self.stop();
                 break;
             }
             let mut token = cmd.splitn(2, " ");
             let cmd = token.next().unwrap();
+            // 搜索期间只响应isready和stop，其他命令等搜索结束后再执行
+            if cmd != "isready" && cmd != "stop" {
+                self.wait();
+            }
             match cmd {
                 "ucci" => self.info(),
                 "isready" => self.is_ready(),
                 "position" => self.position(token.next().unwrap()),
+                "setoption" => self.set_option(token.next().unwrap_or("")),
                 "go" => {
-                    self.go(token
-                        .next()
-                        .unwrap()
-                        .split(" ")
-                        .last()
-                        .unwrap()
-                        .parse()
-                        .unwrap());
+                    let limits = SearchLimits::parse(token.next().unwrap_or(""), self.board.turn, self.millisec);
+                    self.go_in_background(limits);
                 }
+                "stop" => self.stop(),
                 _ => println!("not support"),
             }
         }
@@ -99,6 +226,13 @@ impl UCCIEngine {
         println!("id author nealian");
         println!("id user 2021-2022 www.nealian.cn");
         println!("option usemillisec type check");
+        println!("option threads type spin min 1 max 64 default 1");
+        println!("option multipv type spin min 1 max 16 default 1");
+        println!("option evalfile type string default <empty>");
+        if cfg!(feature = "nnue") {
+            println!("option nnuefile type string default <empty>");
+        }
+        println!("option tablebasedir type string default <empty>");
         println!("ucciok");
     }
 
@@ -106,6 +240,60 @@ impl UCCIEngine {
         println!("readyok");
     }
 
+    // 同时支持UCCI的setoption <name> <value>和UCI的setoption name <name> value <value>
+    pub fn set_option(&mut self, param: &str) {
+        let regex = Regex::new(r#"^(?:name )?(?P<name>\w+)(?: value)? (?P<value>\S+)$"#).unwrap();
+        if let Some(captures) = regex.captures(param) {
+            let value = &captures["value"];
+            match captures["name"].to_lowercase().as_str() {
+                "threads" => match value.parse::<usize>() {
+                    Ok(threads) if threads >= 1 => self.threads = threads,
+                    _ => println!("invalid option value {}", value),
+                },
+                "usemillisec" => self.millisec = value == "true",
+                "multipv" => match value.parse::<usize>() {
+                    Ok(multipv) if multipv >= 1 => self.multipv = multipv,
+                    _ => println!("invalid option value {}", value),
+                },
+                "evalfile" => match EvalParams::load(value) {
+                    Ok(params) => self.set_eval_params(params),
+                    Err(e) => println!("invalid eval file {}", e),
+                },
+                #[cfg(not(feature = "nnue"))]
+                "nnuefile" => println!("nnue is not compiled in"),
+                #[cfg(feature = "nnue")]
+                "nnuefile" => match Network::load(value) {
+                    Ok(network) => {
+                        self.network = Some(Arc::new(network));
+                        self.board.set_network(self.network.clone());
+                    }
+                    Err(e) => println!("invalid nnue file {}", e),
+                },
+                // 残局库目录，表在用到时才读入
+                "tablebasedir" => {
+                    self.tablebase = Some(Arc::new(Tablebase::new(Some(PathBuf::from(value)))));
+                    self.board.set_tablebase(self.tablebase.clone());
+                }
+                // 单个评价参数，如setoption empty_cannon_mg 40
+                name => {
+                    let mut params = self.eval_params.clone();
+                    match value.parse::<i32>() {
+                        Ok(v) if params.set(name, v) => self.set_eval_params(params),
+                        Ok(_) => println!("not support"),
+                        Err(_) => println!("invalid option value {}", value),
+                    }
+                }
+            }
+        } else {
+            println!("not support");
+        }
+    }
+
+    pub fn set_eval_params(&mut self, params: EvalParams) {
+        self.board.eval_params = params.clone();
+        self.eval_params = params;
+    }
+
     pub fn position(&mut self, param: &str) {
         let regex = Regex::new(
             r#"^(?:fen (?P<fen>[kabnrcpKABNRCP1-9/]+ [wrb] - - \d+ \d+)|(?P<startpos>startpos))(?: moves (?P<moves>[a-i]\d[a-i]\d(?: [a-i]\d[a-i]\d)*))?$"#,
@@ -133,27 +321,115 @@ impl UCCIEngine {
                 }
             }
         }
+        self.board.eval_params = self.eval_params.clone();
+        self.board.set_network(self.network.clone());
+        self.board.set_tablebase(self.tablebase.clone());
     }
 
     pub fn go(&mut self, depth: i32) {
+        self.go_with(&SearchLimits::depth(depth));
+    }
+    pub fn go_with(&mut self, limits: &SearchLimits) {
+        if !self.known_move() {
+            search(&mut self.board, self.threads, self.multipv, limits);
+        }
+    }
+    // 在后台线程中搜索，搜索期间可以用stop停止
+    pub fn go_in_background(&mut self, limits: SearchLimits) {
+        if self.known_move() {
+            return;
+        }
+        let stop = Arc::new(AtomicBool::new(false));
+        let mut board = self.board.clone();
+        board.stop = stop.clone();
+        let (threads, multipv) = (self.threads, self.multipv);
+        let handle = thread::spawn(move || search(&mut board, threads, multipv, &limits));
+        self.search = Some((stop, handle));
+    }
+    // 停止后台搜索，搜索线程输出已经搜完的结果
+    pub fn stop(&mut self) {
+        if let Some((stop, _)) = &self.search {
+            stop.store(true, Ordering::Relaxed);
+        }
+        self.wait();
+    }
+    // 等待后台搜索结束
+    pub fn wait(&mut self) {
+        if let Some((_, handle)) = self.search.take() {
+            handle.join().unwrap();
+        }
+    }
+    // 开局库和残局库中的局面直接走棋，返回是否已经输出了着法
+    fn known_move(&mut self) -> bool {
         if let Some(m) = self.search_in_book() {
             println!("bestmove {}", m);
-            return;
+            return true;
         }
-        let (value, best_move) = self.board.iterative_deepening(depth);
-        if let Some(m) = best_move {
-            if m.is_valid() {
-                println!("bestmove {}{} value {}", m.from.to_string(), m.to.to_string(), value);
-                return;
-            }
+        // 残局库中的局面直接走最优的着法
+        if let Some((m, outcome)) = self.board.tablebase_move() {
+            let value = outcome.score(0);
+            println!(
+                "info depth 0 score {} pv {}{}",
+                score_string(value),
+                m.from.to_string(),
+                m.to.to_string()
+            );
+            println!("bestmove {}{} value {}", m.from.to_string(), m.to.to_string(), value);
+            return true;
         }
-        println!("nobestmove");
+        false
     }
     pub fn quit() {
         println!("bye");
     }
 }
 
+// 按限制搜索，每搜完一层输出info，最后输出bestmove
+fn search(board: &mut Board, threads: usize, multipv: usize, limits: &SearchLimits) {
+    if multipv > 1 {
+        search_multipv(board, multipv, limits);
+        return;
+    }
+    let (value, best_move) = board.timed_search_with(limits.depth, threads, limits.time, |result| {
+        println!(
+            "info depth {} score {} nodes {} pv {}",
+            result.depth,
+            score_string(result.value),
+            result.nodes,
+            pv_string(&result.pv)
+        );
+    });
+    match best_move {
+        Some(m) if m.is_valid() => {
+            println!("bestmove {}{} value {}", m.from.to_string(), m.to.to_string(), value)
+        }
+        _ => println!("nobestmove"),
+    }
+}
+
+// 多PV分析，每层的每条变例输出一行info
+fn search_multipv(board: &mut Board, n: usize, limits: &SearchLimits) {
+    let lines = board.with_time_limit(limits.time, |board| {
+        board.search_multipv_with(limits.depth, n, |depth, lines| {
+            for (i, (value, pv)) in lines.iter().enumerate() {
+                println!(
+                    "info depth {} multipv {} score {} pv {}",
+                    depth,
+                    i + 1,
+                    score_string(*value),
+                    pv_string(pv)
+                );
+            }
+        })
+    });
+    match lines.first() {
+        Some((_, pv)) if !pv.is_empty() => {
+            println!("bestmove {}{}", pv[0].from.to_string(), pv[0].to.to_string())
+        }
+        _ => println!("nobestmove"),
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use crate::engine::UCCIEngine;
@@ -185,4 +461,143 @@ mod tests {
         engine.go(8);
         println!("{} {}", engine.board.gen_counter, engine.board.counter);
     }
+
+    #[test]
+    fn test_set_option() {
+        let mut engine = UCCIEngine::new(None);
+        engine.set_option("threads 4");
+        assert_eq!(engine.threads, 4);
+        engine.set_option("name Threads value 2");
+        assert_eq!(engine.threads, 2);
+        engine.set_option("threads 0");
+        assert_eq!(engine.threads, 2);
+        engine.set_option("name MultiPV value 3");
+        assert_eq!(engine.multipv, 3);
+        engine.position("startpos");
+        engine.go(4);
+    }
+
+    #[test]
+    fn test_set_eval_option() {
+        let mut engine = UCCIEngine::new(None);
+        engine.set_option("name empty_cannon_mg value 45");
+        engine.set_option("initiative_bonus 7");
+        engine.set_option("initiative_bonus x");
+        assert_eq!(engine.eval_params.empty_cannon.mg, 45);
+        assert_eq!(engine.eval_params.initiative_bonus, 7);
+        // 换局面后参数仍然有效
+        engine.position("startpos");
+        assert_eq!(engine.board.eval_params, engine.eval_params);
+
+        let path = std::env::temp_dir().join("chchess_eval_option.toml");
+        let mut params = engine.eval_params.clone();
+        params.initiative_bonus = 1;
+        params.save(&path).unwrap();
+        engine.set_option(&format!("name EvalFile value {}", path.display()));
+        assert_eq!(engine.eval_params, params);
+        assert_eq!(engine.board.eval_params, params);
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    #[cfg(feature = "nnue")]
+    fn test_set_nnue_option() {
+        use crate::nnue::Trainer;
+
+        let mut engine = UCCIEngine::new(None);
+        let path = std::env::temp_dir().join("chchess_nnue_option.bin");
+        Trainer::new(8, 0).quantize().save(&path).unwrap();
+        engine.set_option(&format!("name NnueFile value {}", path.display()));
+        assert!(engine.network.is_some());
+        // 换局面后网络仍然加载着
+        engine.position("startpos moves h2e2");
+        assert!(engine
+            .board
+            .evaluate_nnue(engine.board.turn)
+            .is_some());
+        engine.go(2);
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_set_tablebase_option() {
+        use crate::tablebase::{Material, Tablebase};
+
+        let dir = std::env::temp_dir().join(format!("chchess_tablebase_option_{}", std::process::id()));
+        Tablebase::new(Some(dir.clone()))
+            .generate(&Material::parse("KRvK").unwrap(), 1, &mut |_| {})
+            .unwrap();
+        let mut engine = UCCIEngine::new(None);
+        engine.set_option(&format!("tablebasedir {}", dir.display()));
+        engine.position("fen 4k4/9/9/9/9/9/9/9/9/3K4R w - - 0 1");
+        assert!(engine.board.probe_tablebase().is_some());
+        engine.go(2);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_score_string() {
+        use crate::board::mated_value;
+        use crate::engine::score_string;
+
+        assert_eq!(score_string(35), "35");
+        assert_eq!(score_string(-mated_value(1)), "mate 1");
+        assert_eq!(score_string(-mated_value(3)), "mate 2");
+        assert_eq!(score_string(mated_value(2)), "mate -1");
+    }
+
+    #[test]
+    fn test_search_limits() {
+        use crate::board::Player;
+        use crate::constant::MAX_DEPTH;
+        use crate::engine::{SearchLimits, DEFAULT_MOVE_TIME};
+        use std::time::Duration;
+
+        assert_eq!(
+            SearchLimits::parse("depth 5", Player::Red, false),
+            SearchLimits::depth(5)
+        );
+        assert_eq!(SearchLimits::parse("5", Player::Red, false), SearchLimits::depth(5));
+        let limits = SearchLimits::parse("movetime 200", Player::Black, false);
+        assert_eq!(limits.time, Some(Duration::from_millis(200)));
+        // UCCI的time默认以秒为单位
+        let seconds = SearchLimits::parse("time 60 increment 1", Player::Red, false);
+        let millis = SearchLimits::parse("time 60000 increment 1000", Player::Red, true);
+        assert_eq!(seconds.time, millis.time);
+        assert!(seconds.time.unwrap() < Duration::from_secs(30));
+        // UCI按走棋方取时间
+        let red = SearchLimits::parse("wtime 1000 btime 90000", Player::Red, false);
+        let black = SearchLimits::parse("wtime 1000 btime 90000", Player::Black, false);
+        assert!(red.time < black.time);
+        // 只有go时按默认用时，go infinite不限时
+        assert_eq!(
+            SearchLimits::parse("", Player::Red, false).time,
+            Some(DEFAULT_MOVE_TIME)
+        );
+        assert_eq!(
+            SearchLimits::parse("infinite", Player::Red, false),
+            SearchLimits::depth(MAX_DEPTH)
+        );
+        // 设置usemillisec后按毫秒限时
+        let mut engine = UCCIEngine::new(None);
+        engine.set_option("usemillisec true");
+        assert!(engine.millisec);
+        engine.position("startpos");
+        engine.go_with(&SearchLimits::parse("time 300", engine.board.turn, engine.millisec));
+    }
+
+    #[test]
+    fn test_stop_background_search() {
+        use crate::engine::SearchLimits;
+
+        let mut engine = UCCIEngine::new(None);
+        engine.position("fen rnbakabnr/9/1c5c1/p1p1p1p1p/9/9/P1P1P1P1P/1C5C1/9/RNBAKABNR w - - 0 1 moves h2e2");
+        engine.go_in_background(SearchLimits::parse("infinite", engine.board.turn, false));
+        std::thread::sleep(std::time::Duration::from_millis(100));
+        engine.stop();
+        assert!(engine.search.is_none());
+        // 没有搜索时stop不影响下一次搜索
+        engine.stop();
+        engine.go(2);
+    }
 }
@@ -0,0 +1,202 @@
+use crate::board::{mate_moves, Board, Move, RecordTable};
+use crate::constant::RECORD_SIZE;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+// 战术测试题：EPD格式的局面，bm为最佳着法（可以有多个），am为应避免的着法，dm为几步杀
+#[derive(Clone, Debug, PartialEq)]
+pub struct EpdPosition {
+    pub id: String,
+    pub fen: String,
+    pub best_moves: Vec<String>,
+    pub avoid_moves: Vec<String>,
+    pub mate: Option<i32>,
+}
+
+impl EpdPosition {
+    // 每行一题：局面 走棋方 - - 后跟以分号结束的操作，如
+    // 9/9/r2ak4/9/N8/9/9/5K3/9/7R1 w - - bm h0e0; dm 1; id "mate1-1";
+    // 着法为ICCS格式，也可以写成h0-e0。#开头的行为注释
+    pub fn parse(data: &str) -> Result<Vec<Self>, String> {
+        let mut positions = vec![];
+        for (i, line) in data.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let position = Self::parse_line(line).map_err(|e| format!("line {}: {}", i + 1, e))?;
+            positions.push(position);
+        }
+        Ok(positions)
+    }
+    fn parse_line(line: &str) -> Result<Self, String> {
+        let mut tokens = line.splitn(3, ' ');
+        let (pos, turn) = match (tokens.next(), tokens.next()) {
+            (Some(pos), Some(turn @ ("w" | "r" | "b"))) if pos.split('/').count() == 10 => (pos, turn),
+            _ => return Err(format!("invalid position {}", line)),
+        };
+        let fen = format!("{} {} - - 0 1", pos, turn);
+        let board = Board::from_fen(&fen);
+        let mut position = EpdPosition {
+            id: String::new(),
+            fen,
+            best_moves: vec![],
+            avoid_moves: vec![],
+            mate: None,
+        };
+        // 跳过操作之前的“- -”和回合数
+        let mut operations = tokens.next().unwrap_or("").trim_start();
+        while let Some((field, rest)) = operations.split_once(' ') {
+            if field != "-" && field.parse::<u32>().is_err() {
+                break;
+            }
+            operations = rest.trim_start();
+        }
+        let parse_moves = |operand: &str| -> Result<Vec<String>, String> {
+            operand
+                .split_whitespace()
+                .map(|m| {
+                    let m = m.replace('-', "");
+                    match board.parse_move(&m) {
+                        Some(_) => Ok(m),
+                        None => Err(format!("illegal move {}", m)),
+                    }
+                })
+                .collect()
+        };
+        for operation in operations.split(';') {
+            let operation = operation.trim();
+            let (opcode, operand) = operation
+                .split_once(' ')
+                .unwrap_or((operation, ""));
+            match opcode {
+                "bm" => position.best_moves = parse_moves(operand)?,
+                "am" => position.avoid_moves = parse_moves(operand)?,
+                "dm" => {
+                    position.mate = match operand.trim().parse::<i32>() {
+                        Ok(n) if n > 0 => Some(n),
+                        _ => return Err(format!("invalid dm {}", operand)),
+                    }
+                }
+                "id" => position.id = operand.trim().trim_matches('"').to_owned(),
+                // 其他操作（如注释c0）忽略
+                _ => {}
+            }
+        }
+        if position.best_moves.is_empty() && position.avoid_moves.is_empty() && position.mate.is_none() {
+            return Err("no bm, am or dm".to_owned());
+        }
+        Ok(position)
+    }
+    // 搜索结果是否满足题目要求
+    pub fn is_solution(&self, best_move: &str, value: i32) -> bool {
+        if !self.best_moves.is_empty() && !self.best_moves.iter().any(|m| m == best_move) {
+            return false;
+        }
+        if self.avoid_moves.iter().any(|m| m == best_move) {
+            return false;
+        }
+        match self.mate {
+            Some(n) => mate_moves(value).is_some_and(|moves| moves > 0 && moves <= n),
+            None => true,
+        }
+    }
+}
+
+// 一道题的搜索结果
+#[derive(Clone, Debug)]
+pub struct EpdResult {
+    pub id: String,
+    pub best_move: Option<String>,
+    pub value: i32,
+    pub depth: i32,
+    // 从哪一层开始一直满足要求：深度、用时和节点数，没有解出时为None
+    pub solved: Option<(i32, Duration, i32)>,
+}
+
+// 按深度或时间搜索一道题
+pub fn solve(position: &EpdPosition, depth: i32, time: Option<Duration>, threads: usize) -> EpdResult {
+    let mut board = Board::from_fen(&position.fen);
+    // 置换表先分配好，不计入用时
+    board.records = Arc::new(RecordTable::new(RECORD_SIZE as usize));
+    let start = Instant::now();
+    let mut solved = None;
+    let mut last_depth = 0;
+    let (value, best_move) = board.timed_search_with(depth, threads, time, |result| {
+        last_depth = result.depth;
+        if position.is_solution(&iccs(&result.best_move), result.value) {
+            solved.get_or_insert((result.depth, start.elapsed(), result.nodes));
+        } else {
+            solved = None;
+        }
+    });
+    EpdResult {
+        id: position.id.clone(),
+        best_move: best_move.map(|m| iccs(&m)),
+        value,
+        depth: last_depth,
+        solved,
+    }
+}
+
+// 依次搜索每道题，每搜完一道调用一次on_result，返回解出的题数
+pub fn run_suite(
+    positions: &[EpdPosition],
+    depth: i32,
+    time: Option<Duration>,
+    threads: usize,
+    mut on_result: impl FnMut(&EpdPosition, &EpdResult),
+) -> usize {
+    let mut solved = 0;
+    for position in positions {
+        let result = solve(position, depth, time, threads);
+        if result.solved.is_some() {
+            solved += 1;
+        }
+        on_result(position, &result);
+    }
+    solved
+}
+
+fn iccs(m: &Move) -> String {
+    format!("{}{}", m.from.to_string(), m.to.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::board::*;
+    use crate::epd::*;
+
+    #[test]
+    fn test_parse() {
+        let data = r#"
+            # 注释
+            9/9/r2ak4/9/N8/9/9/5K3/9/7R1 w - - bm h0-e0; dm 1; id "mate1-1"; c0 "comment";
+            1N2k4/9/b8/4Pr3/6C2/8r/9/3AK4/9/9 w - - 0 1 am b9a7;
+        "#;
+        let positions = EpdPosition::parse(data).unwrap();
+        assert_eq!(positions.len(), 2);
+        assert_eq!(positions[0].id, "mate1-1");
+        assert_eq!(positions[0].best_moves, vec!["h0e0"]);
+        assert_eq!(positions[0].mate, Some(1));
+        assert_eq!(positions[1].avoid_moves, vec!["b9a7"]);
+        assert_eq!(positions[1].fen, "1N2k4/9/b8/4Pr3/6C2/8r/9/3AK4/9/9 w - - 0 1");
+        assert!(positions[0].is_solution("h0e0", -mated_value(1)));
+        assert!(!positions[0].is_solution("h0e0", 300));
+        assert!(!positions[1].is_solution("b9a7", 0));
+        assert!(EpdPosition::parse("9/9/r2ak4/9/N8/9/9/5K3/9/7R1 w - - bm a5a9;").is_err());
+        assert!(EpdPosition::parse("9/9/r2ak4/9/N8/9/9/5K3/9/7R1 w - -").is_err());
+    }
+
+    #[test]
+    fn test_suite() {
+        let positions = EpdPosition::parse(include_str!("../TACTICS.EPD")).unwrap();
+        let mut unsolved = vec![];
+        let solved = run_suite(&positions, 7, None, 1, |_, result| {
+            if result.solved.is_none() {
+                unsolved.push(result.id.clone());
+            }
+        });
+        assert_eq!(solved, positions.len(), "unsolved: {:?}", unsolved);
+    }
+}
@@ -0,0 +1,748 @@
+use crate::board::{in_board, in_country, Board, ChessType, Player, Position, BOARD_HEIGHT, BOARD_WIDTH};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::ops::{Add, AddAssign, Mul, Sub};
+use std::path::Path;
+
+// 开局时的阶段值，阶段值为0时只剩没有进攻子力的残局
+pub const MAX_PHASE: i32 = 32;
+
+// 中局分和残局分
+#[derive(Clone, Copy, Default, PartialEq, Debug)]
+pub struct Score {
+    pub mg: i32,
+    pub eg: i32,
+}
+
+impl Score {
+    pub fn new(mg: i32, eg: i32) -> Self {
+        Score { mg, eg }
+    }
+    // 按阶段在中局分和残局分之间插值
+    pub fn taper(&self, phase: i32) -> i32 {
+        let phase = phase.clamp(0, MAX_PHASE);
+        (self.mg * phase + self.eg * (MAX_PHASE - phase)) / MAX_PHASE
+    }
+}
+
+impl Add for Score {
+    type Output = Score;
+    fn add(self, rhs: Score) -> Score {
+        Score::new(self.mg + rhs.mg, self.eg + rhs.eg)
+    }
+}
+
+impl AddAssign for Score {
+    fn add_assign(&mut self, rhs: Score) {
+        *self = *self + rhs;
+    }
+}
+
+impl Sub for Score {
+    type Output = Score;
+    fn sub(self, rhs: Score) -> Score {
+        Score::new(self.mg - rhs.mg, self.eg - rhs.eg)
+    }
+}
+
+impl Mul<i32> for Score {
+    type Output = Score;
+    fn mul(self, rhs: i32) -> Score {
+        Score::new(self.mg * rhs, self.eg * rhs)
+    }
+}
+
+// 子力位置分表，按红方视角，黑方的棋子查表前先翻转位置
+pub type ValueTable = [[i32; BOARD_WIDTH as usize]; BOARD_HEIGHT as usize];
+
+// 一种棋子的中局和残局子力位置分表
+#[derive(Clone, Copy, PartialEq, Debug, Serialize, Deserialize)]
+pub struct PieceTables {
+    pub mg: ValueTable,
+    pub eg: ValueTable,
+}
+
+impl PieceTables {
+    pub fn new(mg: ValueTable, eg: ValueTable) -> Self {
+        PieceTables { mg, eg }
+    }
+}
+
+// 一个评价项：是否启用以及中局、残局的权重
+#[derive(Clone, Copy, PartialEq, Debug, Serialize, Deserialize)]
+pub struct EvalTerm {
+    #[serde(default = "enabled_by_default")]
+    pub enabled: bool,
+    pub mg: i32,
+    pub eg: i32,
+}
+
+fn enabled_by_default() -> bool {
+    true
+}
+
+impl EvalTerm {
+    pub fn new(mg: i32, eg: i32) -> Self {
+        EvalTerm { enabled: true, mg, eg }
+    }
+    fn score(&self, count: i32) -> Score {
+        if self.enabled {
+            Score::new(self.mg, self.eg) * count
+        } else {
+            Score::default()
+        }
+    }
+}
+
+// 评价参数，可以从TOML或JSON文件读入，文件中没有给出的参数取默认值
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct EvalParams {
+    pub initiative_bonus: i32, // 先行之利
+    pub king: PieceTables,
+    pub advisor: PieceTables,
+    pub bishop: PieceTables,
+    pub knight: PieceTables,
+    pub rook: PieceTables,
+    pub cannon: PieceTables,
+    pub pawn: PieceTables,
+    pub rook_mobility: EvalTerm,      // 车的活动范围，每个可走的位置
+    pub knight_mobility: EvalTerm,    // 马的活动范围，每个可走的位置
+    pub knight_blocked_leg: EvalTerm, // 蹩马腿，每条被堵住的马腿扣分
+    pub cannon_screen: EvalTerm,      // 炮与对方将之间隔一个子（炮架）
+    pub empty_cannon: EvalTerm,       // 空头炮，炮与对方将之间没有子
+    pub king_exposure: EvalTerm,      // 将离开底线，每离开一行扣分
+    pub missing_advisor: EvalTerm,    // 缺士，按对方进攻子力数量扣分
+    pub missing_bishop: EvalTerm,     // 缺相，按对方进攻子力数量扣分
+    pub connected_pawns: EvalTerm,    // 过河兵左右相连
+    pub central_file: EvalTerm,       // 车、炮、过河兵占中路
+}
+
+impl Default for EvalParams {
+    fn default() -> Self {
+        EvalParams {
+            initiative_bonus: INITIATIVE_BONUS,
+            king: PieceTables::new(KING_VALUE_TABLE, KING_ENDGAME_TABLE),
+            advisor: PieceTables::new(ADVISOR_VALUE_TABLE, ADVISOR_ENDGAME_TABLE),
+            bishop: PieceTables::new(BISHOP_VALUE_TABLE, BISHOP_ENDGAME_TABLE),
+            knight: PieceTables::new(KNIGHT_VALUE_TABLE, KNIGHT_ENDGAME_TABLE),
+            rook: PieceTables::new(ROOK_VALUE_TABLE, ROOK_ENDGAME_TABLE),
+            cannon: PieceTables::new(CANNON_VALUE_TABLE, CANNON_ENDGAME_TABLE),
+            pawn: PieceTables::new(PAWN_VALUE_TABLE, PAWN_ENDGAME_TABLE),
+            rook_mobility: EvalTerm::new(1, 2),
+            knight_mobility: EvalTerm::new(3, 4),
+            knight_blocked_leg: EvalTerm::new(4, 2),
+            cannon_screen: EvalTerm::new(10, 4),
+            empty_cannon: EvalTerm::new(30, 10),
+            king_exposure: EvalTerm::new(8, 2),
+            missing_advisor: EvalTerm::new(3, 2),
+            missing_bishop: EvalTerm::new(2, 2),
+            connected_pawns: EvalTerm::new(6, 12),
+            central_file: EvalTerm::new(5, 2),
+        }
+    }
+}
+
+impl EvalParams {
+    // 关闭所有评价项，只剩子力位置分
+    pub fn disabled() -> Self {
+        let mut params = EvalParams::default();
+        for (_, term) in params.terms_mut() {
+            term.enabled = false;
+        }
+        params
+    }
+    pub fn terms_mut(&mut self) -> [(&'static str, &mut EvalTerm); 10] {
+        [
+            ("rook_mobility", &mut self.rook_mobility),
+            ("knight_mobility", &mut self.knight_mobility),
+            ("knight_blocked_leg", &mut self.knight_blocked_leg),
+            ("cannon_screen", &mut self.cannon_screen),
+            ("empty_cannon", &mut self.empty_cannon),
+            ("king_exposure", &mut self.king_exposure),
+            ("missing_advisor", &mut self.missing_advisor),
+            ("missing_bishop", &mut self.missing_bishop),
+            ("connected_pawns", &mut self.connected_pawns),
+            ("central_file", &mut self.central_file),
+        ]
+    }
+    pub fn table(&self, ct: ChessType) -> &PieceTables {
+        match ct {
+            ChessType::King => &self.king,
+            ChessType::Advisor => &self.advisor,
+            ChessType::Bishop => &self.bishop,
+            ChessType::Knight => &self.knight,
+            ChessType::Rook => &self.rook,
+            ChessType::Cannon => &self.cannon,
+            ChessType::Pawn => &self.pawn,
+        }
+    }
+    pub fn table_mut(&mut self, ct: ChessType) -> &mut PieceTables {
+        match ct {
+            ChessType::King => &mut self.king,
+            ChessType::Advisor => &mut self.advisor,
+            ChessType::Bishop => &mut self.bishop,
+            ChessType::Knight => &mut self.knight,
+            ChessType::Rook => &mut self.rook,
+            ChessType::Cannon => &mut self.cannon,
+            ChessType::Pawn => &mut self.pawn,
+        }
+    }
+    // 按名字设置一个参数，如initiative_bonus、empty_cannon_mg、empty_cannon_eg，
+    // 名字不存在时返回false
+    pub fn set(&mut self, name: &str, value: i32) -> bool {
+        if name == "initiative_bonus" {
+            self.initiative_bonus = value;
+            return true;
+        }
+        for (term_name, term) in self.terms_mut() {
+            if let Some(phase) = name
+                .strip_prefix(term_name)
+                .and_then(|rest| rest.strip_prefix('_'))
+            {
+                match phase {
+                    "mg" => term.mg = value,
+                    "eg" => term.eg = value,
+                    _ => return false,
+                }
+                return true;
+            }
+        }
+        false
+    }
+
+    pub fn from_toml(s: &str) -> Result<Self, String> {
+        toml::from_str(s).map_err(|e| e.to_string())
+    }
+    pub fn from_json(s: &str) -> Result<Self, String> {
+        serde_json::from_str(s).map_err(|e| e.to_string())
+    }
+    pub fn to_toml(&self) -> String {
+        toml::to_string(self).unwrap()
+    }
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).unwrap()
+    }
+    // 按扩展名选择格式，.json为JSON，其他为TOML
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, String> {
+        let path = path.as_ref();
+        let s = fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        if is_json(path) {
+            Self::from_json(&s)
+        } else {
+            Self::from_toml(&s)
+        }
+    }
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), String> {
+        let path = path.as_ref();
+        let s = if is_json(path) { self.to_json() } else { self.to_toml() };
+        fs::write(path, s).map_err(|e| format!("{}: {}", path.display(), e))
+    }
+}
+
+fn is_json(path: &Path) -> bool {
+    path.extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("json"))
+}
+
+// 默认的中局子力位置分表
+const KING_VALUE_TABLE: ValueTable = [
+    [0, 0, 0, 0, 0, 0, 0, 0, 0],
+    [0, 0, 0, 0, 0, 0, 0, 0, 0],
+    [0, 0, 0, 0, 0, 0, 0, 0, 0],
+    [0, 0, 0, 0, 0, 0, 0, 0, 0],
+    [0, 0, 0, 0, 0, 0, 0, 0, 0],
+    [0, 0, 0, 0, 0, 0, 0, 0, 0],
+    [0, 0, 0, 0, 0, 0, 0, 0, 0],
+    [0, 0, 0, 1, 1, 1, 0, 0, 0],
+    [0, 0, 0, 2, 2, 2, 0, 0, 0],
+    [0, 0, 0, 11, 15, 11, 0, 0, 0],
+];
+
+const ADVISOR_VALUE_TABLE: ValueTable = [
+    [0, 0, 0, 0, 0, 0, 0, 0, 0],
+    [0, 0, 0, 0, 0, 0, 0, 0, 0],
+    [0, 0, 0, 0, 0, 0, 0, 0, 0],
+    [0, 0, 0, 0, 0, 0, 0, 0, 0],
+    [0, 0, 0, 0, 0, 0, 0, 0, 0],
+    [0, 0, 0, 0, 0, 0, 0, 0, 0],
+    [0, 0, 0, 0, 0, 0, 0, 0, 0],
+    [0, 0, 0, 20, 0, 20, 0, 0, 0],
+    [0, 0, 0, 0, 23, 0, 0, 0, 0],
+    [0, 0, 0, 20, 0, 20, 0, 0, 0],
+];
+
+const BISHOP_VALUE_TABLE: ValueTable = [
+    [0, 0, 0, 0, 0, 0, 0, 0, 0],
+    [0, 0, 0, 0, 0, 0, 0, 0, 0],
+    [0, 0, 0, 0, 0, 0, 0, 0, 0],
+    [0, 0, 0, 0, 0, 0, 0, 0, 0],
+    [0, 0, 0, 0, 0, 0, 0, 0, 0],
+    [0, 0, 20, 0, 0, 0, 20, 0, 0],
+    [0, 0, 0, 0, 0, 0, 0, 0, 0],
+    [18, 0, 0, 0, 23, 0, 0, 0, 18],
+    [0, 0, 0, 0, 0, 0, 0, 0, 0],
+    [0, 0, 20, 0, 0, 0, 20, 0, 0],
+];
+
+const ROOK_VALUE_TABLE: ValueTable = [
+    [206, 208, 207, 213, 214, 213, 207, 208, 206],
+    [206, 212, 209, 216, 233, 216, 209, 212, 206],
+    [206, 208, 207, 214, 216, 214, 207, 208, 206],
+    [206, 213, 213, 216, 216, 216, 213, 213, 206],
+    [208, 211, 211, 214, 215, 214, 211, 211, 208],
+    [208, 212, 212, 214, 215, 214, 212, 212, 208],
+    [204, 209, 204, 212, 214, 212, 204, 209, 204],
+    [198, 208, 204, 212, 212, 212, 204, 208, 198],
+    [200, 208, 206, 212, 200, 212, 206, 208, 200],
+    [194, 206, 204, 212, 200, 212, 204, 206, 194],
+];
+
+const KNIGHT_VALUE_TABLE: ValueTable = [
+    [90, 90, 90, 96, 90, 96, 90, 90, 90],
+    [90, 96, 103, 97, 94, 97, 103, 96, 90],
+    [92, 98, 99, 103, 99, 103, 99, 98, 92],
+    [93, 108, 100, 107, 100, 107, 100, 108, 93],
+    [90, 100, 99, 103, 104, 103, 99, 100, 90],
+    [90, 98, 101, 102, 103, 102, 101, 98, 90],
+    [92, 94, 98, 95, 98, 95, 98, 94, 92],
+    [93, 92, 94, 95, 92, 95, 94, 92, 93],
+    [85, 90, 92, 93, 78, 93, 92, 90, 85],
+    [88, 85, 90, 88, 90, 88, 90, 85, 88],
+];
+
+const CANNON_VALUE_TABLE: ValueTable = [
+    [100, 100, 96, 91, 90, 91, 96, 100, 100],
+    [98, 98, 96, 92, 89, 92, 96, 98, 98],
+    [97, 97, 96, 91, 92, 91, 96, 97, 97],
+    [96, 99, 99, 98, 100, 98, 99, 99, 96],
+    [96, 96, 96, 96, 100, 96, 96, 96, 96],
+    [95, 96, 99, 96, 100, 96, 99, 96, 95],
+    [96, 96, 96, 96, 96, 96, 96, 96, 96],
+    [97, 96, 100, 99, 101, 99, 100, 96, 97],
+    [96, 97, 98, 98, 98, 98, 98, 97, 96],
+    [96, 96, 97, 99, 99, 99, 97, 96, 96],
+];
+
+const PAWN_VALUE_TABLE: ValueTable = [
+    [9, 9, 9, 11, 13, 11, 9, 9, 9],
+    [19, 24, 34, 42, 44, 42, 34, 24, 19],
+    [19, 24, 32, 37, 37, 37, 32, 24, 19],
+    [19, 23, 27, 29, 30, 29, 27, 23, 19],
+    [14, 18, 20, 27, 29, 27, 20, 18, 14],
+    [7, 0, 13, 0, 16, 0, 13, 0, 7],
+    [7, 0, 7, 0, 15, 0, 7, 0, 7],
+    [0, 0, 0, 0, 0, 0, 0, 0, 0],
+    [0, 0, 0, 0, 0, 0, 0, 0, 0],
+    [0, 0, 0, 0, 0, 0, 0, 0, 0],
+];
+
+// 残局的子力位置分：将更灵活，士相防守作用更大，过河兵更重要（底兵除外），
+// 炮缺少炮架价值降低，马价值略升
+const KING_ENDGAME_TABLE: ValueTable = [
+    [0, 0, 0, 0, 0, 0, 0, 0, 0],
+    [0, 0, 0, 0, 0, 0, 0, 0, 0],
+    [0, 0, 0, 0, 0, 0, 0, 0, 0],
+    [0, 0, 0, 0, 0, 0, 0, 0, 0],
+    [0, 0, 0, 0, 0, 0, 0, 0, 0],
+    [0, 0, 0, 0, 0, 0, 0, 0, 0],
+    [0, 0, 0, 0, 0, 0, 0, 0, 0],
+    [0, 0, 0, 4, 6, 4, 0, 0, 0],
+    [0, 0, 0, 5, 8, 5, 0, 0, 0],
+    [0, 0, 0, 6, 9, 6, 0, 0, 0],
+];
+
+const ADVISOR_ENDGAME_TABLE: ValueTable = [
+    [0, 0, 0, 0, 0, 0, 0, 0, 0],
+    [0, 0, 0, 0, 0, 0, 0, 0, 0],
+    [0, 0, 0, 0, 0, 0, 0, 0, 0],
+    [0, 0, 0, 0, 0, 0, 0, 0, 0],
+    [0, 0, 0, 0, 0, 0, 0, 0, 0],
+    [0, 0, 0, 0, 0, 0, 0, 0, 0],
+    [0, 0, 0, 0, 0, 0, 0, 0, 0],
+    [0, 0, 0, 28, 0, 28, 0, 0, 0],
+    [0, 0, 0, 0, 32, 0, 0, 0, 0],
+    [0, 0, 0, 30, 0, 30, 0, 0, 0],
+];
+
+const BISHOP_ENDGAME_TABLE: ValueTable = [
+    [0, 0, 0, 0, 0, 0, 0, 0, 0],
+    [0, 0, 0, 0, 0, 0, 0, 0, 0],
+    [0, 0, 0, 0, 0, 0, 0, 0, 0],
+    [0, 0, 0, 0, 0, 0, 0, 0, 0],
+    [0, 0, 0, 0, 0, 0, 0, 0, 0],
+    [0, 0, 26, 0, 0, 0, 26, 0, 0],
+    [0, 0, 0, 0, 0, 0, 0, 0, 0],
+    [25, 0, 0, 0, 32, 0, 0, 0, 25],
+    [0, 0, 0, 0, 0, 0, 0, 0, 0],
+    [0, 0, 28, 0, 0, 0, 28, 0, 0],
+];
+
+const ROOK_ENDGAME_TABLE: ValueTable = [
+    [213, 214, 213, 216, 217, 216, 213, 214, 213],
+    [213, 216, 214, 218, 226, 218, 214, 216, 213],
+    [213, 214, 213, 217, 218, 217, 213, 214, 213],
+    [213, 216, 216, 218, 218, 218, 216, 216, 213],
+    [214, 215, 215, 217, 217, 217, 215, 215, 214],
+    [214, 216, 216, 217, 217, 217, 216, 216, 214],
+    [212, 214, 212, 216, 217, 216, 212, 214, 212],
+    [209, 214, 212, 216, 216, 216, 212, 214, 209],
+    [210, 214, 213, 216, 210, 216, 213, 214, 210],
+    [207, 213, 212, 216, 210, 216, 212, 213, 207],
+];
+
+const KNIGHT_ENDGAME_TABLE: ValueTable = [
+    [95, 95, 95, 101, 95, 101, 95, 95, 95],
+    [95, 101, 108, 102, 99, 102, 108, 101, 95],
+    [97, 103, 104, 108, 104, 108, 104, 103, 97],
+    [98, 113, 105, 112, 105, 112, 105, 113, 98],
+    [95, 105, 104, 108, 109, 108, 104, 105, 95],
+    [95, 103, 106, 107, 108, 107, 106, 103, 95],
+    [97, 99, 103, 100, 103, 100, 103, 99, 97],
+    [98, 97, 99, 100, 97, 100, 99, 97, 98],
+    [90, 95, 97, 98, 83, 98, 97, 95, 90],
+    [93, 90, 95, 93, 95, 93, 95, 90, 93],
+];
+
+const CANNON_ENDGAME_TABLE: ValueTable = [
+    [92, 92, 88, 83, 82, 83, 88, 92, 92],
+    [90, 90, 88, 84, 81, 84, 88, 90, 90],
+    [89, 89, 88, 83, 84, 83, 88, 89, 89],
+    [88, 91, 91, 90, 92, 90, 91, 91, 88],
+    [88, 88, 88, 88, 92, 88, 88, 88, 88],
+    [87, 88, 91, 88, 92, 88, 91, 88, 87],
+    [88, 88, 88, 88, 88, 88, 88, 88, 88],
+    [89, 88, 92, 91, 93, 91, 92, 88, 89],
+    [88, 89, 90, 90, 90, 90, 90, 89, 88],
+    [88, 88, 89, 91, 91, 91, 89, 88, 88],
+];
+
+const PAWN_ENDGAME_TABLE: ValueTable = [
+    [10, 10, 10, 12, 14, 12, 10, 10, 10],
+    [30, 35, 45, 55, 60, 55, 45, 35, 30],
+    [30, 35, 42, 50, 52, 50, 42, 35, 30],
+    [28, 32, 36, 40, 42, 40, 36, 32, 28],
+    [22, 24, 26, 30, 32, 30, 26, 24, 22],
+    [10, 0, 12, 0, 14, 0, 12, 0, 10],
+    [10, 0, 10, 0, 14, 0, 10, 0, 10],
+    [0, 0, 0, 0, 0, 0, 0, 0, 0],
+    [0, 0, 0, 0, 0, 0, 0, 0, 0],
+    [0, 0, 0, 0, 0, 0, 0, 0, 0],
+];
+
+const INITIATIVE_BONUS: i32 = 3;
+
+// 各评价项的计数，每项是player一方的数量
+#[derive(Default, Debug, PartialEq)]
+struct FeatureCount {
+    rook_mobility: i32,
+    knight_mobility: i32,
+    knight_blocked_leg: i32,
+    cannon_screen: i32,
+    empty_cannon: i32,
+    king_exposure: i32,
+    missing_advisor: i32,
+    missing_bishop: i32,
+    connected_pawns: i32,
+    central_file: i32,
+}
+
+const CENTRAL_FILE: i32 = BOARD_WIDTH / 2;
+
+impl ChessType {
+    // 计算阶段时每种棋子的分量，士相兵和将不计入
+    pub fn phase_weight(&self) -> i32 {
+        match self {
+            ChessType::Rook => 4,
+            ChessType::Knight | ChessType::Cannon => 2,
+            _ => 0,
+        }
+    }
+}
+
+impl Board {
+    // 按双方剩余的车马炮计算阶段，开局为MAX_PHASE，越接近残局越小
+    pub fn phase(&self) -> i32 {
+        let mut phase = 0;
+        for i in 0..BOARD_HEIGHT {
+            for j in 0..BOARD_WIDTH {
+                if let Some(ct) = self.chess_at(Position::new(i, j)).chess_type() {
+                    phase += ct.phase_weight();
+                }
+            }
+        }
+        phase.min(MAX_PHASE)
+    }
+    // 两个位置之间（同一行或同一列）的棋子数
+    fn count_chess_between(&self, posa: Position, posb: Position) -> i32 {
+        let mut count = 0;
+        if posa.row == posb.row {
+            for j in posa.col.min(posb.col) + 1..posa.col.max(posb.col) {
+                if self
+                    .chess_at(Position::new(posa.row, j))
+                    .chess_type()
+                    .is_some()
+                {
+                    count += 1;
+                }
+            }
+        } else if posa.col == posb.col {
+            for i in posa.row.min(posb.row) + 1..posa.row.max(posb.row) {
+                if self
+                    .chess_at(Position::new(i, posa.col))
+                    .chess_type()
+                    .is_some()
+                {
+                    count += 1;
+                }
+            }
+        }
+        count
+    }
+    fn count_features(&self, player: Player) -> FeatureCount {
+        let mut count = FeatureCount::default();
+        let enemy_king = self.king_position(player.next());
+        let mut advisors = 0;
+        let mut bishops = 0;
+        let mut enemy_attackers = 0;
+        for i in 0..BOARD_HEIGHT {
+            for j in 0..BOARD_WIDTH {
+                let pos = Position::new(i, j);
+                let chess = self.chess_at(pos);
+                let ct = match self.move_type_at(pos) {
+                    Some(ct) => ct,
+                    None => continue,
+                };
+                if !chess.belong_to(player) {
+                    if let ChessType::Rook | ChessType::Knight | ChessType::Cannon = ct {
+                        enemy_attackers += 1;
+                    }
+                    continue;
+                }
+                match ct {
+                    ChessType::Rook => {
+                        count.rook_mobility += self
+                            .generate_move_for_chess_type(ct, pos)
+                            .into_iter()
+                            .filter(|t| in_board(*t) && !self.chess_at(*t).belong_to(player))
+                            .count() as i32;
+                        if j == CENTRAL_FILE {
+                            count.central_file += 1;
+                        }
+                    }
+                    ChessType::Knight => {
+                        count.knight_mobility += self
+                            .generate_move_for_chess_type(ct, pos)
+                            .into_iter()
+                            .filter(|t| in_board(*t) && !self.chess_at(*t).belong_to(player))
+                            .count() as i32;
+                        for leg in [pos.up(1), pos.down(1), pos.left(1), pos.right(1)] {
+                            if in_board(leg) && self.chess_at(leg).chess_type().is_some() {
+                                count.knight_blocked_leg += 1;
+                            }
+                        }
+                    }
+                    ChessType::Cannon => {
+                        if let Some(king) = enemy_king {
+                            if king.row == i || king.col == j {
+                                match self.count_chess_between(pos, king) {
+                                    0 => count.empty_cannon += 1,
+                                    1 => count.cannon_screen += 1,
+                                    _ => (),
+                                }
+                            }
+                        }
+                        if j == CENTRAL_FILE {
+                            count.central_file += 1;
+                        }
+                    }
+                    ChessType::Pawn => {
+                        if !in_country(i, player) {
+                            let connected = [pos.left(1), pos.right(1)].into_iter().any(|p| {
+                                self.chess_at(p).belong_to(player) && self.move_type_at(p) == Some(ChessType::Pawn)
+                            });
+                            if connected {
+                                count.connected_pawns += 1;
+                            }
+                            if j == CENTRAL_FILE {
+                                count.central_file += 1;
+                            }
+                        }
+                    }
+                    ChessType::Advisor => advisors += 1,
+                    ChessType::Bishop => bishops += 1,
+                    ChessType::King => {
+                        let base_row = if player == Player::Red { BOARD_HEIGHT - 1 } else { 0 };
+                        count.king_exposure += (i - base_row).abs();
+                    }
+                }
+            }
+        }
+        count.missing_advisor = (2 - advisors).max(0) * enemy_attackers;
+        count.missing_bishop = (2 - bishops).max(0) * enemy_attackers;
+        count
+    }
+    // 子力位置分之外的评价，返回player一方的中局分和残局分
+    pub fn evaluate_features(&self, player: Player) -> Score {
+        let params = &self.eval_params;
+        let side_score = |count: FeatureCount| {
+            params.rook_mobility.score(count.rook_mobility)
+                + params
+                    .knight_mobility
+                    .score(count.knight_mobility)
+                - params
+                    .knight_blocked_leg
+                    .score(count.knight_blocked_leg)
+                + params.cannon_screen.score(count.cannon_screen)
+                + params.empty_cannon.score(count.empty_cannon)
+                - params.king_exposure.score(count.king_exposure)
+                - params
+                    .missing_advisor
+                    .score(count.missing_advisor)
+                - params.missing_bishop.score(count.missing_bishop)
+                + params
+                    .connected_pawns
+                    .score(count.connected_pawns)
+                + params.central_file.score(count.central_file)
+        };
+        side_score(self.count_features(player)) - side_score(self.count_features(player.next()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::board::*;
+    use crate::eval::*;
+
+    fn only(enable: impl Fn(&mut EvalParams) -> &mut EvalTerm) -> EvalParams {
+        let mut params = EvalParams::disabled();
+        enable(&mut params).enabled = true;
+        params
+    }
+
+    #[test]
+    fn test_symmetric() {
+        let board = Board::from_fen("rnbakabnr/9/1c5c1/p1p1p1p1p/9/9/P1P1P1P1P/1C5C1/9/RNBAKABNR w - - 0 1");
+        assert_eq!(board.evaluate_features(Player::Red), Score::default());
+        assert_eq!(board.evaluate_features(Player::Black), Score::default());
+    }
+
+    #[test]
+    fn test_empty_cannon() {
+        let mut board = Board::from_fen("4k4/9/9/9/9/9/9/4C4/9/3K5 w - - 0 1");
+        board.eval_params = only(|p| &mut p.empty_cannon);
+        assert_eq!(board.evaluate_features(Player::Red), Score::new(30, 10));
+        assert_eq!(board.evaluate_features(Player::Black), Score::new(-30, -10));
+        board.eval_params = only(|p| &mut p.cannon_screen);
+        assert_eq!(board.evaluate_features(Player::Red), Score::default());
+    }
+
+    #[test]
+    fn test_cannon_screen() {
+        let mut board = Board::from_fen("4k4/9/9/9/4p4/9/9/4C4/9/3K5 w - - 0 1");
+        board.eval_params = only(|p| &mut p.cannon_screen);
+        assert_eq!(board.evaluate_features(Player::Red), Score::new(10, 4));
+        board.eval_params = only(|p| &mut p.empty_cannon);
+        assert_eq!(board.evaluate_features(Player::Red), Score::default());
+    }
+
+    #[test]
+    fn test_knight_blocked_leg() {
+        // 红马两条马腿被堵住
+        let mut board = Board::from_fen("3k5/9/9/9/9/9/4P4/3PN4/9/5K3 w - - 0 1");
+        board.eval_params = only(|p| &mut p.knight_blocked_leg);
+        assert_eq!(board.evaluate_features(Player::Red), Score::new(-8, -4));
+        board.eval_params = only(|p| &mut p.knight_mobility);
+        assert_eq!(board.evaluate_features(Player::Red), Score::new(3, 4) * 3);
+    }
+
+    #[test]
+    fn test_connected_pawns() {
+        let mut board = Board::from_fen("3k5/9/9/3PP4/9/9/9/9/9/5K3 w - - 0 1");
+        board.eval_params = only(|p| &mut p.connected_pawns);
+        assert_eq!(board.evaluate_features(Player::Red), Score::new(12, 24));
+        // 没过河的兵不算
+        let mut board = Board::from_fen("3k5/9/9/9/9/3PP4/9/9/9/5K3 w - - 0 1");
+        board.eval_params = only(|p| &mut p.connected_pawns);
+        assert_eq!(board.evaluate_features(Player::Red), Score::default());
+    }
+
+    #[test]
+    fn test_missing_advisor() {
+        // 黑方缺两个士，红方有一车一马
+        let mut board = Board::from_fen("3k5/9/9/9/9/9/9/9/3A1A3/RN2K4 w - - 0 1");
+        board.eval_params = only(|p| &mut p.missing_advisor);
+        assert_eq!(board.evaluate_features(Player::Red), Score::new(3, 2) * 4);
+    }
+
+    #[test]
+    fn test_king_exposure() {
+        let mut board = Board::from_fen("9/3k5/9/9/9/9/9/9/9/5K3 w - - 0 1");
+        board.eval_params = only(|p| &mut p.king_exposure);
+        assert_eq!(board.evaluate_features(Player::Red), Score::new(8, 2));
+    }
+
+    #[test]
+    fn test_phase() {
+        let board = Board::from_fen("rnbakabnr/9/1c5c1/p1p1p1p1p/9/9/P1P1P1P1P/1C5C1/9/RNBAKABNR w - - 0 1");
+        assert_eq!(board.phase(), MAX_PHASE);
+        // 双车一炮
+        let board = Board::from_fen("3k5/9/9/9/9/9/9/4C4/9/R2K4r w - - 0 1");
+        assert_eq!(board.phase(), 10);
+        let board = Board::from_fen("3k5/9/9/3PP4/9/9/9/9/9/5K3 w - - 0 1");
+        assert_eq!(board.phase(), 0);
+    }
+
+    #[test]
+    fn test_taper() {
+        let score = Score::new(100, 20);
+        assert_eq!(score.taper(MAX_PHASE), 100);
+        assert_eq!(score.taper(0), 20);
+        assert_eq!(score.taper(MAX_PHASE / 2), 60);
+        assert_eq!(score.taper(MAX_PHASE + 10), 100);
+    }
+
+    #[test]
+    fn test_endgame_table() {
+        // 只剩将和兵时完全按残局的子力位置分计算
+        let mut board = Board::from_fen("3k5/9/9/4P4/9/9/9/9/9/5K3 w - - 0 1");
+        board.eval_params = EvalParams::disabled();
+        assert_eq!(board.phase(), 0);
+        assert_eq!(board.evaluate(Player::Red), 42 + 3);
+    }
+
+    #[test]
+    fn test_params_round_trip() {
+        let mut params = EvalParams {
+            initiative_bonus: 5,
+            ..Default::default()
+        };
+        params.rook.eg[0][0] = 300;
+        params.empty_cannon.enabled = false;
+        assert_eq!(EvalParams::from_toml(&params.to_toml()), Ok(params.clone()));
+        assert_eq!(EvalParams::from_json(&params.to_json()), Ok(params));
+    }
+
+    #[test]
+    fn test_params_partial() {
+        // 文件中没有的参数取默认值，评价项默认启用
+        let params = EvalParams::from_toml("initiative_bonus = 10\nempty_cannon = { mg = 50, eg = 20 }\n").unwrap();
+        assert_eq!(params.initiative_bonus, 10);
+        assert_eq!(params.empty_cannon, EvalTerm::new(50, 20));
+        assert_eq!(params.pawn, EvalParams::default().pawn);
+        let params = EvalParams::from_json(r#"{"king_exposure": {"enabled": false, "mg": 1, "eg": 1}}"#).unwrap();
+        assert!(!params.king_exposure.enabled);
+        assert!(EvalParams::from_toml("initiative_bonus = \"x\"").is_err());
+    }
+
+    #[test]
+    fn test_params_set() {
+        let mut params = EvalParams::default();
+        assert!(params.set("empty_cannon_mg", 40));
+        assert!(params.set("initiative_bonus", 0));
+        assert!(!params.set("empty_cannon", 40));
+        assert!(!params.set("unknown_mg", 40));
+        assert_eq!(params.empty_cannon, EvalTerm::new(40, 10));
+        assert_eq!(params.initiative_bonus, 0);
+    }
+}
@@ -0,0 +1,418 @@
+use crate::board::{Board, Move};
+use crate::constant::STARTPOS;
+use crate::notation::Notation;
+use crate::pgn::{PgnGame, PgnMove};
+
+// 复盘用的棋谱树：每个节点是一步棋，第一个子节点是主线，其余子节点是变着。
+// 节点按编号存放在数组中，只增不删，编号在整个对局中不变。
+// 不依赖搜索用的Board::move_history，需要局面时从开始局面按路径走出来
+
+pub type NodeId = usize;
+
+// 引擎分数在PGN注释中的写法，如{[%eval 35] 注释}
+const EVAL_PREFIX: &str = "[%eval ";
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct GameNode {
+    pub m: Option<Move>, // 根节点没有着法
+    pub parent: Option<NodeId>,
+    pub children: Vec<NodeId>,
+    pub comment: Option<String>,
+    pub nags: Vec<u8>,
+    pub eval: Option<i32>, // 引擎给出的红方视角分数
+}
+
+impl GameNode {
+    fn new(m: Option<Move>, parent: Option<NodeId>) -> Self {
+        GameNode {
+            m,
+            parent,
+            children: vec![],
+            comment: None,
+            nags: vec![],
+            eval: None,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct GameTree {
+    pub tags: Vec<(String, String)>,
+    start_fen: String,
+    nodes: Vec<GameNode>,
+    current: NodeId,
+}
+
+impl Default for GameTree {
+    fn default() -> Self {
+        Self::new(STARTPOS)
+    }
+}
+
+impl GameTree {
+    pub fn new(fen: &str) -> Self {
+        GameTree {
+            tags: vec![],
+            start_fen: fen.to_owned(),
+            nodes: vec![GameNode::new(None, None)],
+            current: 0,
+        }
+    }
+    pub fn root(&self) -> NodeId {
+        0
+    }
+    pub fn current(&self) -> NodeId {
+        self.current
+    }
+    pub fn start_fen(&self) -> &str {
+        &self.start_fen
+    }
+    pub fn len(&self) -> usize {
+        self.nodes.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.nodes.len() == 1
+    }
+    pub fn node(&self, id: NodeId) -> &GameNode {
+        &self.nodes[id]
+    }
+    pub fn node_mut(&mut self, id: NodeId) -> &mut GameNode {
+        &mut self.nodes[id]
+    }
+
+    // 从根节点到id的路径，不含根节点
+    pub fn path(&self, id: NodeId) -> Vec<NodeId> {
+        let mut path = vec![];
+        let mut id = id;
+        while let Some(parent) = self.nodes[id].parent {
+            path.push(id);
+            id = parent;
+        }
+        path.reverse();
+        path
+    }
+    pub fn ply(&self, id: NodeId) -> usize {
+        self.path(id).len()
+    }
+    // 从根节点开始的主线
+    pub fn mainline(&self) -> Vec<NodeId> {
+        let mut line = vec![];
+        let mut id = self.root();
+        while let Some(&next) = self.nodes[id].children.first() {
+            line.push(next);
+            id = next;
+        }
+        line
+    }
+    // 和id同一个父节点的着法，第一个为主线
+    pub fn siblings(&self, id: NodeId) -> &[NodeId] {
+        match self.nodes[id].parent {
+            Some(parent) => &self.nodes[parent].children,
+            None => &[],
+        }
+    }
+    pub fn start_board(&self) -> Board {
+        Board::from_fen(&self.start_fen)
+    }
+    // 走到id时的局面，move_history为路径上的着法
+    pub fn board_at(&self, id: NodeId) -> Board {
+        let mut board = self.start_board();
+        for node in self.path(id) {
+            if let Some(m) = &self.nodes[node].m {
+                board.do_move(m, false);
+            }
+        }
+        board
+    }
+    pub fn board(&self) -> Board {
+        self.board_at(self.current)
+    }
+
+    fn add_child(&mut self, parent: NodeId, m: Move) -> NodeId {
+        let id = self.nodes.len();
+        self.nodes
+            .push(GameNode::new(Some(m), Some(parent)));
+        self.nodes[parent].children.push(id);
+        id
+    }
+    // 在当前局面走一步：已有这步棋时走到那个节点，否则新增一个节点，已有后续着法时成为变着
+    pub fn add_move(&mut self, m: &Move) -> Result<NodeId, String> {
+        let existing = self.nodes[self.current]
+            .children
+            .iter()
+            .find(|id| self.nodes[**id].m.as_ref() == Some(m));
+        if let Some(&id) = existing {
+            self.current = id;
+            return Ok(id);
+        }
+        if !self.board().legal_moves().contains(m) {
+            return Err(format!("illegal move {}{}", m.from.to_string(), m.to.to_string()));
+        }
+        self.current = self.add_child(self.current, m.clone());
+        Ok(self.current)
+    }
+
+    // 沿主线前进一步
+    pub fn forward(&mut self) -> bool {
+        match self.nodes[self.current].children.first() {
+            Some(&next) => {
+                self.current = next;
+                true
+            }
+            None => false,
+        }
+    }
+    pub fn back(&mut self) -> bool {
+        match self.nodes[self.current].parent {
+            Some(parent) => {
+                self.current = parent;
+                true
+            }
+            None => false,
+        }
+    }
+    pub fn jump(&mut self, id: NodeId) -> bool {
+        if id < self.nodes.len() {
+            self.current = id;
+            true
+        } else {
+            false
+        }
+    }
+    pub fn to_start(&mut self) {
+        self.current = self.root();
+    }
+    // 沿当前节点之后的主线走到底
+    pub fn to_end(&mut self) {
+        while self.forward() {}
+    }
+    // 把id所在的变着提升为主线，路径上每一步都换到第一个
+    pub fn promote_variation(&mut self, id: NodeId) {
+        for node in self.path(id) {
+            let parent = self.nodes[node].parent.unwrap();
+            let children = &mut self.nodes[parent].children;
+            let index = children.iter().position(|c| *c == node).unwrap();
+            children[..=index].rotate_right(1);
+        }
+    }
+
+    // 转成PGN棋谱，引擎分数写在注释中
+    pub fn to_pgn_game(&self) -> PgnGame {
+        let mut game = PgnGame {
+            tags: self.tags.clone(),
+            comment: self.nodes[self.root()].comment.clone(),
+            moves: self.pgn_line(self.root()),
+        };
+        if self.start_fen != STARTPOS {
+            game.set_tag("FEN", &self.start_fen);
+        }
+        game
+    }
+    fn pgn_move(&self, id: NodeId) -> PgnMove {
+        let node = &self.nodes[id];
+        let mut pm = PgnMove::new(node.m.clone().unwrap());
+        pm.nags = node.nags.clone();
+        pm.comment = match (node.eval, &node.comment) {
+            (Some(eval), Some(comment)) => Some(format!("{}{}] {}", EVAL_PREFIX, eval, comment)),
+            (Some(eval), None) => Some(format!("{}{}]", EVAL_PREFIX, eval)),
+            (None, comment) => comment.clone(),
+        };
+        pm
+    }
+    // parent之后的主线，其余子节点作为变着挂在主线着法上
+    fn pgn_line(&self, parent: NodeId) -> Vec<PgnMove> {
+        let mut moves = vec![];
+        let mut parent = parent;
+        while let Some((&main, others)) = self.nodes[parent].children.split_first() {
+            let mut pm = self.pgn_move(main);
+            for &other in others {
+                let mut variation = vec![self.pgn_move(other)];
+                variation.extend(self.pgn_line(other));
+                pm.variations.push(variation);
+            }
+            moves.push(pm);
+            parent = main;
+        }
+        moves
+    }
+
+    pub fn from_pgn_game(game: &PgnGame) -> Self {
+        let mut tree = GameTree::new(game.tag("FEN").unwrap_or(STARTPOS));
+        tree.tags = game
+            .tags
+            .iter()
+            .filter(|(name, _)| name != "FEN")
+            .cloned()
+            .collect();
+        tree.nodes[0].comment = game.comment.clone();
+        tree.add_pgn_line(tree.root(), &game.moves);
+        tree
+    }
+    fn add_pgn_line(&mut self, parent: NodeId, moves: &[PgnMove]) {
+        let mut parent = parent;
+        for pm in moves {
+            let id = self.add_child(parent, pm.m.clone());
+            let (eval, comment) = split_eval(pm.comment.as_deref());
+            let node = &mut self.nodes[id];
+            node.nags = pm.nags.clone();
+            node.eval = eval;
+            node.comment = comment;
+            for variation in pm.variations.iter() {
+                self.add_pgn_line(parent, variation);
+            }
+            parent = id;
+        }
+    }
+
+    pub fn parse(data: &str) -> Result<Self, String> {
+        PgnGame::parse(data).map(|game| Self::from_pgn_game(&game))
+    }
+    pub fn to_pgn(&self, notation: Notation) -> String {
+        self.to_pgn_game().to_pgn(notation)
+    }
+}
+
+// 从注释中分出引擎分数
+fn split_eval(comment: Option<&str>) -> (Option<i32>, Option<String>) {
+    let comment = match comment {
+        Some(comment) => comment,
+        None => return (None, None),
+    };
+    let parsed = comment.find(EVAL_PREFIX).and_then(|start| {
+        let rest = &comment[start + EVAL_PREFIX.len()..];
+        let end = rest.find(']')?;
+        let eval = rest[..end].trim().parse().ok()?;
+        let text = format!("{}{}", &comment[..start], &rest[end + 1..]);
+        Some((eval, text.trim().to_owned()))
+    });
+    match parsed {
+        Some((eval, text)) if text.is_empty() => (Some(eval), None),
+        Some((eval, text)) => (Some(eval), Some(text)),
+        None => (None, Some(comment.to_owned())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::board::*;
+    use crate::gametree::*;
+
+    fn play(tree: &mut GameTree, iccs: &str) -> NodeId {
+        let m = tree.board().parse_move(iccs).unwrap();
+        tree.add_move(&m).unwrap()
+    }
+
+    fn iccs(tree: &GameTree, ids: &[NodeId]) -> Vec<String> {
+        ids.iter()
+            .map(|id| {
+                let m = tree.node(*id).m.as_ref().unwrap();
+                format!("{}{}", m.from.to_string(), m.to.to_string())
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_navigation() {
+        let mut tree = GameTree::default();
+        assert!(tree.is_empty());
+        assert!(!tree.back());
+        let cannon = play(&mut tree, "h2e2");
+        play(&mut tree, "h9g7");
+        play(&mut tree, "h0g2");
+        assert_eq!(tree.ply(tree.current()), 3);
+        // 退回去走另一步成为变着
+        assert!(tree.back());
+        assert!(tree.back());
+        let knight = play(&mut tree, "b9c7");
+        assert_eq!(iccs(&tree, &tree.mainline()), ["h2e2", "h9g7", "h0g2"]);
+        assert_eq!(tree.siblings(knight).len(), 2);
+        // 已有的着法不会重复添加
+        tree.back();
+        let count = tree.len();
+        play(&mut tree, "b9c7");
+        assert_eq!(tree.len(), count);
+        assert_eq!(tree.current(), knight);
+
+        tree.to_start();
+        tree.to_end();
+        assert_eq!(tree.ply(tree.current()), 3);
+        assert!(tree.jump(cannon));
+        assert!(tree.forward());
+        assert_eq!(iccs(&tree, &[tree.current()]), ["h9g7"]);
+        assert!(!tree.jump(100));
+        let board = tree.board_at(knight);
+        assert_eq!(board.move_history.len(), 2);
+        assert_eq!(board.turn, Player::Red);
+        // 不是走棋方的着法
+        let m = tree.board_at(cannon).parse_move("h9g7").unwrap();
+        tree.jump(tree.root());
+        assert!(tree.add_move(&m).is_err());
+    }
+
+    #[test]
+    fn test_promote_variation() {
+        let mut tree = GameTree::default();
+        play(&mut tree, "h2e2");
+        play(&mut tree, "h9g7");
+        tree.to_start();
+        play(&mut tree, "b2e2");
+        play(&mut tree, "b9c7");
+        tree.back();
+        let deep = play(&mut tree, "h9g7");
+        tree.promote_variation(deep);
+        assert_eq!(iccs(&tree, &tree.mainline()), ["b2e2", "h9g7"]);
+        let first = tree.mainline()[0];
+        assert_eq!(iccs(&tree, tree.siblings(first)), ["b2e2", "h2e2"]);
+        assert_eq!(iccs(&tree, tree.siblings(deep)), ["h9g7", "b9c7"]);
+    }
+
+    #[test]
+    fn test_pgn_round_trip() {
+        let mut tree = GameTree::new("3k5/9/9/9/9/9/9/9/9/4K3R w - - 0 1");
+        tree.tags
+            .push(("Event".to_owned(), "残局".to_owned()));
+        tree.node_mut(0).comment = Some("车胜将".to_owned());
+        let check = play(&mut tree, "i0i9");
+        tree.node_mut(check).eval = Some(9000);
+        tree.node_mut(check).nags.push(1);
+        play(&mut tree, "d9d8");
+        tree.back();
+        tree.back();
+        let quiet = play(&mut tree, "i0i8");
+        tree.node_mut(quiet).eval = Some(-20);
+        tree.node_mut(quiet).comment = Some("太慢".to_owned());
+
+        let text = tree.to_pgn(Notation::Iccs);
+        assert!(
+            text.contains("1. I0-I9 $1 {[%eval 9000]} (1. I0-I8 {[%eval -20] 太慢}) 1... D9-D8"),
+            "{}",
+            text
+        );
+        let parsed = GameTree::parse(&text).unwrap();
+        assert_eq!(parsed.start_fen(), tree.start_fen());
+        assert_eq!(parsed.node(0).comment, tree.node(0).comment);
+        assert_eq!(parsed.tags[0], ("Event".to_owned(), "残局".to_owned()));
+        assert_eq!(parsed.len(), tree.len());
+        for (a, b) in parsed
+            .mainline()
+            .iter()
+            .zip(tree.mainline().iter())
+        {
+            assert_eq!(parsed.node(*a).m, tree.node(*b).m);
+            assert_eq!(parsed.node(*a).eval, tree.node(*b).eval);
+            assert_eq!(parsed.node(*a).nags, tree.node(*b).nags);
+        }
+        let variation = parsed.siblings(parsed.mainline()[0])[1];
+        assert_eq!(parsed.node(variation).eval, Some(-20));
+        assert_eq!(parsed.node(variation).comment.as_deref(), Some("太慢"));
+    }
+
+    #[test]
+    fn test_split_eval() {
+        assert_eq!(split_eval(None), (None, None));
+        assert_eq!(split_eval(Some("好棋")), (None, Some("好棋".to_owned())));
+        assert_eq!(split_eval(Some("[%eval 12]")), (Some(12), None));
+        assert_eq!(split_eval(Some("好棋 [%eval -5]")), (Some(-5), Some("好棋".to_owned())));
+        assert_eq!(split_eval(Some("[%eval x]")), (None, Some("[%eval x]".to_owned())));
+    }
+}
@@ -1,4 +1,22 @@
+pub mod arena;
 pub mod board;
+pub mod cli;
 pub mod constant;
+pub mod endgame;
 pub mod engine;
+pub mod epd;
+pub mod eval;
+pub mod gametree;
+pub mod movesort;
+pub mod multipv;
+pub mod nnue;
+pub mod notation;
+pub mod pgn;
+pub mod rules;
+pub mod see;
+pub mod selfplay;
+pub mod smp;
+pub mod tablebase;
+pub mod tune;
+pub mod xqf;
 pub mod zobrist;
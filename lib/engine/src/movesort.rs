@@ -0,0 +1,290 @@
+use crate::board::{Board, Move, BOARD_HEIGHT, BOARD_WIDTH};
+use crate::constant::MAX_DEPTH;
+
+const SQUARES: usize = (BOARD_WIDTH * BOARD_HEIGHT) as usize;
+// 历史表分数上限，超过后整体减半，避免溢出
+const HISTORY_LIMIT: i32 = 1 << 20;
+
+fn square(m: &Move) -> (usize, usize) {
+    (
+        (m.from.row * BOARD_WIDTH + m.from.col) as usize,
+        (m.to.row * BOARD_WIDTH + m.to.col) as usize,
+    )
+}
+
+// 搜索过程中积累的着法排序信息
+#[derive(Clone, Debug)]
+pub struct Heuristics {
+    // 杀手着法，每层保存两个
+    killers: Vec<[Option<Move>; 2]>,
+    // 历史表，按行棋方、起点、终点记录
+    history: Vec<i32>,
+    // 反击着法表，按对方上一步的起点、终点记录
+    counters: Vec<Option<Move>>,
+}
+
+impl Default for Heuristics {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Heuristics {
+    pub fn new() -> Self {
+        Heuristics {
+            killers: vec![[None, None]; MAX_DEPTH as usize + 1],
+            history: vec![0; 2 * SQUARES * SQUARES],
+            counters: vec![None; 2 * SQUARES * SQUARES],
+        }
+    }
+    // 新的一次搜索开始时调用，杀手着法清空，历史表减半保留
+    pub fn new_search(&mut self) {
+        for killer in self.killers.iter_mut() {
+            *killer = [None, None];
+        }
+        for h in self.history.iter_mut() {
+            *h /= 2;
+        }
+    }
+    pub fn killers(&self, ply: i32) -> [Option<Move>; 2] {
+        match self.killers.get(ply as usize) {
+            Some(killer) => killer.clone(),
+            None => [None, None],
+        }
+    }
+    pub fn history_score(&self, m: &Move) -> i32 {
+        let (from, to) = square(m);
+        self.history[(m.player.value() as usize * SQUARES + from) * SQUARES + to]
+    }
+    pub fn counter_move(&self, prev: Option<&Move>) -> Option<Move> {
+        let prev = prev?;
+        if !prev.is_valid() {
+            return None;
+        }
+        let (from, to) = square(prev);
+        self.counters[(prev.player.value() as usize * SQUARES + from) * SQUARES + to].clone()
+    }
+    // 非吃子着法产生截断时，更新杀手着法、历史表和反击着法
+    pub fn update(&mut self, m: &Move, prev: Option<&Move>, depth: i32, ply: i32) {
+        if let Some(killer) = self.killers.get_mut(ply as usize) {
+            if killer[0].as_ref() != Some(m) {
+                killer[1] = killer[0].take();
+                killer[0] = Some(m.clone());
+            }
+        }
+
+        let (from, to) = square(m);
+        let index = (m.player.value() as usize * SQUARES + from) * SQUARES + to;
+        self.history[index] += depth * depth;
+        if self.history[index] > HISTORY_LIMIT {
+            for h in self.history.iter_mut() {
+                *h /= 2;
+            }
+        }
+
+        if let Some(prev) = prev {
+            if prev.is_valid() {
+                let (from, to) = square(prev);
+                self.counters[(prev.player.value() as usize * SQUARES + from) * SQUARES + to] = Some(m.clone());
+            }
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum MoveStage {
+    HashMove,    // 置换表或主要变例中的着法
+    Captures,    // 吃子着法
+    Killers,     // 杀手着法和反击着法
+    Quiets,      // 其余的非吃子着法，按历史表排序
+    BadCaptures, // 静态交换评估亏子的吃子着法
+    Done,
+}
+
+// 分阶段生成着法，前面的阶段产生截断时后面的着法就不用生成了
+// 吃子着法中静态交换评估亏子的放到最后
+pub struct MoveSorter {
+    pub stage: MoveStage,
+    hash_move: Option<Move>,
+    killers: Vec<Move>,
+    moves: Vec<Move>,
+    bad_captures: Vec<Move>,
+    index: usize,
+}
+
+impl MoveSorter {
+    pub fn new(hash_move: Option<Move>, killers: [Option<Move>; 2], counter: Option<Move>) -> Self {
+        let mut candidates: Vec<Move> = vec![];
+        for m in killers.into_iter().chain([counter]).flatten() {
+            if !candidates.contains(&m) && Some(&m) != hash_move.as_ref() {
+                candidates.push(m);
+            }
+        }
+        MoveSorter {
+            stage: MoveStage::HashMove,
+            hash_move,
+            killers: candidates,
+            moves: vec![],
+            bad_captures: vec![],
+            index: 0,
+        }
+    }
+    fn is_searched(&self, m: &Move) -> bool {
+        Some(m) == self.hash_move.as_ref() || (m.capture.chess_type().is_none() && self.killers.contains(m))
+    }
+    pub fn next(&mut self, board: &mut Board) -> Option<Move> {
+        loop {
+            match self.stage {
+                MoveStage::HashMove => {
+                    self.stage = MoveStage::Captures;
+                    self.moves = board.generate_move(true);
+                    self.index = 0;
+                    match &self.hash_move {
+                        Some(m) if board.is_pseudo_legal(m) => return Some(m.clone()),
+                        _ => self.hash_move = None,
+                    }
+                }
+                MoveStage::Captures => {
+                    while self.index < self.moves.len() {
+                        let m = self.moves[self.index].clone();
+                        self.index += 1;
+                        if self.is_searched(&m) {
+                            continue;
+                        }
+                        if board.see(&m) < 0 {
+                            self.bad_captures.push(m);
+                            continue;
+                        }
+                        return Some(m);
+                    }
+                    // 杀手着法只保留当前局面下合法的非吃子着法
+                    let killers = std::mem::take(&mut self.killers);
+                    self.killers = killers
+                        .into_iter()
+                        .filter(|m| m.capture.chess_type().is_none() && board.is_pseudo_legal(m))
+                        .collect();
+                    self.stage = MoveStage::Killers;
+                    self.index = 0;
+                }
+                MoveStage::Killers => {
+                    if self.index < self.killers.len() {
+                        self.index += 1;
+                        return Some(self.killers[self.index - 1].clone());
+                    }
+                    let mut quiets: Vec<Move> = board
+                        .generate_move(false)
+                        .into_iter()
+                        .filter(|m| m.capture.chess_type().is_none() && !self.is_searched(m))
+                        .collect();
+                    quiets.sort_by_key(|m| -board.heuristics.history_score(m));
+                    self.moves = quiets;
+                    self.stage = MoveStage::Quiets;
+                    self.index = 0;
+                }
+                MoveStage::Quiets => {
+                    if self.index < self.moves.len() {
+                        self.index += 1;
+                        return Some(self.moves[self.index - 1].clone());
+                    }
+                    self.moves = std::mem::take(&mut self.bad_captures);
+                    self.stage = MoveStage::BadCaptures;
+                    self.index = 0;
+                }
+                MoveStage::BadCaptures => {
+                    if self.index < self.moves.len() {
+                        self.index += 1;
+                        return Some(self.moves[self.index - 1].clone());
+                    }
+                    self.stage = MoveStage::Done;
+                }
+                MoveStage::Done => return None,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::board::*;
+    use crate::movesort::*;
+
+    #[test]
+    fn test_move_sorter_stages() {
+        let mut board = Board::from_fen("rnbakabnr/9/1c5c1/p1p1p1p1p/9/9/P1P1P1P1P/1C5C1/9/RNBAKABNR w - - 0 1");
+        let all = board.generate_move(false);
+        let hash_move = all
+            .iter()
+            .find(|m| m.capture.chess_type().is_none())
+            .cloned();
+        let killer = all
+            .iter()
+            .rev()
+            .find(|m| m.capture.chess_type().is_none())
+            .cloned();
+        let mut sorter = MoveSorter::new(hash_move.clone(), [killer.clone(), None], None);
+
+        let mut moves = vec![];
+        while let Some(m) = sorter.next(&mut board) {
+            assert!(!moves.contains(&m));
+            moves.push(m);
+        }
+        assert_eq!(moves.len(), all.len());
+        assert_eq!(Some(&moves[0]), hash_move.as_ref());
+        assert_eq!(Some(&moves[1]), killer.as_ref());
+        // 炮打马会被车吃回，排在最后
+        assert_eq!(moves[all.len() - 2].capture.chess_type(), Some(ChessType::Knight));
+        assert_eq!(moves[all.len() - 1].capture.chess_type(), Some(ChessType::Knight));
+    }
+
+    #[test]
+    fn test_bad_captures_last() {
+        // 车吃有炮保护的卒排在所有非吃子着法之后
+        let mut board = Board::from_fen("3k5/c8/b8/9/p7R/9/9/9/9/5K3 w - - 0 1");
+        let mut sorter = MoveSorter::new(None, [None, None], None);
+        let mut moves = vec![];
+        while let Some(m) = sorter.next(&mut board) {
+            moves.push(m);
+        }
+        let last = moves.last().unwrap();
+        assert_eq!(last.capture, Chess::Black(ChessType::Pawn));
+        assert_eq!(sorter.stage, MoveStage::Done);
+    }
+
+    #[test]
+    fn test_illegal_killer_skipped() {
+        let mut board = Board::from_fen("rnbakabnr/9/1c5c1/p1p1p1p1p/9/9/P1P1P1P1P/1C5C1/9/RNBAKABNR w - - 0 1");
+        let killer = Move {
+            player: Player::Red,
+            from: Position::new(9, 4),
+            to: Position::new(7, 4),
+            chess: Chess::Red(ChessType::King),
+            capture: Chess::None,
+        };
+        let count = board.generate_move(false).len();
+        let mut sorter = MoveSorter::new(None, [Some(killer.clone()), None], None);
+        let mut moves = vec![];
+        while let Some(m) = sorter.next(&mut board) {
+            moves.push(m);
+        }
+        assert!(!moves.contains(&killer));
+        assert_eq!(moves.len(), count);
+    }
+
+    #[test]
+    fn test_history_update() {
+        let mut heuristics = Heuristics::new();
+        let m = Move {
+            player: Player::Red,
+            from: Position::new(9, 1),
+            to: Position::new(7, 2),
+            chess: Chess::Red(ChessType::Knight),
+            capture: Chess::None,
+        };
+        heuristics.update(&m, None, 4, 2);
+        assert_eq!(heuristics.history_score(&m), 16);
+        assert_eq!(heuristics.killers(2)[0], Some(m.clone()));
+        heuristics.new_search();
+        assert_eq!(heuristics.history_score(&m), 8);
+        assert_eq!(heuristics.killers(2)[0], None);
+    }
+}
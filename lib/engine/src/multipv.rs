@@ -0,0 +1,166 @@
+use crate::board::{Board, Move, RecordTable};
+use crate::constant::{MAX, MAX_DEPTH, MIN, RECORD_SIZE};
+use std::collections::HashSet;
+use std::sync::Arc;
+
+impl Board {
+    // 从第一步开始，沿置换表中记录的最佳着法取出主要变例
+    pub fn principal_variation(&mut self, first: &Move) -> Vec<Move> {
+        let mut pv: Vec<Move> = vec![];
+        let mut seen = HashSet::new();
+        let mut next = Some(first.clone());
+        while let Some(m) = next {
+            if pv.len() >= MAX_DEPTH as usize || !self.is_pseudo_legal(&m) || !seen.insert(self.zobrist_value) {
+                break;
+            }
+            self.do_move(&m, false);
+            if self.is_checked(self.turn.next()) {
+                self.undo_move(&m);
+                break;
+            }
+            pv.push(m);
+            next = self
+                .find_record()
+                .and_then(|record| record.best_move);
+        }
+        for m in pv.iter().rev() {
+            self.undo_move(m);
+        }
+        pv
+    }
+
+    // 多PV搜索：每一层依次搜索n次，每次在根节点排除前面已经找到的着法，
+    // 返回按分数从高到低排列的n条变例
+    pub fn search_multipv(&mut self, max_depth: i32, n: usize) -> Vec<(i32, Vec<Move>)> {
+        self.search_multipv_with(max_depth, n, |_, _| {})
+    }
+    // 每搜完一层用层数和这一层的变例调用一次callback
+    pub fn search_multipv_with(
+        &mut self,
+        max_depth: i32,
+        n: usize,
+        mut callback: impl FnMut(i32, &[(i32, Vec<Move>)]),
+    ) -> Vec<(i32, Vec<Move>)> {
+        self.distance = 0;
+        self.heuristics.new_search();
+        if self.records.is_empty() {
+            self.records = Arc::new(RecordTable::new(RECORD_SIZE as usize));
+        }
+        let mut lines: Vec<(i32, Vec<Move>)> = vec![];
+        for depth in 1..max_depth + 1 {
+            let mut current: Vec<(i32, Vec<Move>)> = vec![];
+            self.excluded_moves = vec![];
+            while current.len() < n {
+                // 上一层的第i条变例优先搜索
+                self.best_moves_last = match lines.get(current.len()) {
+                    Some((_, pv)) => pv.clone(),
+                    None => vec![],
+                };
+                let (value, best_move) = self.alpha_beta_pvs(depth, MIN, MAX);
+                if self.is_stopped() {
+                    break;
+                }
+                match best_move {
+                    Some(m) => {
+                        let pv = self.principal_variation(&m);
+                        self.excluded_moves.push(m);
+                        current.push((value, pv));
+                    }
+                    None => break,
+                }
+            }
+            self.excluded_moves = vec![];
+            current.sort_by_key(|(value, _)| -value);
+            // 被中止的这一层不完整，用上一层的结果，第一层时保留已经搜完的变例
+            if self.is_stopped() {
+                if lines.is_empty() {
+                    lines = current;
+                }
+                break;
+            }
+            lines = current;
+            callback(depth, &lines);
+        }
+        if lines.is_empty() {
+            if let Some(m) = self.fallback_move() {
+                lines.push((self.evaluate(self.turn), vec![m]));
+            }
+        }
+        self.best_moves_last = match lines.first() {
+            Some((_, pv)) => pv.clone(),
+            None => vec![],
+        };
+        lines
+    }
+}
+
+// 分析局面，返回最好的n个着法的分数和变例，不改变传入的棋盘
+pub fn analyse(board: &Board, depth: i32, n: usize) -> Vec<(i32, Vec<Move>)> {
+    let mut board = board.clone();
+    board.search_multipv(depth, n)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::board::*;
+    use crate::multipv::*;
+
+    #[test]
+    fn test_analyse() {
+        let board = Board::from_fen("rnbakabnr/9/1c5c1/p1p1p1p1p/9/9/P1P1P1P1P/1C5C1/9/RNBAKABNR w - - 0 1");
+        let lines = analyse(&board, 3, 3);
+        assert_eq!(lines.len(), 3);
+        for i in 1..lines.len() {
+            assert!(lines[i - 1].0 >= lines[i].0);
+            assert_ne!(lines[i - 1].1[0], lines[i].1[0]);
+        }
+        for (_, pv) in lines.iter() {
+            assert!(!pv.is_empty());
+            assert_eq!(pv[0].player, Player::Red);
+        }
+        // 分析不改变原来的棋盘
+        assert!(board.records.is_empty());
+    }
+
+    #[test]
+    fn test_analyse_few_moves() {
+        // 红帅只有两步可走
+        let board = Board::from_fen("3k5/9/9/9/9/9/9/9/9/4K4 w - - 0 1");
+        let lines = analyse(&board, 2, 5);
+        assert_eq!(lines.len(), 2);
+    }
+
+    #[test]
+    fn test_search_multipv_with() {
+        let mut board = Board::from_fen("rnbakabnr/9/1c5c1/p1p1p1p1p/9/9/P1P1P1P1P/1C5C1/9/RNBAKABNR w - - 0 1");
+        let mut depths = vec![];
+        board.search_multipv_with(3, 2, |depth, lines| {
+            assert_eq!(lines.len(), 2);
+            depths.push(depth);
+        });
+        assert_eq!(depths, vec![1, 2, 3]);
+    }
+
+    #[test]
+    fn test_principal_variation() {
+        let mut board = Board::from_fen("4k4/9/9/9/9/9/9/4p4/9/5K3 b - - 0 1");
+        let lines = board.search_multipv(3, 1);
+        assert_eq!(lines[0].0, -mated_value(1));
+        assert_eq!(lines[0].1.len(), 1);
+    }
+
+    #[test]
+    fn test_timed_multipv() {
+        use crate::constant::MAX_DEPTH;
+        use std::time::{Duration, Instant};
+
+        let mut board = Board::from_fen("rnbakabnr/9/1c5c1/p1p1p1p1p/9/9/P1P1P1P1P/1C5C1/9/RNBAKABNR w - - 0 1");
+        let start = Instant::now();
+        let lines = board.with_time_limit(Some(Duration::from_millis(100)), |board| {
+            board.search_multipv(MAX_DEPTH, 3)
+        });
+        assert!(start.elapsed().as_secs() < 5);
+        assert_eq!(lines.len(), 3);
+        assert!(!board.is_stopped());
+    }
+}
@@ -0,0 +1,420 @@
+use crate::board::{Board, Chess, Player, Position, BOARD_HEIGHT, BOARD_WIDTH};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use std::fs;
+use std::path::Path;
+use std::sync::Arc;
+
+// 输入特征数：己方/对方 × 7种棋子 × 90个位置
+pub const INPUTS: usize = 2 * 7 * (BOARD_WIDTH * BOARD_HEIGHT) as usize;
+// 量化系数：隐藏层的取值范围是0..QA，输出层权重放大QB倍
+const QA: i32 = 255;
+const QB: i32 = 64;
+// 网络输出乘以SCALE为局面分
+const SCALE: i32 = 400;
+const MAGIC: &[u8; 4] = b"CCNN";
+const VERSION: u32 = 1;
+
+// 从perspective一方看棋子的输入特征，黑方视角下棋盘翻转过来，
+// 这样同一个网络对双方都适用
+fn feature(perspective: Player, chess: Chess, pos: Position) -> Option<usize> {
+    let ct = chess.chess_type()?;
+    let side = if chess.belong_to(perspective) { 0 } else { 1 };
+    let pos = if perspective == Player::Red { pos } else { pos.flip() };
+    Some(
+        (side * 7 + ct.value() as usize) * (BOARD_WIDTH * BOARD_HEIGHT) as usize
+            + (pos.row * BOARD_WIDTH + pos.col) as usize,
+    )
+}
+
+// 局面中所有棋子的输入特征
+fn features(board: &Board, perspective: Player) -> Vec<usize> {
+    let mut result = vec![];
+    for i in 0..BOARD_HEIGHT {
+        for j in 0..BOARD_WIDTH {
+            let pos = Position::new(i, j);
+            if let Some(f) = feature(perspective, board.chess_at(pos), pos) {
+                result.push(f);
+            }
+        }
+    }
+    result
+}
+
+// 量化后的网络：输入层 -> 隐藏层（双方各一份，共用权重） -> 输出
+#[derive(Clone, PartialEq, Debug)]
+pub struct Network {
+    pub hidden: usize,
+    pub feature_weights: Vec<i16>, // INPUTS × hidden，按特征排列
+    pub feature_bias: Vec<i16>,    // hidden
+    pub output_weights: Vec<i16>,  // 2 × hidden，前一半是走棋方的
+    pub output_bias: i32,
+}
+
+// 二进制格式（小端）：CCNN、版本号u32、隐藏层大小u32，
+// 之后依次是feature_weights、feature_bias、output_weights（i16）和output_bias（i32）
+impl Network {
+    pub fn from_bytes(data: &[u8]) -> Result<Self, String> {
+        let mut reader = Reader { data, offset: 0 };
+        if reader.take(4)? != MAGIC {
+            return Err("not a network file".to_owned());
+        }
+        let version = reader.u32()?;
+        if version != VERSION {
+            return Err(format!("unsupported network version {}", version));
+        }
+        let hidden = reader.u32()? as usize;
+        if hidden == 0 {
+            return Err("empty hidden layer".to_owned());
+        }
+        let network = Network {
+            hidden,
+            feature_weights: reader.i16s(INPUTS * hidden)?,
+            feature_bias: reader.i16s(hidden)?,
+            output_weights: reader.i16s(2 * hidden)?,
+            output_bias: reader.i32()?,
+        };
+        if reader.offset != data.len() {
+            return Err("trailing data in network file".to_owned());
+        }
+        Ok(network)
+    }
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut data = MAGIC.to_vec();
+        data.extend(VERSION.to_le_bytes());
+        data.extend((self.hidden as u32).to_le_bytes());
+        for v in self
+            .feature_weights
+            .iter()
+            .chain(self.feature_bias.iter())
+            .chain(self.output_weights.iter())
+        {
+            data.extend(v.to_le_bytes());
+        }
+        data.extend(self.output_bias.to_le_bytes());
+        data
+    }
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, String> {
+        let path = path.as_ref();
+        let data = fs::read(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        Self::from_bytes(&data)
+    }
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), String> {
+        let path = path.as_ref();
+        fs::write(path, self.to_bytes()).map_err(|e| format!("{}: {}", path.display(), e))
+    }
+    fn update(&self, values: &mut [i32], feature: usize, sign: i32) {
+        let weights = &self.feature_weights[feature * self.hidden..(feature + 1) * self.hidden];
+        for (v, w) in values.iter_mut().zip(weights) {
+            *v += sign * *w as i32;
+        }
+    }
+    // 走棋方和对方的隐藏层算出走棋方的局面分
+    fn output(&self, us: &[i32], them: &[i32]) -> i32 {
+        let (us_weights, them_weights) = self.output_weights.split_at(self.hidden);
+        let mut sum = self.output_bias as i64;
+        for (v, w) in us.iter().zip(us_weights) {
+            sum += (*v).clamp(0, QA) as i64 * *w as i64;
+        }
+        for (v, w) in them.iter().zip(them_weights) {
+            sum += (*v).clamp(0, QA) as i64 * *w as i64;
+        }
+        (sum * SCALE as i64 / (QA * QB) as i64) as i32
+    }
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, n: usize) -> Result<&'a [u8], String> {
+        if self.offset + n > self.data.len() {
+            return Err("network file is truncated".to_owned());
+        }
+        let bytes = &self.data[self.offset..self.offset + n];
+        self.offset += n;
+        Ok(bytes)
+    }
+    fn u32(&mut self) -> Result<u32, String> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+    fn i32(&mut self) -> Result<i32, String> {
+        Ok(i32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+    fn i16s(&mut self, n: usize) -> Result<Vec<i16>, String> {
+        Ok(self
+            .take(2 * n)?
+            .chunks(2)
+            .map(|b| i16::from_le_bytes([b[0], b[1]]))
+            .collect())
+    }
+}
+
+// 网络和随着走棋增量更新的隐藏层（累加器），按红、黑两个视角各存一份
+#[derive(Clone, Debug)]
+pub struct Nnue {
+    network: Arc<Network>,
+    accumulator: [Vec<i32>; 2],
+}
+
+impl Nnue {
+    pub fn new(network: Arc<Network>, board: &Board) -> Self {
+        let mut nnue = Nnue {
+            accumulator: [vec![], vec![]],
+            network,
+        };
+        nnue.refresh(board);
+        nnue
+    }
+    // 按棋盘重新计算累加器
+    pub fn refresh(&mut self, board: &Board) {
+        for player in [Player::Red, Player::Black] {
+            let mut values: Vec<i32> = self
+                .network
+                .feature_bias
+                .iter()
+                .map(|b| *b as i32)
+                .collect();
+            for f in features(board, player) {
+                self.network.update(&mut values, f, 1);
+            }
+            self.accumulator[player.value() as usize] = values;
+        }
+    }
+    fn update(&mut self, chess: Chess, pos: Position, sign: i32) {
+        for player in [Player::Red, Player::Black] {
+            if let Some(f) = feature(player, chess, pos) {
+                self.network
+                    .update(&mut self.accumulator[player.value() as usize], f, sign);
+            }
+        }
+    }
+    // chess从from走到to，吃掉capture
+    pub fn apply_move(&mut self, from: Position, to: Position, chess: Chess, capture: Chess) {
+        self.update(chess, from, -1);
+        self.update(capture, to, -1);
+        self.update(chess, to, 1);
+    }
+    pub fn undo_move(&mut self, from: Position, to: Position, chess: Chess, capture: Chess) {
+        self.update(chess, to, -1);
+        self.update(capture, to, 1);
+        self.update(chess, from, 1);
+    }
+    pub fn evaluate(&self, player: Player) -> i32 {
+        self.network.output(
+            &self.accumulator[player.value() as usize],
+            &self.accumulator[player.next().value() as usize],
+        )
+    }
+}
+
+impl Board {
+    // 加载或去掉网络，加载后累加器在apply_move和undo_move中更新，
+    // 没有打开nnue特性时不加载
+    pub fn set_network(&mut self, network: Option<Arc<Network>>) {
+        self.nnue = network
+            .filter(|_| cfg!(feature = "nnue"))
+            .map(|network| Nnue::new(network, self));
+    }
+    pub fn evaluate_nnue(&self, player: Player) -> Option<i32> {
+        self.nnue
+            .as_ref()
+            .map(|nnue| nnue.evaluate(player))
+    }
+}
+
+// 用浮点数训练网络，训练完再量化
+pub struct Trainer {
+    pub hidden: usize,
+    pub learning_rate: f32,
+    feature_weights: Vec<f32>,
+    feature_bias: Vec<f32>,
+    output_weights: Vec<f32>,
+    output_bias: f32,
+}
+
+impl Trainer {
+    pub fn new(hidden: usize, seed: u64) -> Self {
+        let mut rng = StdRng::seed_from_u64(seed);
+        let mut random = |n: usize, range: f32| {
+            (0..n)
+                .map(|_| rng.gen_range(-range..range))
+                .collect::<Vec<f32>>()
+        };
+        Trainer {
+            hidden,
+            learning_rate: 0.01,
+            feature_weights: random(INPUTS * hidden, 0.1),
+            feature_bias: vec![0.0; hidden],
+            output_weights: random(2 * hidden, 1.0 / hidden as f32),
+            output_bias: 0.0,
+        }
+    }
+    // 从已有的网络继续训练
+    pub fn from_network(network: &Network) -> Self {
+        Trainer {
+            hidden: network.hidden,
+            learning_rate: 0.01,
+            feature_weights: network
+                .feature_weights
+                .iter()
+                .map(|w| *w as f32 / QA as f32)
+                .collect(),
+            feature_bias: network
+                .feature_bias
+                .iter()
+                .map(|w| *w as f32 / QA as f32)
+                .collect(),
+            output_weights: network
+                .output_weights
+                .iter()
+                .map(|w| *w as f32 / QB as f32)
+                .collect(),
+            output_bias: network.output_bias as f32 / (QA * QB) as f32,
+        }
+    }
+    fn accumulate(&self, features: &[usize]) -> Vec<f32> {
+        let mut values = self.feature_bias.clone();
+        for f in features {
+            let weights = &self.feature_weights[f * self.hidden..(f + 1) * self.hidden];
+            for (v, w) in values.iter_mut().zip(weights) {
+                *v += w;
+            }
+        }
+        values
+    }
+    fn forward(&self, us: &[f32], them: &[f32]) -> f32 {
+        let (us_weights, them_weights) = self.output_weights.split_at(self.hidden);
+        let mut out = self.output_bias;
+        for (v, w) in us.iter().zip(us_weights) {
+            out += v.clamp(0.0, 1.0) * w;
+        }
+        for (v, w) in them.iter().zip(them_weights) {
+            out += v.clamp(0.0, 1.0) * w;
+        }
+        out
+    }
+    // 走棋方的局面分
+    pub fn evaluate(&self, board: &Board) -> i32 {
+        let us = self.accumulate(&features(board, board.turn));
+        let them = self.accumulate(&features(board, board.turn.next()));
+        (self.forward(&us, &them) * SCALE as f32) as i32
+    }
+    // 用一个局面训练一次，result是红方的得分，返回训练前的平方误差
+    pub fn train(&mut self, board: &Board, result: f64) -> f64 {
+        let target = if board.turn == Player::Red {
+            result
+        } else {
+            1.0 - result
+        } as f32;
+        let us_features = features(board, board.turn);
+        let them_features = features(board, board.turn.next());
+        let us = self.accumulate(&us_features);
+        let them = self.accumulate(&them_features);
+        let out = self.forward(&us, &them);
+        // 预测的得分与调参时局面分到得分的换算一致
+        let p = 1.0 / (1.0 + 10f32.powf(-out));
+        let grad = 2.0 * (p - target) * p * (1.0 - p) * 10f32.ln() * self.learning_rate;
+
+        let hidden = self.hidden;
+        for (side, (values, side_features)) in [(us, us_features), (them, them_features)]
+            .into_iter()
+            .enumerate()
+        {
+            for (i, v) in values.into_iter().enumerate() {
+                let w = self.output_weights[side * hidden + i];
+                self.output_weights[side * hidden + i] -= grad * v.clamp(0.0, 1.0);
+                if v <= 0.0 || v >= 1.0 {
+                    continue;
+                }
+                let delta = grad * w;
+                self.feature_bias[i] -= delta;
+                for f in side_features.iter() {
+                    self.feature_weights[f * hidden + i] -= delta;
+                }
+            }
+        }
+        self.output_bias -= grad;
+        ((p - target) * (p - target)) as f64
+    }
+    pub fn quantize(&self) -> Network {
+        let quantize = |values: &[f32], scale: i32| {
+            values
+                .iter()
+                .map(|v| {
+                    (v * scale as f32)
+                        .round()
+                        .clamp(i16::MIN as f32, i16::MAX as f32) as i16
+                })
+                .collect::<Vec<i16>>()
+        };
+        Network {
+            hidden: self.hidden,
+            feature_weights: quantize(&self.feature_weights, QA),
+            feature_bias: quantize(&self.feature_bias, QA),
+            output_weights: quantize(&self.output_weights, QB),
+            output_bias: (self.output_bias * (QA * QB) as f32).round() as i32,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::board::*;
+    use crate::nnue::*;
+
+    #[cfg(feature = "nnue")]
+    const FEN: &str = "rnbakabnr/9/1c5c1/p1p1p1p1p/9/9/P1P1P1P1P/1C5C1/9/RNBAKABNR w - - 0 1";
+
+    fn network() -> Arc<Network> {
+        Arc::new(Trainer::new(16, 1).quantize())
+    }
+
+    #[test]
+    fn test_network_bytes() {
+        let network = network();
+        let data = network.to_bytes();
+        assert_eq!(Network::from_bytes(&data).as_ref(), Ok(network.as_ref()));
+        assert!(Network::from_bytes(&data[..data.len() - 1]).is_err());
+        assert!(Network::from_bytes(b"XXXX").is_err());
+    }
+
+    #[test]
+    #[cfg(feature = "nnue")]
+    fn test_accumulator_update() {
+        let mut board = Board::from_fen(FEN);
+        board.set_network(Some(network()));
+        let before = board.evaluate_nnue(Player::Red);
+        let moves = board.generate_move(false);
+        for m in moves.iter() {
+            board.do_move(m, false);
+            let mut refreshed = board.clone();
+            refreshed.set_network(Some(network()));
+            assert_eq!(board.evaluate_nnue(board.turn), refreshed.evaluate_nnue(board.turn));
+            board.undo_move(m);
+        }
+        assert_eq!(board.evaluate_nnue(Player::Red), before);
+        // 双方对称的局面，走棋方的分数相同
+        assert_eq!(board.evaluate_nnue(Player::Red), board.evaluate_nnue(Player::Black));
+    }
+
+    #[test]
+    fn test_train() {
+        // 红方多一个车，训练后红方走棋时分数为正
+        let board = Board::from_fen("3k5/9/9/9/9/9/9/9/9/R3K4 w - - 0 1");
+        let mut trainer = Trainer::new(8, 2);
+        let first = trainer.train(&board, 1.0);
+        for _ in 0..200 {
+            trainer.train(&board, 1.0);
+        }
+        assert!(trainer.train(&board, 1.0) < first);
+        assert!(trainer.evaluate(&board) > 0);
+        // 量化后的结果和浮点数的结果接近
+        let v = Nnue::new(Arc::new(trainer.quantize()), &board).evaluate(Player::Red);
+        assert!((v - trainer.evaluate(&board)).abs() <= 10);
+        let restored = Trainer::from_network(&trainer.quantize());
+        assert!((restored.evaluate(&board) - trainer.evaluate(&board)).abs() <= 10);
+    }
+}
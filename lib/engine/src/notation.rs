@@ -0,0 +1,314 @@
+use crate::board::{Board, ChessType, Move, Player, Position, BOARD_HEIGHT};
+
+// 着法记法：ICCS坐标（h2e2）、WXF（C2=5）和中文纵线记法（炮二平五）。
+// 纵线从走棋方的右手边数起，红方用中文数字，黑方用阿拉伯数字
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Notation {
+    Iccs,
+    Wxf,
+    Chinese,
+}
+
+const RED_NUMBERS: [char; 9] = ['一', '二', '三', '四', '五', '六', '七', '八', '九'];
+
+// 着法的各个部分
+struct Description {
+    player: Player,
+    chess_type: ChessType,
+    // 同一纵线上有多个同样的子时，这个子从前往后的序号和个数
+    tandem: Option<(usize, usize)>,
+    file: i32,
+    action: char, // 进+、退-、平=
+    target: i32,
+}
+
+// 走棋方看到的纵线号，1到9
+fn file_number(player: Player, col: i32) -> i32 {
+    if player == Player::Red {
+        9 - col
+    } else {
+        col + 1
+    }
+}
+
+fn number_text(player: Player, n: i32) -> String {
+    if player == Player::Red {
+        RED_NUMBERS[(n - 1) as usize].to_string()
+    } else {
+        n.to_string()
+    }
+}
+
+fn chinese_name(player: Player, ct: ChessType) -> char {
+    match (ct, player) {
+        (ChessType::King, Player::Red) => '帅',
+        (ChessType::King, Player::Black) => '将',
+        (ChessType::Advisor, Player::Red) => '仕',
+        (ChessType::Advisor, Player::Black) => '士',
+        (ChessType::Bishop, Player::Red) => '相',
+        (ChessType::Bishop, Player::Black) => '象',
+        (ChessType::Knight, _) => '马',
+        (ChessType::Rook, _) => '车',
+        (ChessType::Cannon, _) => '炮',
+        (ChessType::Pawn, Player::Red) => '兵',
+        (ChessType::Pawn, Player::Black) => '卒',
+    }
+}
+
+fn wxf_letter(ct: ChessType) -> char {
+    match ct {
+        ChessType::King => 'K',
+        ChessType::Advisor => 'A',
+        ChessType::Bishop => 'E',
+        ChessType::Knight => 'H',
+        ChessType::Rook => 'R',
+        ChessType::Cannon => 'C',
+        ChessType::Pawn => 'P',
+    }
+}
+
+fn describe(board: &Board, m: &Move) -> Description {
+    let player = m.player;
+    let chess_type = board
+        .move_type_at(m.from)
+        .unwrap_or(ChessType::Pawn);
+    // 仕相的进退已经能区分同一纵线上的两个子，不用前后
+    let tandem = if matches!(chess_type, ChessType::Advisor | ChessType::Bishop | ChessType::King) {
+        None
+    } else {
+        let mut rows: Vec<i32> = (0..BOARD_HEIGHT)
+            .filter(|row| {
+                let pos = Position::new(*row, m.from.col);
+                board.chess_at(pos).belong_to(player) && board.move_type_at(pos) == Some(chess_type)
+            })
+            .collect();
+        // 红方在下，行号小的在前
+        if player == Player::Black {
+            rows.reverse();
+        }
+        let index = rows
+            .iter()
+            .position(|row| *row == m.from.row)
+            .unwrap_or(0);
+        if rows.len() > 1 {
+            Some((index, rows.len()))
+        } else {
+            None
+        }
+    };
+    let forward = if player == Player::Red {
+        m.from.row - m.to.row
+    } else {
+        m.to.row - m.from.row
+    };
+    let action = match forward {
+        0 => '=',
+        f if f > 0 => '+',
+        _ => '-',
+    };
+    // 直走的子进退写步数，斜走的子和平移写目标纵线
+    let straight = matches!(
+        chess_type,
+        ChessType::King | ChessType::Rook | ChessType::Cannon | ChessType::Pawn
+    );
+    let target = if action != '=' && straight {
+        forward.abs()
+    } else {
+        file_number(player, m.to.col)
+    };
+    Description {
+        player,
+        chess_type,
+        tandem,
+        file: file_number(player, m.from.col),
+        action,
+        target,
+    }
+}
+
+// 同一纵线上的子：两个为前后，三个为前中后，更多时按一二三四五
+fn tandem_text(index: usize, count: usize, wxf: bool) -> char {
+    match (count, wxf) {
+        (2, false) => ['前', '后'][index],
+        (3, false) => ['前', '中', '后'][index],
+        (_, false) => RED_NUMBERS[index],
+        (2, true) => ['+', '-'][index],
+        (3, true) => ['+', '.', '-'][index],
+        (_, true) => ['a', 'b', 'c', 'd', 'e'][index],
+    }
+}
+
+pub fn to_iccs(m: &Move) -> String {
+    format!("{}{}", m.from.to_string(), m.to.to_string())
+}
+
+pub fn to_wxf(board: &Board, m: &Move) -> String {
+    let d = describe(board, m);
+    let letter = wxf_letter(d.chess_type);
+    let head = match d.tandem {
+        Some((index, count)) => format!("{}{}", tandem_text(index, count, true), letter),
+        None => format!("{}{}", letter, d.file),
+    };
+    format!("{}{}{}", head, d.action, d.target)
+}
+
+pub fn to_chinese(board: &Board, m: &Move) -> String {
+    let d = describe(board, m);
+    let name = chinese_name(d.player, d.chess_type);
+    let head = match d.tandem {
+        Some((index, count)) => format!("{}{}", tandem_text(index, count, false), name),
+        None => format!("{}{}", name, number_text(d.player, d.file)),
+    };
+    let action = match d.action {
+        '+' => '进',
+        '-' => '退',
+        _ => '平',
+    };
+    format!("{}{}{}", head, action, number_text(d.player, d.target))
+}
+
+// 按指定记法写出当前局面下的着法
+pub fn format_move(board: &Board, m: &Move, notation: Notation) -> String {
+    match notation {
+        Notation::Iccs => to_iccs(m),
+        Notation::Wxf => to_wxf(board, m),
+        Notation::Chinese => to_chinese(board, m),
+    }
+}
+
+// 整盘棋的着法按指定记法写出，包括悔棋后还能重走的着法
+pub fn move_list(board: &Board, notation: Notation) -> Vec<String> {
+    let mut board = board.clone();
+    let moves = board.game_moves();
+    board.goto_ply(0);
+    moves
+        .iter()
+        .map(|m| {
+            let text = format_move(&board, m, notation);
+            board.do_move(m, board.jieqi);
+            text
+        })
+        .collect()
+}
+
+// 统一写法以便比较：繁体字和异体字、全角数字、红黑方不同的子名和数字都换成同一个写法
+fn normalize(s: &str) -> String {
+    s.trim()
+        .chars()
+        .filter(|c| !c.is_whitespace())
+        .map(|c| match c {
+            '帅' | '帥' | '将' | '將' => 'K',
+            '仕' | '士' => 'A',
+            '相' | '象' => 'E',
+            '马' | '馬' | '傌' => 'H',
+            '车' | '車' | '俥' => 'R',
+            '炮' | '砲' | '包' => 'C',
+            '兵' | '卒' => 'P',
+            '进' | '進' => '+',
+            '退' => '-',
+            '平' => '=',
+            '１'..='９' => char::from_u32(c as u32 - '１' as u32 + '1' as u32).unwrap(),
+            _ => match RED_NUMBERS.iter().position(|n| *n == c) {
+                Some(i) => char::from_digit(i as u32 + 1, 10).unwrap(),
+                None => c.to_ascii_uppercase(),
+            },
+        })
+        .map(|c| match c {
+            'N' => 'H',
+            'B' => 'E',
+            c => c,
+        })
+        .collect()
+}
+
+// 解析ICCS、WXF或中文记法的着法，返回当前局面下对应的合法着法
+pub fn parse_notation(board: &Board, s: &str) -> Option<Move> {
+    let mut board = board.clone();
+    let iccs = s.trim().replace('-', "").to_lowercase();
+    if let Some(m) = board.parse_move(&iccs) {
+        return board.legal_moves().into_iter().find(|x| *x == m);
+    }
+    let target = normalize(s);
+    board
+        .legal_moves()
+        .into_iter()
+        .find(|m| normalize(&to_wxf(&board, m)) == target || normalize(&to_chinese(&board, m)) == target)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::board::*;
+    use crate::notation::*;
+
+    fn find(board: &Board, iccs: &str) -> Move {
+        board.parse_move(iccs).unwrap()
+    }
+
+    #[test]
+    fn test_opening_moves() {
+        let mut board = Board::init(false, false);
+        let m = find(&board, "h2e2");
+        assert_eq!(to_wxf(&board, &m), "C2=5");
+        assert_eq!(to_chinese(&board, &m), "炮二平五");
+        board.do_move(&m, true);
+        let m = find(&board, "h9g7");
+        assert_eq!(to_wxf(&board, &m), "H8+7");
+        assert_eq!(to_chinese(&board, &m), "马8进7");
+        assert_eq!(move_list(&board, Notation::Chinese), ["炮二平五"]);
+        let m = find(&board, "a9a8");
+        assert_eq!(to_chinese(&board, &m), "车1进1");
+        let m = find(&board, "e9e8");
+        assert_eq!(to_chinese(&board, &m), "将5进1");
+        let m = find(&board, "f9e8");
+        assert_eq!(to_wxf(&board, &m), "A6+5");
+        assert_eq!(format_move(&board, &m, Notation::Iccs), "f9e8");
+        // 悔棋后还能重走的着法也列出来
+        board.do_move(&find(&board, "h9g7"), true);
+        board.undo();
+        board.undo();
+        assert_eq!(move_list(&board, Notation::Wxf), ["C2=5", "H8+7"]);
+    }
+
+    #[test]
+    fn test_tandem() {
+        // 两个红车在同一纵线上
+        let board = Board::from_fen("4k4/9/9/9/4R4/9/9/4R4/9/3K5 w - - 0 1");
+        let m = find(&board, "e5e8");
+        assert_eq!(to_chinese(&board, &m), "前车进三");
+        assert_eq!(to_wxf(&board, &m), "+R+3");
+        let m = find(&board, "e2c2");
+        assert_eq!(to_chinese(&board, &m), "后车平七");
+        assert_eq!(to_wxf(&board, &m), "-R=7");
+        // 三个黑卒在同一纵线上，黑方在上，行号大的在前
+        let board = Board::from_fen("4k4/9/9/9/9/2p6/2p6/2p6/9/4K4 b - - 0 1");
+        let m = find(&board, "c2c1");
+        assert_eq!(to_chinese(&board, &m), "前卒进1");
+        let m = find(&board, "c3b3");
+        assert_eq!(to_chinese(&board, &m), "中卒平2");
+        assert_eq!(to_wxf(&board, &m), ".P=2");
+    }
+
+    #[test]
+    fn test_parse_notation() {
+        let mut board = Board::init(false, false);
+        for s in ["炮二平五", "C2=5", "c2=5", "炮2平5", "炮二平５", "h2e2", "h2-e2"] {
+            assert_eq!(parse_notation(&board, s), Some(find(&board, "h2e2")), "{}", s);
+        }
+        assert_eq!(parse_notation(&board, "傌二進三"), Some(find(&board, "h0g2")));
+        assert_eq!(parse_notation(&board, "炮二进九"), None);
+        assert_eq!(parse_notation(&board, "h2h8"), None);
+        let m = find(&board, "b2e2");
+        board.do_move(&m, true);
+        assert_eq!(parse_notation(&board, "马8进7"), Some(find(&board, "h9g7")));
+        assert_eq!(parse_notation(&board, "N8+7"), Some(find(&board, "h9g7")));
+        // 每个合法着法用三种记法写出后都能解析回来
+        let board = Board::from_fen("3k5/9/9/9/4R4/9/2p6/4R4/9/5K3 w - - 0 1");
+        for m in board.clone().legal_moves() {
+            for notation in [Notation::Iccs, Notation::Wxf, Notation::Chinese] {
+                let s = format_move(&board, &m, notation);
+                assert_eq!(parse_notation(&board, &s), Some(m.clone()), "{}", s);
+            }
+        }
+    }
+}
@@ -0,0 +1,508 @@
+use crate::board::{Board, Move, Player};
+use crate::constant::STARTPOS;
+use crate::notation::{format_move, parse_notation, Notation};
+use crate::rules::{judge, GameResult};
+use std::fs;
+use std::path::Path;
+
+// 象棋PGN棋谱：标签（Event、Red、Black、Result、FEN等）加着法，
+// 着法可以是ICCS（H2-E2）、WXF或中文记法，支持{注释}、;行注释、$n评注和(变着)
+
+// 棋谱中的一步：着法、评注、注释，以及代替这一步的变着
+#[derive(Clone, Debug, PartialEq)]
+pub struct PgnMove {
+    pub m: Move,
+    pub nags: Vec<u8>,
+    pub comment: Option<String>,
+    // 每个变着都从这一步之前的局面开始
+    pub variations: Vec<Vec<PgnMove>>,
+}
+
+impl PgnMove {
+    pub fn new(m: Move) -> Self {
+        PgnMove {
+            m,
+            nags: vec![],
+            comment: None,
+            variations: vec![],
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct PgnGame {
+    pub tags: Vec<(String, String)>,
+    // 第一步之前的注释
+    pub comment: Option<String>,
+    pub moves: Vec<PgnMove>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+    Word(String),
+    Comment(String),
+    Nag(u8),
+    Open,
+    Close,
+}
+
+fn tokenize(text: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = vec![];
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '{' => {
+                let comment: String = chars.by_ref().take_while(|c| *c != '}').collect();
+                tokens.push(Token::Comment(comment.trim().to_owned()));
+            }
+            ';' => {
+                let comment: String = chars
+                    .by_ref()
+                    .take_while(|c| *c != '\n')
+                    .collect();
+                tokens.push(Token::Comment(comment.trim().to_owned()));
+            }
+            '(' => tokens.push(Token::Open),
+            ')' => tokens.push(Token::Close),
+            '$' => {
+                let mut digits = String::new();
+                while let Some(c) = chars.next_if(|c| c.is_ascii_digit()) {
+                    digits.push(c);
+                }
+                tokens.push(Token::Nag(
+                    digits
+                        .parse()
+                        .map_err(|_| format!("invalid nag ${}", digits))?,
+                ));
+            }
+            c if c.is_whitespace() => {}
+            c => {
+                let mut word = c.to_string();
+                while let Some(c) = chars.next_if(|c| !c.is_whitespace() && !"{};()$".contains(*c)) {
+                    word.push(c);
+                }
+                // 着法后面的!?换成评注
+                let annotation = word.trim_end_matches(['!', '?']).len();
+                let suffix = word.split_off(annotation.max(1).min(word.len()));
+                tokens.push(Token::Word(word));
+                if !suffix.is_empty() {
+                    let nag = match suffix.as_str() {
+                        "!" => 1,
+                        "?" => 2,
+                        "!!" => 3,
+                        "??" => 4,
+                        "!?" => 5,
+                        "?!" => 6,
+                        _ => return Err(format!("invalid annotation {}", suffix)),
+                    };
+                    tokens.push(Token::Nag(nag));
+                }
+            }
+        }
+    }
+    Ok(tokens)
+}
+
+fn is_result(word: &str) -> bool {
+    matches!(word, "1-0" | "0-1" | "1/2-1/2" | "*")
+}
+
+fn append_comment(target: &mut Option<String>, comment: String) {
+    match target {
+        Some(c) => {
+            c.push(' ');
+            c.push_str(&comment);
+        }
+        None => *target = Some(comment),
+    }
+}
+
+// 解析一条着法序列，遇到)或结果时返回
+fn parse_line(
+    tokens: &[Token],
+    index: &mut usize,
+    board: &mut Board,
+    leading: &mut Option<String>,
+) -> Result<Vec<PgnMove>, String> {
+    let mut moves: Vec<PgnMove> = vec![];
+    // 变着开头的注释放到第一步的注释中
+    let mut pending = None;
+    while *index < tokens.len() {
+        let token = tokens[*index].clone();
+        *index += 1;
+        match token {
+            Token::Word(word) => {
+                if is_result(&word) {
+                    *index -= 1;
+                    break;
+                }
+                // 去掉回合数，如1.、1...或者12.h2e2，WXF的中间子.P=2不以数字开头
+                let word = if word.starts_with(|c: char| c.is_ascii_digit()) {
+                    word.trim_start_matches(|c: char| c.is_ascii_digit())
+                        .trim_start_matches('.')
+                } else {
+                    word.as_str()
+                };
+                if word.is_empty() || word.chars().all(|c| c == '.') {
+                    continue;
+                }
+                let m = parse_notation(board, word).ok_or_else(|| format!("illegal move {}", word))?;
+                board.do_move(&m, false);
+                let mut node = PgnMove::new(m);
+                node.comment = pending.take();
+                moves.push(node);
+            }
+            Token::Comment(comment) => match moves.last_mut() {
+                Some(last) => append_comment(&mut last.comment, comment),
+                None if leading.is_none() => *leading = Some(comment),
+                None => append_comment(&mut pending, comment),
+            },
+            Token::Nag(nag) => match moves.last_mut() {
+                Some(last) => last.nags.push(nag),
+                None => return Err(format!("nag ${} before any move", nag)),
+            },
+            Token::Open => {
+                let last = moves
+                    .last_mut()
+                    .ok_or("variation before any move")?;
+                let mut variation_board = board.clone();
+                variation_board.undo_move(&last.m);
+                let mut comment = None;
+                let mut variation = parse_line(tokens, index, &mut variation_board, &mut comment)?;
+                if tokens.get(*index) != Some(&Token::Close) {
+                    return Err("unclosed variation".to_owned());
+                }
+                *index += 1;
+                if let (Some(comment), Some(first)) = (comment, variation.first_mut()) {
+                    let rest = first.comment.take();
+                    first.comment = Some(comment);
+                    if let Some(rest) = rest {
+                        append_comment(&mut first.comment, rest);
+                    }
+                }
+                if !variation.is_empty() {
+                    last.variations.push(variation);
+                }
+            }
+            Token::Close => {
+                *index -= 1;
+                break;
+            }
+        }
+    }
+    Ok(moves)
+}
+
+// 标签值中的\和"要转义
+fn escape(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+fn parse_tag(line: &str) -> Result<(String, String), String> {
+    let error = || format!("invalid tag {}", line);
+    let inner = line
+        .strip_prefix('[')
+        .and_then(|l| l.strip_suffix(']'))
+        .ok_or_else(error)?;
+    let (name, value) = inner.trim().split_once(' ').ok_or_else(error)?;
+    let value = value.trim();
+    let value = value
+        .strip_prefix('"')
+        .and_then(|v| v.strip_suffix('"'))
+        .ok_or_else(error)?;
+    let mut unescaped = String::new();
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        if c == '\\' {
+            unescaped.extend(chars.next());
+        } else {
+            unescaped.push(c);
+        }
+    }
+    Ok((name.to_owned(), unescaped))
+}
+
+fn result_string(result: Option<GameResult>) -> String {
+    result.map_or("*".to_owned(), |r| r.to_string())
+}
+
+impl PgnGame {
+    pub fn tag(&self, name: &str) -> Option<&str> {
+        self.tags
+            .iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, v)| v.as_str())
+    }
+    pub fn set_tag(&mut self, name: &str, value: &str) {
+        match self.tags.iter_mut().find(|(n, _)| n == name) {
+            Some(tag) => tag.1 = value.to_owned(),
+            None => self
+                .tags
+                .push((name.to_owned(), value.to_owned())),
+        }
+    }
+    pub fn result(&self) -> Option<GameResult> {
+        match self.tag("Result") {
+            Some("1-0") => Some(GameResult::RedWin),
+            Some("0-1") => Some(GameResult::BlackWin),
+            Some("1/2-1/2") => Some(GameResult::Draw),
+            _ => None,
+        }
+    }
+    pub fn set_result(&mut self, result: Option<GameResult>) {
+        self.set_tag("Result", &result_string(result));
+    }
+    // 开始局面，没有FEN标签时为标准开局
+    pub fn start_board(&self) -> Board {
+        Board::from_fen(self.tag("FEN").unwrap_or(STARTPOS))
+    }
+    // 走完主线后的局面，move_history中为主线的着法
+    pub fn to_board(&self) -> Board {
+        let mut board = self.start_board();
+        for node in self.moves.iter() {
+            board.do_move(&node.m, false);
+        }
+        board
+    }
+    // 从对局的move_history生成棋谱，结果按棋规判断
+    pub fn from_board(board: &Board) -> Self {
+        let mut start = board.clone();
+        for m in board.move_history.iter().rev() {
+            start.undo_move(m);
+        }
+        let mut game = PgnGame::default();
+        for name in ["Game", "Event", "Site", "Date", "Round", "Red", "Black"] {
+            game.set_tag(name, if name == "Game" { "Chinese Chess" } else { "?" });
+        }
+        game.set_result(judge(board).map(|(result, _)| result));
+        let fen = start.to_fen();
+        if fen != STARTPOS {
+            game.set_tag("FEN", &fen);
+        }
+        game.moves = board
+            .move_history
+            .iter()
+            .map(|m| PgnMove::new(m.clone()))
+            .collect();
+        game
+    }
+    // 读入文件中的第一盘棋
+    pub fn parse(data: &str) -> Result<Self, String> {
+        Self::parse_all(data)?
+            .into_iter()
+            .next()
+            .ok_or_else(|| "no game found".to_owned())
+    }
+    // 一个文件可以有多盘棋，每盘以标签开始
+    pub fn parse_all(data: &str) -> Result<Vec<Self>, String> {
+        let mut games = vec![];
+        let mut tags = vec![];
+        let mut movetext = String::new();
+        for line in data.lines() {
+            let line = line.trim();
+            if line.starts_with('[') {
+                if !movetext.trim().is_empty() {
+                    games.push(Self::parse_game(std::mem::take(&mut tags), &movetext)?);
+                    movetext.clear();
+                }
+                tags.push(parse_tag(line)?);
+            } else {
+                movetext.push_str(line);
+                movetext.push('\n');
+            }
+        }
+        if !tags.is_empty() || !movetext.trim().is_empty() {
+            games.push(Self::parse_game(tags, &movetext)?);
+        }
+        Ok(games)
+    }
+    fn parse_game(tags: Vec<(String, String)>, movetext: &str) -> Result<Self, String> {
+        let mut game = PgnGame {
+            tags,
+            comment: None,
+            moves: vec![],
+        };
+        let tokens = tokenize(movetext)?;
+        let mut board = game.start_board();
+        let mut index = 0;
+        game.moves = parse_line(&tokens, &mut index, &mut board, &mut game.comment)?;
+        match tokens.get(index) {
+            // 没有Result标签时用着法后面的结果
+            Some(Token::Word(word)) if is_result(word) && game.tag("Result").is_none() => {
+                game.set_tag("Result", word);
+            }
+            Some(Token::Close) => return Err("unexpected )".to_owned()),
+            _ => {}
+        }
+        Ok(game)
+    }
+    // 写出棋谱，Format标签记录着法的记法
+    pub fn to_pgn(&self, notation: Notation) -> String {
+        let mut out = String::new();
+        for (name, value) in self.tags.iter() {
+            if name != "Format" {
+                out.push_str(&format!("[{} \"{}\"]\n", name, escape(value)));
+            }
+        }
+        if self.tag("Result").is_none() {
+            out.push_str("[Result \"*\"]\n");
+        }
+        let format = match notation {
+            Notation::Iccs => "ICCS",
+            Notation::Wxf => "WXF",
+            Notation::Chinese => "Chinese",
+        };
+        out.push_str(&format!("[Format \"{}\"]\n\n", format));
+
+        let mut words = vec![];
+        if let Some(comment) = &self.comment {
+            words.push(format!("{{{}}}", comment));
+        }
+        let mut board = self.start_board();
+        write_line(&mut words, &self.moves, &mut board, notation, 1);
+        words.push(self.tag("Result").unwrap_or("*").to_owned());
+        // 每行不超过80个字符
+        let mut line = String::new();
+        for word in words {
+            if !line.is_empty() && line.chars().count() + word.chars().count() >= 80 {
+                out.push_str(&line);
+                out.push('\n');
+                line.clear();
+            }
+            if !line.is_empty() {
+                line.push(' ');
+            }
+            line.push_str(&word);
+        }
+        out.push_str(&line);
+        out.push('\n');
+        out
+    }
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, String> {
+        let path = path.as_ref();
+        let data = fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        Self::parse(&data)
+    }
+    pub fn save(&self, path: impl AsRef<Path>, notation: Notation) -> Result<(), String> {
+        let path = path.as_ref();
+        fs::write(path, self.to_pgn(notation)).map_err(|e| format!("{}: {}", path.display(), e))
+    }
+}
+
+// 写出一条着法序列，注释或变着之后的黑方着法要重新写回合数
+fn write_line(words: &mut Vec<String>, moves: &[PgnMove], board: &mut Board, notation: Notation, mut number: usize) {
+    let mut need_number = true;
+    for node in moves {
+        if board.turn == Player::Red {
+            words.push(format!("{}.", number));
+        } else if need_number {
+            words.push(format!("{}...", number));
+        }
+        need_number = false;
+        words.push(match notation {
+            Notation::Iccs => {
+                let iccs = format_move(board, &node.m, notation).to_uppercase();
+                format!("{}-{}", &iccs[..2], &iccs[2..])
+            }
+            _ => format_move(board, &node.m, notation),
+        });
+        words.extend(node.nags.iter().map(|nag| format!("${}", nag)));
+        if let Some(comment) = &node.comment {
+            words.push(format!("{{{}}}", comment));
+            need_number = true;
+        }
+        for variation in node.variations.iter() {
+            // 括号紧贴着变着的第一个和最后一个词
+            let start = words.len();
+            write_line(words, variation, &mut board.clone(), notation, number);
+            words[start].insert(0, '(');
+            words.last_mut().unwrap().push(')');
+            need_number = true;
+        }
+        board.do_move(&node.m, false);
+        if board.turn == Player::Red {
+            number += 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::board::*;
+    use crate::notation::*;
+    use crate::pgn::*;
+
+    const SAMPLE: &str = r#"[Game "Chinese Chess"]
+[Event "测试"]
+[Red "红方"]
+[Black "黑\"方\""]
+[Result "1-0"]
+[Format "ICCS"]
+
+{开局} 1. H2-E2 H9-G7 {屏风马} 2. H0-G2 $1 (2. B0-C2 I9-H9) 2... I9-H9 ; 出车
+3. I0-H0 1-0
+"#;
+
+    #[test]
+    fn test_parse() {
+        let game = PgnGame::parse(SAMPLE).unwrap();
+        assert_eq!(game.tag("Black"), Some("黑\"方\""));
+        assert_eq!(game.result(), Some(GameResult::RedWin));
+        assert_eq!(game.comment.as_deref(), Some("开局"));
+        assert_eq!(game.moves.len(), 5);
+        assert_eq!(game.moves[1].comment.as_deref(), Some("屏风马"));
+        assert_eq!(game.moves[2].nags, vec![1]);
+        assert_eq!(game.moves[2].variations[0].len(), 2);
+        assert_eq!(game.moves[3].comment.as_deref(), Some("出车"));
+        let board = game.to_board();
+        assert_eq!(board.move_history.len(), 5);
+        assert_eq!(
+            board.to_fen(),
+            "rnbakabr1/9/1c4nc1/p1p1p1p1p/9/9/P1P1P1P1P/1C2C1N2/9/RNBAKABR1 b - - 0 1"
+        );
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let game = PgnGame::parse(SAMPLE).unwrap();
+        for notation in [Notation::Iccs, Notation::Wxf, Notation::Chinese] {
+            let text = game.to_pgn(notation);
+            let parsed = PgnGame::parse(&text).unwrap();
+            assert_eq!(parsed.moves, game.moves, "{}", text);
+            assert_eq!(parsed.comment, game.comment);
+        }
+        let text = game.to_pgn(Notation::Chinese);
+        assert!(
+            text.contains("1. 炮二平五 马8进7 {屏风马} 2. 马二进三 $1 (2. 马八进七 车9平8) 2... 车9平8"),
+            "{}",
+            text
+        );
+        assert!(text.contains("[Format \"Chinese\"]"));
+    }
+
+    #[test]
+    fn test_from_board() {
+        let mut board = Board::from_fen("3k5/9/9/9/9/9/9/9/9/4K3R b - - 0 1");
+        for m in ["d9d8", "i0i8", "d8d9"] {
+            let m = board.parse_move(m).unwrap();
+            board.do_move(&m, false);
+        }
+        let game = PgnGame::from_board(&board);
+        assert_eq!(game.tag("FEN"), Some("3k5/9/9/9/9/9/9/9/9/4K3R b - - 0 1"));
+        assert_eq!(game.result(), None);
+        let text = game.to_pgn(Notation::Iccs);
+        assert!(text.contains("1... D9-D8 2. I0-I8 D8-D9 *"), "{}", text);
+        let parsed = PgnGame::parse(&text).unwrap();
+        assert_eq!(parsed.to_board().to_fen(), board.to_fen());
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert!(PgnGame::parse("1. h2h8").is_err());
+        assert!(PgnGame::parse("1. h2e2 (1. b2e2").is_err());
+        assert!(PgnGame::parse("[Event \"x\"\n1. h2e2").is_err());
+        // 多盘棋
+        let games = PgnGame::parse_all(&format!("{}\n{}", SAMPLE, SAMPLE)).unwrap();
+        assert_eq!(games.len(), 2);
+    }
+}
@@ -0,0 +1,191 @@
+use crate::board::{Board, Chess, ChessType, Player, Position, BOARD_HEIGHT, BOARD_WIDTH};
+use std::fmt;
+
+// 对局结果
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum GameResult {
+    RedWin,
+    BlackWin,
+    Draw,
+}
+
+impl GameResult {
+    pub fn win(player: Player) -> Self {
+        if player == Player::Red {
+            GameResult::RedWin
+        } else {
+            GameResult::BlackWin
+        }
+    }
+    pub fn loss(player: Player) -> Self {
+        GameResult::win(player.next())
+    }
+    // 红方的得分，胜1和0.5负0
+    pub fn score(&self) -> f64 {
+        match self {
+            GameResult::RedWin => 1.0,
+            GameResult::BlackWin => 0.0,
+            GameResult::Draw => 0.5,
+        }
+    }
+}
+
+impl fmt::Display for GameResult {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            GameResult::RedWin => write!(f, "1-0"),
+            GameResult::BlackWin => write!(f, "0-1"),
+            GameResult::Draw => write!(f, "1/2-1/2"),
+        }
+    }
+}
+
+// 对局结束的原因
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Reason {
+    Checkmate,      // 将死
+    Stalemate,      // 困毙
+    PerpetualCheck, // 长将
+    Repetition,     // 重复局面
+    NoAttackers,    // 双方都没有车马炮兵
+    Resignation,    // 认输
+    Timeout,        // 超时
+}
+
+impl fmt::Display for Reason {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let s = match self {
+            Reason::Checkmate => "将死",
+            Reason::Stalemate => "困毙",
+            Reason::PerpetualCheck => "长将",
+            Reason::Repetition => "重复局面",
+            Reason::NoAttackers => "双方无进攻子力",
+            Reason::Resignation => "认输",
+            Reason::Timeout => "超时",
+        };
+        write!(f, "{}", s)
+    }
+}
+
+// 按棋规判断对局是否结束。重复局面从move_history倒推，所以需要用do_move走棋。
+// 长捉等其他禁着暂不判断，不是长将的重复局面都判和
+pub fn judge(board: &Board) -> Option<(GameResult, Reason)> {
+    let mut board = board.clone();
+    if board.legal_moves().is_empty() {
+        let reason = if board.is_checked(board.turn) {
+            Reason::Checkmate
+        } else {
+            Reason::Stalemate
+        };
+        return Some((GameResult::loss(board.turn), reason));
+    }
+    if let Some(result) = repetition(&mut board) {
+        return Some(result);
+    }
+    if !has_attackers(&board, Player::Red) && !has_attackers(&board, Player::Black) {
+        return Some((GameResult::Draw, Reason::NoAttackers));
+    }
+    None
+}
+
+// 当前局面第三次出现时，只有一方在循环中步步将军则判这一方负，否则判和
+fn repetition(board: &mut Board) -> Option<(GameResult, Reason)> {
+    let (zobrist, zobrist_lock, turn) = (board.zobrist_value, board.zobrist_value_lock, board.turn);
+    let mut count = 1;
+    // 每一方在循环中是否每步都将军
+    let mut always_check = [true, true];
+    for m in board.move_history.clone().iter().rev() {
+        always_check[m.player.value() as usize] &= board.is_checked(m.player.next());
+        board.undo_move(m);
+        if board.turn == turn && board.zobrist_value == zobrist && board.zobrist_value_lock == zobrist_lock {
+            count += 1;
+            if count >= 3 {
+                return Some(match always_check {
+                    [true, false] => (GameResult::BlackWin, Reason::PerpetualCheck),
+                    [false, true] => (GameResult::RedWin, Reason::PerpetualCheck),
+                    _ => (GameResult::Draw, Reason::Repetition),
+                });
+            }
+        }
+        // 吃子以后不可能再回到之前的局面
+        if m.capture != Chess::None {
+            break;
+        }
+    }
+    None
+}
+
+// 是否还有车马炮兵
+fn has_attackers(board: &Board, player: Player) -> bool {
+    (0..BOARD_HEIGHT).any(|i| {
+        (0..BOARD_WIDTH).any(|j| {
+            let chess = board.chess_at(Position::new(i, j));
+            chess.player() == Some(player)
+                && matches!(
+                    chess.chess_type(),
+                    Some(ChessType::Rook | ChessType::Knight | ChessType::Cannon | ChessType::Pawn)
+                )
+        })
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::board::*;
+    use crate::rules::*;
+
+    fn play(board: &mut Board, moves: &[&str]) {
+        for m in moves {
+            let (from, to): (Position, Position) = (m[..2].into(), m[2..].into());
+            let m = Move {
+                player: board.turn,
+                from,
+                to,
+                chess: board.chess_at(from),
+                capture: board.chess_at(to),
+            };
+            board.do_move(&m, true);
+        }
+    }
+
+    #[test]
+    fn test_checkmate() {
+        // 三个兵在将前，无着可应
+        let board = Board::from_fen("4k4/3PPP3/9/9/9/9/9/9/9/4K4 b - - 0 1");
+        assert_eq!(judge(&board), Some((GameResult::RedWin, Reason::Checkmate)));
+        assert_eq!(GameResult::RedWin.to_string(), "1-0");
+    }
+
+    #[test]
+    fn test_stalemate() {
+        // 黑将没有被将军，但走到e9会对将，走到d8会被兵吃
+        let board = Board::from_fen("3k5/9/3P5/9/9/9/9/9/9/4K4 b - - 0 1");
+        assert_eq!(judge(&board), Some((GameResult::RedWin, Reason::Stalemate)));
+    }
+
+    #[test]
+    fn test_repetition() {
+        let mut board = Board::from_fen("3k5/9/9/9/9/9/9/9/r8/4K3R w - - 0 1");
+        let cycle = ["i0i1", "a1a2", "i1i0", "a2a1"];
+        play(&mut board, &cycle);
+        assert_eq!(judge(&board), None);
+        play(&mut board, &cycle);
+        assert_eq!(judge(&board), Some((GameResult::Draw, Reason::Repetition)));
+    }
+
+    #[test]
+    fn test_perpetual_check() {
+        // 红车在d线和e线之间来回将军，黑将来回躲
+        let mut board = Board::from_fen("4k4/9/9/9/9/9/9/9/9/3R1K3 w - - 0 1");
+        let cycle = ["d0e0", "e9d9", "e0d0", "d9e9"];
+        play(&mut board, &cycle);
+        play(&mut board, &cycle);
+        assert_eq!(judge(&board), Some((GameResult::BlackWin, Reason::PerpetualCheck)));
+    }
+
+    #[test]
+    fn test_no_attackers() {
+        let board = Board::from_fen("3k5/4a4/9/9/9/9/9/9/9/4K4 w - - 0 1");
+        assert_eq!(judge(&board), Some((GameResult::Draw, Reason::NoAttackers)));
+    }
+}
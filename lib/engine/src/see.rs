@@ -0,0 +1,235 @@
+use crate::board::{
+    in_board, in_country, in_palace, Board, Chess, ChessType, Move, Player, Position, BOARD_HEIGHT, BOARD_WIDTH,
+};
+
+type Squares = [[Chess; BOARD_WIDTH as usize]; BOARD_HEIGHT as usize];
+
+// 静态交换评估用的局面，只记录棋子位置，交换过程中棋子被拿走后
+// 炮架和马腿会随之变化
+struct Exchange {
+    chesses: Squares,
+    chesses_status: Squares,
+}
+
+impl Exchange {
+    fn chess_at(&self, pos: Position) -> Chess {
+        if in_board(pos) {
+            self.chesses[pos.row as usize][pos.col as usize]
+        } else {
+            Chess::None
+        }
+    }
+    // 揭棋中未翻开的棋子按所在位置的棋子类型走
+    fn move_type_at(&self, pos: Position) -> Option<ChessType> {
+        let status = self.chesses_status[pos.row as usize][pos.col as usize];
+        status
+            .chess_type()
+            .or(self.chess_at(pos).chess_type())
+    }
+    fn is(&self, pos: Position, player: Player, ct: ChessType) -> bool {
+        in_board(pos) && self.chess_at(pos).belong_to(player) && self.move_type_at(pos) == Some(ct)
+    }
+    fn remove(&mut self, pos: Position) {
+        self.chesses[pos.row as usize][pos.col as usize] = Chess::None;
+        self.chesses_status[pos.row as usize][pos.col as usize] = Chess::None;
+    }
+    fn place(&mut self, pos: Position, chess: Chess) {
+        self.chesses[pos.row as usize][pos.col as usize] = chess;
+        self.chesses_status[pos.row as usize][pos.col as usize] = Chess::None;
+    }
+    // 找出player一方攻击target的所有棋子
+    fn attackers(&self, target: Position, player: Player) -> Vec<Position> {
+        let mut attackers = vec![];
+
+        // 兵，过河后可以横着吃
+        let forward = if player == Player::Red {
+            target.down(1)
+        } else {
+            target.up(1)
+        };
+        if self.is(forward, player, ChessType::Pawn) {
+            attackers.push(forward);
+        }
+        for pos in [target.left(1), target.right(1)] {
+            if self.is(pos, player, ChessType::Pawn) && !in_country(pos.row, player) {
+                attackers.push(pos);
+            }
+        }
+
+        // 士
+        if in_palace(target, player) {
+            for pos in [
+                target.up(1).left(1),
+                target.up(1).right(1),
+                target.down(1).left(1),
+                target.down(1).right(1),
+            ] {
+                if self.is(pos, player, ChessType::Advisor) {
+                    attackers.push(pos);
+                }
+            }
+        }
+
+        // 相，塞象眼时不能吃
+        if in_country(target.row, player) {
+            for (dr, dc) in [(-1, -1), (-1, 1), (1, -1), (1, 1)] {
+                let pos = Position::new(target.row + 2 * dr, target.col + 2 * dc);
+                let eye = Position::new(target.row + dr, target.col + dc);
+                if self.is(pos, player, ChessType::Bishop) && self.chess_at(eye) == Chess::None {
+                    attackers.push(pos);
+                }
+            }
+        }
+
+        // 马，蹩马腿时不能吃，马腿在马的一侧
+        for (dr, dc) in [(-2, -1), (-2, 1), (2, -1), (2, 1), (-1, -2), (1, -2), (-1, 2), (1, 2)] {
+            let pos = Position::new(target.row + dr, target.col + dc);
+            let leg = if dr.abs() == 2 {
+                Position::new(pos.row - dr / 2, pos.col)
+            } else {
+                Position::new(pos.row, pos.col - dc / 2)
+            };
+            if self.is(pos, player, ChessType::Knight) && self.chess_at(leg) == Chess::None {
+                attackers.push(pos);
+            }
+        }
+
+        // 车和炮，沿四个方向找第一个和第二个棋子
+        for (dr, dc) in [(-1, 0), (1, 0), (0, -1), (0, 1)] {
+            let mut pos = Position::new(target.row + dr, target.col + dc);
+            let mut has_screen = false;
+            while in_board(pos) {
+                if self.chess_at(pos) != Chess::None {
+                    if !has_screen {
+                        if self.is(pos, player, ChessType::Rook) {
+                            attackers.push(pos);
+                        }
+                        has_screen = true;
+                    } else {
+                        if self.is(pos, player, ChessType::Cannon) {
+                            attackers.push(pos);
+                        }
+                        break;
+                    }
+                }
+                pos = Position::new(pos.row + dr, pos.col + dc);
+            }
+        }
+
+        // 帅
+        if in_palace(target, player) {
+            for pos in [target.up(1), target.down(1), target.left(1), target.right(1)] {
+                if self.is(pos, player, ChessType::King) {
+                    attackers.push(pos);
+                }
+            }
+        }
+        attackers
+    }
+    // 子力最小的攻击者
+    fn least_attacker(&self, target: Position, player: Player) -> Option<Position> {
+        self.attackers(target, player)
+            .into_iter()
+            .min_by_key(|pos| {
+                self.chess_at(*pos)
+                    .chess_type()
+                    .unwrap()
+                    .see_value()
+            })
+    }
+}
+
+impl Board {
+    // 静态交换评估：双方轮流用最小的子在m.to上交换，返回走子方的得失
+    pub fn see(&self, m: &Move) -> i32 {
+        let mut exchange = Exchange {
+            chesses: self.chesses,
+            chesses_status: self.chesses_status,
+        };
+        let target = m.to;
+        let mut gain = vec![m
+            .capture
+            .chess_type()
+            .map_or(0, |ct| ct.see_value())];
+        let mut attacker_value = m
+            .chess
+            .chess_type()
+            .map_or(0, |ct| ct.see_value());
+        exchange.remove(m.from);
+        exchange.place(target, m.chess);
+        let mut player = m.player.next();
+
+        while let Some(pos) = exchange.least_attacker(target, player) {
+            let d = gain.len();
+            gain.push(attacker_value - gain[d - 1]);
+            // 无论后面怎么换都不会改变结果了
+            if (-gain[d - 1]).max(gain[d]) < 0 {
+                break;
+            }
+            let chess = exchange.chess_at(pos);
+            attacker_value = chess.chess_type().unwrap().see_value();
+            exchange.remove(pos);
+            exchange.place(target, chess);
+            player = player.next();
+        }
+
+        while gain.len() > 1 {
+            let last = gain.pop().unwrap();
+            let d = gain.len() - 1;
+            gain[d] = -(-gain[d]).max(last);
+        }
+        gain[0]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::board::*;
+
+    fn capture(board: &Board, from: Position, to: Position) -> Move {
+        Move {
+            player: board.turn,
+            from,
+            to,
+            chess: board.chess_at(from),
+            capture: board.chess_at(to),
+        }
+    }
+
+    #[test]
+    fn test_see_cannon_screen() {
+        // 炮有炮架保护卒，车吃卒亏
+        let board = Board::from_fen("3k5/c8/b8/9/p7R/9/9/9/9/5K3 w - - 0 1");
+        let m = capture(&board, Position::new(4, 8), Position::new(4, 0));
+        assert_eq!(board.see(&m), 10 - 200);
+
+        // 没有炮架，炮保护不了卒
+        let board = Board::from_fen("3k5/c8/9/9/p7R/9/9/9/9/5K3 w - - 0 1");
+        let m = capture(&board, Position::new(4, 8), Position::new(4, 0));
+        assert_eq!(board.see(&m), 10);
+    }
+
+    #[test]
+    fn test_see_knight_leg() {
+        let board = Board::from_fen("3k5/9/1n7/9/p7R/9/9/9/9/5K3 w - - 0 1");
+        let m = capture(&board, Position::new(4, 8), Position::new(4, 0));
+        assert_eq!(board.see(&m), 10 - 200);
+
+        // 蹩马腿
+        let board = Board::from_fen("3k5/9/1n7/1p7/p7R/9/9/9/9/5K3 w - - 0 1");
+        let m = capture(&board, Position::new(4, 8), Position::new(4, 0));
+        assert_eq!(board.see(&m), 10);
+    }
+
+    #[test]
+    fn test_see_screen_changes() {
+        // 车做炮架，车先吃卒后炮就打不到了
+        let board = Board::from_fen("r2k5/9/9/9/p6RC/9/9/9/9/5K3 w - - 0 1");
+        let m = capture(&board, Position::new(4, 7), Position::new(4, 0));
+        assert_eq!(board.see(&m), 10 - 200);
+
+        // 炮先吃卒，黑车吃炮后红车可以吃回来，所以黑方不会换
+        let m = capture(&board, Position::new(4, 8), Position::new(4, 0));
+        assert_eq!(board.see(&m), 10);
+    }
+}
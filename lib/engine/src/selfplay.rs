@@ -0,0 +1,392 @@
+use crate::board::is_mate_value;
+use crate::board::{Board, Chess, ChessType, Move, Player, Position, RecordTable, BOARD_HEIGHT, BOARD_WIDTH};
+use crate::engine::{search_book, PreLoad};
+use crate::rules::judge;
+use crate::tune::parse_positions;
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use rand::SeedableRng;
+use std::fs;
+use std::path::Path;
+use std::sync::atomic::Ordering;
+use std::sync::{mpsc, Arc};
+use std::thread;
+
+type Squares = [[Chess; BOARD_WIDTH as usize]; BOARD_HEIGHT as usize];
+
+// 每条记录的字节数
+pub const RECORD_BYTES: usize = 64;
+// 自对弈时每个线程的置换表大小
+const SELFPLAY_RECORD_SIZE: usize = 0x10000;
+
+// 棋子的4位编码，0为空，红方为ChessType::value()加1，黑方再加8
+fn encode_chess(chess: Chess) -> u8 {
+    match chess {
+        Chess::Red(ct) => ct.value() as u8 + 1,
+        Chess::Black(ct) => ct.value() as u8 + 9,
+        Chess::None => 0,
+    }
+}
+
+fn decode_chess(code: u8) -> Result<Chess, String> {
+    let types = [
+        ChessType::Pawn,
+        ChessType::King,
+        ChessType::Advisor,
+        ChessType::Bishop,
+        ChessType::Knight,
+        ChessType::Rook,
+        ChessType::Cannon,
+    ];
+    match code {
+        0 => Ok(Chess::None),
+        1..=7 => Ok(Chess::Red(types[code as usize - 1])),
+        9..=15 => Ok(Chess::Black(types[code as usize - 9])),
+        _ => Err(format!("invalid chess code {}", code)),
+    }
+}
+
+// 自对弈中的一个局面
+#[derive(Clone, PartialEq, Debug)]
+pub struct PositionRecord {
+    pub chesses: Squares,
+    pub hidden: Vec<Position>, // 揭棋中还没翻开的棋子
+    pub turn: Player,
+    pub value: i32,      // 走棋方视角的搜索分数
+    pub best_move: Move, // 搜索出的最佳着法
+    pub result: f64,     // 红方视角的对局结果，红胜1，和0.5，黑胜0
+}
+
+// 记录格式，共RECORD_BYTES字节：
+// 0..45 棋盘，每个位置4位，位置i在第i/2字节，偶数位置在低4位
+// 45..57 未翻开的位置，每个位置1位
+// 57 走棋方，0红1黑
+// 58..60 分数，i16小端
+// 60、61 最佳着法的起点和终点，行*9+列
+// 62 对局结果，0黑胜，1和，2红胜
+// 63 保留
+impl PositionRecord {
+    pub fn new(board: &Board, value: i32, mut best_move: Move) -> Self {
+        // 未翻开的棋子记为所在初始位置的棋子，不把真实的棋子泄露给训练
+        let initial = Board::init(false, false);
+        let mut chesses = board.chesses;
+        let mut hidden = vec![];
+        for i in 0..BOARD_HEIGHT {
+            for j in 0..BOARD_WIDTH {
+                let pos = Position::new(i, j);
+                if board.chess_status_at(pos) != Chess::None {
+                    chesses[i as usize][j as usize] = initial.chess_at(pos);
+                    hidden.push(pos);
+                }
+            }
+        }
+        best_move.chess = chesses[best_move.from.row as usize][best_move.from.col as usize];
+        PositionRecord {
+            chesses,
+            hidden,
+            turn: board.turn,
+            value,
+            best_move,
+            result: 0.5,
+        }
+    }
+    pub fn to_bytes(&self) -> [u8; RECORD_BYTES] {
+        let mut data = [0u8; RECORD_BYTES];
+        for i in 0..BOARD_HEIGHT as usize {
+            for j in 0..BOARD_WIDTH as usize {
+                let index = i * BOARD_WIDTH as usize + j;
+                data[index / 2] |= encode_chess(self.chesses[i][j]) << (4 * (index % 2));
+            }
+        }
+        for pos in self.hidden.iter() {
+            let index = (pos.row * BOARD_WIDTH + pos.col) as usize;
+            data[45 + index / 8] |= 1 << (index % 8);
+        }
+        data[57] = self.turn.value() as u8;
+        let value = self.value.clamp(i16::MIN as i32, i16::MAX as i32) as i16;
+        data[58..60].copy_from_slice(&value.to_le_bytes());
+        data[60] = (self.best_move.from.row * BOARD_WIDTH + self.best_move.from.col) as u8;
+        data[61] = (self.best_move.to.row * BOARD_WIDTH + self.best_move.to.col) as u8;
+        data[62] = (self.result * 2.0).round() as u8;
+        data
+    }
+    pub fn from_bytes(data: &[u8]) -> Result<Self, String> {
+        if data.len() != RECORD_BYTES {
+            return Err("invalid record size".to_owned());
+        }
+        let square = |index: u8| -> Result<Position, String> {
+            if index as i32 >= BOARD_WIDTH * BOARD_HEIGHT {
+                return Err(format!("invalid square {}", index));
+            }
+            Ok(Position::new(index as i32 / BOARD_WIDTH, index as i32 % BOARD_WIDTH))
+        };
+        let mut chesses = [[Chess::None; BOARD_WIDTH as usize]; BOARD_HEIGHT as usize];
+        let mut hidden = vec![];
+        for (i, row) in chesses.iter_mut().enumerate() {
+            for (j, chess) in row.iter_mut().enumerate() {
+                let index = i * BOARD_WIDTH as usize + j;
+                *chess = decode_chess((data[index / 2] >> (4 * (index % 2))) & 0xf)?;
+                if data[45 + index / 8] & (1 << (index % 8)) != 0 {
+                    hidden.push(Position::new(i as i32, j as i32));
+                }
+            }
+        }
+        let turn = match data[57] {
+            0 => Player::Red,
+            1 => Player::Black,
+            v => return Err(format!("invalid turn {}", v)),
+        };
+        let (from, to) = (square(data[60])?, square(data[61])?);
+        if data[62] > 2 {
+            return Err(format!("invalid result {}", data[62]));
+        }
+        Ok(PositionRecord {
+            chesses,
+            hidden,
+            turn,
+            value: i16::from_le_bytes([data[58], data[59]]) as i32,
+            best_move: Move {
+                player: turn,
+                from,
+                to,
+                chess: chesses[from.row as usize][from.col as usize],
+                capture: chesses[to.row as usize][to.col as usize],
+            },
+            result: data[62] as f64 / 2.0,
+        })
+    }
+    // 还原棋盘，未翻开的棋子按所在的初始位置走
+    pub fn to_board(&self) -> Board {
+        let mut board = Board::from_fen(&self.fen());
+        let initial = Board::init(false, false);
+        for pos in self.hidden.iter() {
+            board.chesses_status[pos.row as usize][pos.col as usize] = initial.chess_at(*pos);
+        }
+        board.jieqi = !self.hidden.is_empty();
+        board
+    }
+    pub fn fen(&self) -> String {
+        let mut board = Board::empty();
+        board.chesses = self.chesses;
+        board.turn = self.turn;
+        board.to_fen()
+    }
+}
+
+pub fn read_records(data: &[u8]) -> Result<Vec<PositionRecord>, String> {
+    if !data.len().is_multiple_of(RECORD_BYTES) {
+        return Err("data size is not a multiple of the record size".to_owned());
+    }
+    data.chunks(RECORD_BYTES)
+        .map(PositionRecord::from_bytes)
+        .collect()
+}
+
+// 读入带对局结果的局面，.bin文件为自对弈记录，其他为文本
+pub fn load_positions(path: impl AsRef<Path>) -> Result<Vec<(String, f64)>, String> {
+    let path = path.as_ref();
+    let error = |e: std::io::Error| format!("{}: {}", path.display(), e);
+    if path.extension().is_some_and(|ext| ext == "bin") {
+        Ok(read_records(&fs::read(path).map_err(error)?)?
+            .into_iter()
+            .map(|record| (record.fen(), record.result))
+            .collect())
+    } else {
+        parse_positions(&fs::read_to_string(path).map_err(error)?)
+    }
+}
+
+// 文本格式：FEN;分数;最佳着法;结果，可以直接用于调参
+pub fn record_line(record: &PositionRecord) -> String {
+    format!(
+        "{};{};{}{};{}",
+        record.fen(),
+        record.value,
+        record.best_move.from.to_string(),
+        record.best_move.to.to_string(),
+        record.result
+    )
+}
+
+// 自对弈的设置
+#[derive(Clone, Debug)]
+pub struct SelfPlayConfig {
+    pub depth: i32,          // 每步的最大搜索深度
+    pub nodes: Option<i32>,  // 每步的节点数，搜完一层后超过就停止
+    pub random_plies: usize, // 开局随机走的步数
+    pub use_book: bool,      // 开局先按开局库走
+    pub jieqi: bool,         // 揭棋
+    pub max_plies: usize,    // 超过这个步数判和
+}
+
+impl Default for SelfPlayConfig {
+    fn default() -> Self {
+        SelfPlayConfig {
+            depth: 6,
+            nodes: None,
+            random_plies: 8,
+            use_book: true,
+            jieqi: false,
+            max_plies: 300,
+        }
+    }
+}
+
+// 按节点数或深度搜索一步
+fn search(board: &mut Board, config: &SelfPlayConfig) -> (i32, Option<Move>) {
+    board.counter = 0;
+    board.stop.store(false, Ordering::Relaxed);
+    let stop = board.stop.clone();
+    let nodes = config.nodes;
+    board.iterative_deepening_with(config.depth, |result| {
+        if nodes.is_some_and(|n| result.nodes >= n) {
+            stop.store(true, Ordering::Relaxed);
+        }
+    })
+}
+
+// 下一盘棋，返回记录的局面。开局阶段、被将军和最佳着法是吃子的局面不记录
+pub fn play_game(config: &SelfPlayConfig, book: &[PreLoad], rng: &mut StdRng) -> Vec<PositionRecord> {
+    let mut board = Board::init(config.jieqi, false);
+    board.records = Arc::new(RecordTable::new(SELFPLAY_RECORD_SIZE));
+    let mut records = vec![];
+    let mut in_book = config.use_book && !config.jieqi;
+    let mut result = 0.5;
+    for ply in 0..config.max_plies {
+        // 和对战一样按规则裁决：将死、困毙、长将和重复局面
+        if let Some((game_result, _)) = judge(&board) {
+            result = game_result.score();
+            break;
+        }
+        let moves = board.legal_moves();
+        let book_move = if in_book {
+            search_book(book, &board).and_then(|s| board.parse_move(&s))
+        } else {
+            None
+        };
+        let m = if let Some(m) = book_move {
+            m
+        } else if ply < config.random_plies {
+            in_book = false;
+            moves.choose(rng).unwrap().clone()
+        } else {
+            in_book = false;
+            let (value, best_move) = search(&mut board, config);
+            let m = best_move.unwrap_or_else(|| moves[0].clone());
+            if !is_mate_value(value) && !board.is_checked(board.turn) && m.capture == Chess::None {
+                records.push(PositionRecord::new(&board, value, m.clone()));
+            }
+            m
+        };
+        board.do_move(&m, config.jieqi);
+    }
+    for record in records.iter_mut() {
+        record.result = result;
+    }
+    records
+}
+
+// 用threads个线程下games盘棋，每下完一盘在调用线程中调用on_game
+pub fn generate(
+    config: &SelfPlayConfig,
+    book: &[PreLoad],
+    games: usize,
+    threads: usize,
+    seed: u64,
+    mut on_game: impl FnMut(Vec<PositionRecord>),
+) {
+    let threads = threads.max(1).min(games.max(1));
+    let (sender, receiver) = mpsc::channel();
+    thread::scope(|s| {
+        for t in 0..threads {
+            let sender = sender.clone();
+            s.spawn(move || {
+                let mut rng = StdRng::seed_from_u64(seed.wrapping_add(t as u64));
+                // 第t个线程下第t、t+threads、t+2*threads……盘
+                for _ in (t..games).step_by(threads) {
+                    if sender
+                        .send(play_game(config, book, &mut rng))
+                        .is_err()
+                    {
+                        break;
+                    }
+                }
+            });
+        }
+        drop(sender);
+        for records in receiver {
+            on_game(records);
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::board::*;
+    use crate::selfplay::*;
+
+    #[test]
+    fn test_record_bytes() {
+        let mut board = Board::from_fen("rnbakabnr/9/1c5c1/p1p1p1p1p/9/9/P1P1P1P1P/1C5C1/9/RNBAKABNR b - - 0 1");
+        // 左上角未翻开的棋子其实是炮，记录里只能看到车
+        board.chesses[0][0] = Chess::Black(ChessType::Cannon);
+        board.chesses_status[0][0] = Chess::Black(ChessType::Rook);
+        let m = Move {
+            player: Player::Black,
+            from: Position::new(2, 1),
+            to: Position::new(2, 4),
+            chess: Chess::Black(ChessType::Cannon),
+            capture: Chess::None,
+        };
+        let mut record = PositionRecord::new(&board, -35, m);
+        record.result = 1.0;
+        let data = record.to_bytes();
+        assert_eq!(PositionRecord::from_bytes(&data), Ok(record.clone()));
+        assert_eq!(record.hidden, vec![Position::new(0, 0)]);
+        assert_eq!(record.chesses[0][0], Chess::Black(ChessType::Rook));
+        assert_eq!(
+            record.fen(),
+            "rnbakabnr/9/1c5c1/p1p1p1p1p/9/9/P1P1P1P1P/1C5C1/9/RNBAKABNR b - - 0 1"
+        );
+        assert!(record.to_board().jieqi);
+        assert!(read_records(&data[..10]).is_err());
+    }
+
+    #[test]
+    fn test_play_game() {
+        let config = SelfPlayConfig {
+            depth: 2,
+            random_plies: 4,
+            use_book: false,
+            max_plies: 30,
+            ..Default::default()
+        };
+        let mut rng = StdRng::seed_from_u64(1);
+        let records = play_game(&config, &[], &mut rng);
+        assert!(!records.is_empty());
+        for record in records.iter() {
+            let board = record.to_board();
+            assert!(board.is_pseudo_legal(&record.best_move));
+            assert_eq!(record.result, records[0].result);
+        }
+    }
+
+    #[test]
+    fn test_generate() {
+        let config = SelfPlayConfig {
+            depth: 1,
+            nodes: Some(100),
+            jieqi: true,
+            max_plies: 20,
+            ..Default::default()
+        };
+        let mut games = 0;
+        let mut positions = 0;
+        generate(&config, &[], 5, 3, 0, |records| {
+            games += 1;
+            positions += records.len();
+        });
+        assert_eq!(games, 5);
+        assert!(positions > 0);
+    }
+}
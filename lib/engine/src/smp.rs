@@ -0,0 +1,175 @@
+use crate::board::{Board, DepthResult, Move, RecordTable};
+use crate::constant::RECORD_SIZE;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+// 辅助线程错开搜索的层数：第i个辅助线程把层数按SKIP_SIZE[i]分组、错开SKIP_PHASE[i]，
+// 隔一组搜一组，各线程的起始深度和搜索的层都不一样，置换表和走法排序的内容也就不同
+const SKIP_SIZE: [i32; 20] = [1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 3, 3, 4, 4, 4, 4, 4, 4, 4, 4];
+const SKIP_PHASE: [i32; 20] = [0, 1, 0, 1, 2, 3, 0, 1, 2, 3, 4, 5, 0, 1, 2, 3, 4, 5, 6, 7];
+
+fn skip_depth(helper: usize, depth: i32) -> bool {
+    let i = helper % SKIP_SIZE.len();
+    (depth + SKIP_PHASE[i]) / SKIP_SIZE[i] % 2 != 0
+}
+
+impl Board {
+    // 多线程搜索（Lazy SMP）：各线程在自己的棋盘副本上搜索，通过共享的置换表交换结果，
+    // 辅助线程跳过不同的层，主线程搜索完毕后通知辅助线程停止。
+    // threads为1时不创建线程，搜索结果是确定的
+    pub fn parallel_search(&mut self, max_depth: i32, threads: usize) -> (i32, Option<Move>) {
+        self.parallel_search_with(max_depth, threads, |_| {})
+    }
+    // 主线程每搜完一层调用一次callback
+    pub fn parallel_search_with(
+        &mut self,
+        max_depth: i32,
+        threads: usize,
+        callback: impl FnMut(&DepthResult),
+    ) -> (i32, Option<Move>) {
+        if threads <= 1 {
+            return self.iterative_deepening_with(max_depth, callback);
+        }
+        if self.records.is_empty() {
+            self.records = Arc::new(RecordTable::new(RECORD_SIZE as usize));
+        }
+
+        let stop = Arc::new(AtomicBool::new(false));
+        let helpers: Vec<Board> = (1..threads)
+            .map(|_| {
+                let mut helper = self.clone();
+                helper.stop = stop.clone();
+                helper.counter = 0;
+                helper.gen_counter = 0;
+                helper
+            })
+            .collect();
+
+        thread::scope(|s| {
+            let handles: Vec<_> = helpers
+                .into_iter()
+                .enumerate()
+                .map(|(i, mut helper)| {
+                    s.spawn(move || {
+                        helper.iterative_deepening_skip(max_depth + 1, |depth| skip_depth(i, depth), |_| {});
+                        (helper.counter, helper.gen_counter)
+                    })
+                })
+                .collect();
+
+            let result = self.iterative_deepening_with(max_depth, callback);
+            stop.store(true, Ordering::Relaxed);
+            for handle in handles {
+                let (counter, gen_counter) = handle.join().unwrap();
+                self.counter += counter;
+                self.gen_counter += gen_counter;
+            }
+            result
+        })
+    }
+    // 限时搜索：time为None时只按深度搜索，否则到时间后停止，返回已搜完的最深一层的结果
+    pub fn timed_search_with(
+        &mut self,
+        max_depth: i32,
+        threads: usize,
+        time: Option<Duration>,
+        callback: impl FnMut(&DepthResult),
+    ) -> (i32, Option<Move>) {
+        self.with_time_limit(time, |board| board.parallel_search_with(max_depth, threads, callback))
+    }
+    // 在限定时间内执行search，到时间后设置停止标志，搜索结束后清除。
+    // 开始时不清除停止标志，搜索开始前收到的stop仍然有效
+    pub fn with_time_limit<T>(&mut self, time: Option<Duration>, search: impl FnOnce(&mut Board) -> T) -> T {
+        let stop = self.stop.clone();
+        let (done, timer) = mpsc::channel::<()>();
+        let result = thread::scope(|s| {
+            if let Some(time) = time {
+                s.spawn(move || {
+                    // 搜索提前结束时done被丢弃，计时线程随之退出
+                    if let Err(RecvTimeoutError::Timeout) = timer.recv_timeout(time) {
+                        stop.store(true, Ordering::Relaxed);
+                    }
+                });
+            }
+            let result = search(self);
+            drop(done);
+            result
+        });
+        self.stop.store(false, Ordering::Relaxed);
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::board::*;
+    use crate::constant::MAX_DEPTH;
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn test_parallel_search() {
+        let fen = "rnbakabnr/9/1c5c1/p1p1p1p1p/9/9/P1P1P1P1P/1C5C1/9/RNBAKABNR w - - 0 1";
+        let mut board = Board::from_fen(fen);
+        let (value, best_move) = board.parallel_search(4, 4);
+        assert!(best_move.unwrap().is_valid());
+        assert!(value.abs() < 1000);
+        // 搜索完毕后棋盘恢复原状
+        assert_eq!(board.zobrist_value, Board::from_fen(fen).zobrist_value);
+    }
+
+    #[test]
+    fn test_single_thread_deterministic() {
+        let fen = "rnbakabnr/9/1c5c1/p1p1p1p1p/9/9/P1P1P1P1P/1C5C1/9/RNBAKABNR w - - 0 1";
+        let first = Board::from_fen(fen).parallel_search(4, 1);
+        let second = Board::from_fen(fen).parallel_search(4, 1);
+        assert_eq!(first, second);
+    }
+
+    #[test]
+    fn test_skip_depth() {
+        use crate::smp::skip_depth;
+
+        let depths = |helper| {
+            (1..=8)
+                .filter(|&depth| !skip_depth(helper, depth))
+                .collect::<Vec<i32>>()
+        };
+        assert_eq!(depths(0), vec![2, 4, 6, 8]);
+        assert_eq!(depths(1), vec![1, 3, 5, 7]);
+        assert_eq!(depths(2), vec![1, 4, 5, 8]);
+        assert_eq!(depths(3), vec![3, 4, 7, 8]);
+    }
+
+    #[test]
+    fn test_parallel_search_mate() {
+        let mut board = Board::from_fen("4k4/9/9/9/9/9/9/4p4/9/5K3 b - - 0 1");
+        let (value, _) = board.parallel_search(4, 3);
+        assert_eq!(value, -mated_value(1));
+    }
+
+    #[test]
+    fn test_timed_search() {
+        let fen = "rnbakabnr/9/1c5c1/p1p1p1p1p/9/9/P1P1P1P1P/1C5C1/9/RNBAKABNR w - - 0 1";
+        let mut board = Board::from_fen(fen);
+        let start = Instant::now();
+        let (_, best_move) = board.timed_search_with(MAX_DEPTH, 2, Some(Duration::from_millis(100)), |_| {});
+        assert!(best_move.unwrap().is_valid());
+        assert!(start.elapsed().as_secs() < 5);
+        assert!(!board.is_stopped());
+    }
+
+    #[test]
+    fn test_stopped_before_search() {
+        // 第一层没搜完就停止时仍然给出合法的着法
+        let mut board = Board::from_fen("rnbakabnr/9/1c5c1/p1p1p1p1p/9/9/P1P1P1P1P/1C5C1/9/RNBAKABNR w - - 0 1");
+        board
+            .stop
+            .store(true, std::sync::atomic::Ordering::Relaxed);
+        let (_, best_move) = board.timed_search_with(MAX_DEPTH, 1, None, |_| {});
+        assert!(board.legal_moves().contains(&best_move.unwrap()));
+        assert!(!board.is_stopped());
+    }
+}
@@ -0,0 +1,797 @@
+use crate::board::{mated_value, Board, Chess, ChessType, Move, Player, Position, BOARD_HEIGHT, BOARD_WIDTH};
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU16, Ordering};
+use std::sync::{Arc, RwLock};
+use std::thread;
+
+// 残局库：对车马炮兵不多的残局（可以带士相），从将死和困毙的局面往前逆推，
+// 算出每个局面的胜负和到将死的半回合数。按棋规困毙算负，对将的局面不合法；
+// 长将、长捉等禁着不考虑，无法分出胜负的循环局面都算和棋
+
+const MAGIC: &[u8; 4] = b"CCTB";
+const VERSION: u32 = 1;
+// 编码：0为和，ILLEGAL为不合法的局面，其他为到将死的半回合数加1
+const ILLEGAL: u16 = u16::MAX;
+// 同一种子最多的个数（兵）
+const MAX_SAME: usize = 5;
+const SQUARES: usize = (BOARD_WIDTH * BOARD_HEIGHT) as usize;
+// 子在表中的排列顺序，进攻子力在前
+const ORDER: [ChessType; 6] = [
+    ChessType::Rook,
+    ChessType::Knight,
+    ChessType::Cannon,
+    ChessType::Pawn,
+    ChessType::Advisor,
+    ChessType::Bishop,
+];
+
+fn letter(ct: ChessType) -> char {
+    match ct {
+        ChessType::King => 'K',
+        ChessType::Advisor => 'A',
+        ChessType::Bishop => 'B',
+        ChessType::Knight => 'N',
+        ChessType::Rook => 'R',
+        ChessType::Cannon => 'C',
+        ChessType::Pawn => 'P',
+    }
+}
+
+fn order(ct: ChessType) -> usize {
+    ORDER
+        .iter()
+        .position(|t| *t == ct)
+        .unwrap_or(ORDER.len())
+}
+
+// 棋子的编号，红方为0..7，黑方为7..14
+fn chess_code(chess: Chess) -> Option<usize> {
+    let ct = chess.chess_type()?;
+    Some(chess.player()?.value() as usize * 7 + ct.value() as usize)
+}
+
+// 子可能出现的位置，黑方的位置由红方的翻转得到
+fn squares(player: Player, ct: ChessType) -> Vec<Position> {
+    let red: Vec<Position> = match ct {
+        ChessType::King => (7..10)
+            .flat_map(|i| (3..6).map(move |j| Position::new(i, j)))
+            .collect(),
+        ChessType::Advisor => [(9, 3), (9, 5), (8, 4), (7, 3), (7, 5)]
+            .iter()
+            .map(|&(i, j)| Position::new(i, j))
+            .collect(),
+        ChessType::Bishop => [(9, 2), (9, 6), (7, 0), (7, 4), (7, 8), (5, 2), (5, 6)]
+            .iter()
+            .map(|&(i, j)| Position::new(i, j))
+            .collect(),
+        // 没过河的兵只能在自己的兵线上
+        ChessType::Pawn => (0..BOARD_HEIGHT)
+            .flat_map(|i| (0..BOARD_WIDTH).map(move |j| Position::new(i, j)))
+            .filter(|p| p.row < 5 || (p.row < 7 && p.col % 2 == 0))
+            .collect(),
+        _ => (0..BOARD_HEIGHT)
+            .flat_map(|i| (0..BOARD_WIDTH).map(move |j| Position::new(i, j)))
+            .collect(),
+    };
+    if player == Player::Red {
+        red
+    } else {
+        red.iter().map(|p| p.flip()).collect()
+    }
+}
+
+// 子力组合，不含将帅，名字如KRvKNAA
+#[derive(Clone, Debug, PartialEq)]
+pub struct Material {
+    pub red: Vec<ChessType>,
+    pub black: Vec<ChessType>,
+}
+
+impl Material {
+    pub fn new(mut red: Vec<ChessType>, mut black: Vec<ChessType>) -> Self {
+        red.sort_by_key(|ct| order(*ct));
+        black.sort_by_key(|ct| order(*ct));
+        Material { red, black }
+    }
+    pub fn parse(name: &str) -> Result<Self, String> {
+        let error = || format!("invalid material {}", name);
+        let (red, black) = name.split_once('v').ok_or_else(error)?;
+        let side = |s: &str| -> Result<Vec<ChessType>, String> {
+            let pieces = s.strip_prefix('K').ok_or_else(error)?;
+            let pieces = pieces
+                .chars()
+                .map(|c| {
+                    ORDER
+                        .iter()
+                        .find(|ct| letter(**ct) == c)
+                        .copied()
+                        .ok_or_else(error)
+                })
+                .collect::<Result<Vec<ChessType>, String>>()?;
+            // 每种子的个数不能超过开局时的个数
+            for ct in ORDER {
+                let limit = if ct == ChessType::Pawn { 5 } else { 2 };
+                if pieces.iter().filter(|t| **t == ct).count() > limit {
+                    return Err(error());
+                }
+            }
+            Ok(pieces)
+        };
+        Ok(Material::new(side(red)?, side(black)?))
+    }
+    pub fn name(&self) -> String {
+        let side = |pieces: &[ChessType]| -> String { pieces.iter().map(|ct| letter(*ct)).collect() };
+        format!("K{}vK{}", side(&self.red), side(&self.black))
+    }
+    // 局面中的子力组合
+    pub fn of_board(board: &Board) -> Self {
+        let (mut red, mut black) = (vec![], vec![]);
+        for row in board.chesses.iter() {
+            for chess in row.iter() {
+                match chess {
+                    Chess::Red(ct) if *ct != ChessType::King => red.push(*ct),
+                    Chess::Black(ct) if *ct != ChessType::King => black.push(*ct),
+                    _ => {}
+                }
+            }
+        }
+        Material::new(red, black)
+    }
+    // 双方的子数
+    pub fn count(&self) -> usize {
+        self.red.len() + self.black.len()
+    }
+    pub fn swapped(&self) -> Self {
+        Material::new(self.black.clone(), self.red.clone())
+    }
+    // 红黑互换的两个组合只需要一张表，规定红方的子力不弱于黑方
+    pub fn is_canonical(&self) -> bool {
+        strength(&self.red) >= strength(&self.black)
+    }
+    pub fn canonical(&self) -> Self {
+        if self.is_canonical() {
+            self.clone()
+        } else {
+            self.swapped()
+        }
+    }
+    // 吃掉一个子以后的子力组合
+    pub fn captures(&self) -> Vec<Material> {
+        let mut result: Vec<Material> = vec![];
+        let remove = |pieces: &[ChessType], i: usize| -> Vec<ChessType> {
+            let mut pieces = pieces.to_vec();
+            pieces.remove(i);
+            pieces
+        };
+        for i in 0..self.red.len() {
+            result.push(Material::new(remove(&self.red, i), self.black.clone()).canonical());
+        }
+        for i in 0..self.black.len() {
+            result.push(Material::new(self.red.clone(), remove(&self.black, i)).canonical());
+        }
+        let mut unique: Vec<Material> = vec![];
+        for material in result {
+            if !unique.contains(&material) {
+                unique.push(material);
+            }
+        }
+        unique
+    }
+}
+
+// 比较双方子力的强弱：先比进攻子力的个数和种类，再比总子数和种类
+fn strength(pieces: &[ChessType]) -> (usize, Vec<usize>, usize, Vec<usize>) {
+    let values: Vec<usize> = pieces
+        .iter()
+        .map(|ct| ORDER.len() - order(*ct))
+        .collect();
+    let attackers: Vec<usize> = pieces
+        .iter()
+        .filter(|ct| !matches!(ct, ChessType::Advisor | ChessType::Bishop))
+        .map(|ct| ORDER.len() - order(*ct))
+        .collect();
+    (attackers.len(), attackers, pieces.len(), values)
+}
+
+// 查询结果，走棋方的胜、和、负，步数为到将死的半回合数
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Outcome {
+    Win(u16),
+    Draw,
+    Loss(u16),
+}
+
+impl Outcome {
+    fn from_code(code: u16) -> Option<Self> {
+        match code {
+            ILLEGAL => None,
+            0 => Some(Outcome::Draw),
+            _ if code % 2 == 1 => Some(Outcome::Loss(code - 1)),
+            _ => Some(Outcome::Win(code - 1)),
+        }
+    }
+    fn code(&self) -> u16 {
+        match self {
+            Outcome::Draw => 0,
+            Outcome::Win(plies) | Outcome::Loss(plies) => plies + 1,
+        }
+    }
+    // 走棋方视角的局面分，胜负为杀棋分数，ply为局面距离搜索根节点的步数
+    pub fn score(&self, ply: i32) -> i32 {
+        match self {
+            Outcome::Win(plies) => -mated_value(ply + *plies as i32),
+            Outcome::Draw => 0,
+            Outcome::Loss(plies) => mated_value(ply + *plies as i32),
+        }
+    }
+}
+
+// 表中的一个子：棋子、可能的位置，以及位置到序号的映射
+struct Slot {
+    chess: Chess,
+    squares: Vec<Position>,
+    lookup: [u8; SQUARES],
+}
+
+// 一个子力组合的残局表，局面的编号为各子位置序号组成的混合进制数，乘2再加走棋方
+pub struct Table {
+    pub material: Material,
+    slots: Vec<Slot>,
+    size: usize,
+    dtm: Vec<u16>,
+}
+
+impl Table {
+    fn new(material: Material) -> Self {
+        let mut pieces = vec![Chess::Red(ChessType::King), Chess::Black(ChessType::King)];
+        pieces.extend(material.red.iter().map(|ct| Chess::Red(*ct)));
+        pieces.extend(material.black.iter().map(|ct| Chess::Black(*ct)));
+        let slots: Vec<Slot> = pieces
+            .into_iter()
+            .map(|chess| {
+                let squares = squares(chess.player().unwrap(), chess.chess_type().unwrap());
+                let mut lookup = [u8::MAX; SQUARES];
+                for (i, p) in squares.iter().enumerate() {
+                    lookup[(p.row * BOARD_WIDTH + p.col) as usize] = i as u8;
+                }
+                Slot { chess, squares, lookup }
+            })
+            .collect();
+        let size = slots.iter().map(|s| s.squares.len()).product();
+        Table {
+            material,
+            slots,
+            size,
+            dtm: vec![],
+        }
+    }
+    // 局面数（双方走棋分开算）
+    pub fn len(&self) -> usize {
+        self.size * 2
+    }
+    pub fn is_empty(&self) -> bool {
+        self.size == 0
+    }
+    // 局面的编号，flip为true时红黑互换后再编号
+    fn index(&self, board: &Board, flip: bool) -> Option<usize> {
+        let mut found = [[0u8; MAX_SAME]; 14];
+        let mut counts = [0usize; 14];
+        for i in 0..BOARD_HEIGHT {
+            for j in 0..BOARD_WIDTH {
+                let mut pos = Position::new(i, j);
+                let mut chess = board.chess_at(pos);
+                if flip {
+                    pos = pos.flip();
+                    chess = match chess {
+                        Chess::Red(ct) => Chess::Black(ct),
+                        Chess::Black(ct) => Chess::Red(ct),
+                        Chess::None => Chess::None,
+                    };
+                }
+                if let Some(code) = chess_code(chess) {
+                    if counts[code] >= MAX_SAME {
+                        return None;
+                    }
+                    found[code][counts[code]] = (pos.row * BOARD_WIDTH + pos.col) as u8;
+                    counts[code] += 1;
+                }
+            }
+        }
+        let mut index = 0;
+        let mut start = 0;
+        while start < self.slots.len() {
+            // 同一种子的位置序号从小到大排列
+            let chess = self.slots[start].chess;
+            let end = start
+                + self.slots[start..]
+                    .iter()
+                    .take_while(|s| s.chess == chess)
+                    .count();
+            let code = chess_code(chess).unwrap();
+            if counts[code] != end - start {
+                return None;
+            }
+            let mut digits = [0u8; MAX_SAME];
+            for (k, square) in found[code][..counts[code]].iter().enumerate() {
+                digits[k] = self.slots[start].lookup[*square as usize];
+                if digits[k] == u8::MAX {
+                    return None;
+                }
+            }
+            digits[..counts[code]].sort();
+            for (k, slot) in self.slots[start..end].iter().enumerate() {
+                index = index * slot.squares.len() + digits[k] as usize;
+            }
+            counts[code] = 0;
+            start = end;
+        }
+        // 局面中不能有表外的子
+        if counts.iter().any(|c| *c > 0) {
+            return None;
+        }
+        let turn = if flip { board.turn.next() } else { board.turn };
+        Some(index * 2 + turn.value() as usize)
+    }
+    // 按编号摆出局面，同一种子的序号不是从小到大或者两个子在同一格时返回false
+    fn setup(&self, index: usize, board: &mut Board, placed: &mut Vec<Position>) -> bool {
+        for pos in placed.drain(..) {
+            board.set_chess(pos, Chess::None, false);
+        }
+        board.turn = if index % 2 == 1 { Player::Black } else { Player::Red };
+        let mut rest = index / 2;
+        let mut digits = vec![0; self.slots.len()];
+        for (k, slot) in self.slots.iter().enumerate().rev() {
+            digits[k] = rest % slot.squares.len();
+            rest /= slot.squares.len();
+        }
+        for (k, slot) in self.slots.iter().enumerate() {
+            if k > 0 && self.slots[k - 1].chess == slot.chess && digits[k - 1] >= digits[k] {
+                return false;
+            }
+            let pos = slot.squares[digits[k]];
+            if board.chess_at(pos) != Chess::None {
+                return false;
+            }
+            board.set_chess(pos, slot.chess, false);
+            placed.push(pos);
+        }
+        true
+    }
+    // 查询局面，局面的子力组合必须和表一致（或者红黑互换后一致）
+    pub fn probe(&self, board: &Board) -> Option<Outcome> {
+        let flip = Material::of_board(board) != self.material;
+        let index = self.index(board, flip)?;
+        Outcome::from_code(*self.dtm.get(index)?)
+    }
+    // 最长的杀棋步数
+    pub fn longest(&self) -> u16 {
+        self.dtm
+            .iter()
+            .filter(|c| **c != ILLEGAL && **c != 0)
+            .map(|c| c - 1)
+            .max()
+            .unwrap_or(0)
+    }
+    // 走棋方胜、和、负的局面数，不合法的局面不计
+    pub fn stats(&self) -> (usize, usize, usize) {
+        let (mut wins, mut draws, mut losses) = (0, 0, 0);
+        for code in self.dtm.iter() {
+            match Outcome::from_code(*code) {
+                Some(Outcome::Win(_)) => wins += 1,
+                Some(Outcome::Draw) => draws += 1,
+                Some(Outcome::Loss(_)) => losses += 1,
+                None => {}
+            }
+        }
+        (wins, draws, losses)
+    }
+    // 胜和负文件每个局面一个字节：0和，1胜，2负，3不合法
+    pub fn wdl_bytes(&self) -> Vec<u8> {
+        let mut data = self.header();
+        data.extend(
+            self.dtm
+                .iter()
+                .map(|code| match Outcome::from_code(*code) {
+                    Some(Outcome::Draw) => 0,
+                    Some(Outcome::Win(_)) => 1,
+                    Some(Outcome::Loss(_)) => 2,
+                    None => 3,
+                }),
+        );
+        data
+    }
+    // 步数文件每个局面两个字节，为编码后的步数
+    pub fn dtm_bytes(&self) -> Vec<u8> {
+        let mut data = self.header();
+        for code in self.dtm.iter() {
+            data.extend(code.to_le_bytes());
+        }
+        data
+    }
+    fn header(&self) -> Vec<u8> {
+        let name = self.material.name();
+        let mut data = MAGIC.to_vec();
+        data.extend(VERSION.to_le_bytes());
+        data.push(name.len() as u8);
+        data.extend(name.as_bytes());
+        data.extend((self.len() as u64).to_le_bytes());
+        data
+    }
+    // 读入步数文件
+    pub fn from_dtm_bytes(data: &[u8]) -> Result<Self, String> {
+        let (mut table, body) = Table::read_header(data)?;
+        if body.len() != table.len() * 2 {
+            return Err("tablebase file size mismatch".to_owned());
+        }
+        table.dtm = body
+            .chunks_exact(2)
+            .map(|b| u16::from_le_bytes([b[0], b[1]]))
+            .collect();
+        Ok(table)
+    }
+    fn read_header(data: &[u8]) -> Result<(Self, &[u8]), String> {
+        if data.len() < 9 || &data[..4] != MAGIC {
+            return Err("not a tablebase file".to_owned());
+        }
+        let version = u32::from_le_bytes(data[4..8].try_into().unwrap());
+        if version != VERSION {
+            return Err(format!("unsupported tablebase version {}", version));
+        }
+        let name_len = data[8] as usize;
+        if data.len() < 9 + name_len + 8 {
+            return Err("truncated tablebase file".to_owned());
+        }
+        let name = std::str::from_utf8(&data[9..9 + name_len]).map_err(|e| e.to_string())?;
+        let table = Table::new(Material::parse(name)?);
+        let len = u64::from_le_bytes(
+            data[9 + name_len..17 + name_len]
+                .try_into()
+                .unwrap(),
+        ) as usize;
+        if len != table.len() {
+            return Err("tablebase size mismatch".to_owned());
+        }
+        Ok((table, &data[17 + name_len..]))
+    }
+    pub fn save(&self, dir: impl AsRef<Path>) -> Result<(), String> {
+        let dir = dir.as_ref();
+        fs::create_dir_all(dir).map_err(|e| format!("{}: {}", dir.display(), e))?;
+        let name = self.material.name();
+        for (path, data) in [
+            (dir.join(format!("{}.wdl", name)), self.wdl_bytes()),
+            (dir.join(format!("{}.dtm", name)), self.dtm_bytes()),
+        ] {
+            fs::write(&path, data).map_err(|e| format!("{}: {}", path.display(), e))?;
+        }
+        Ok(())
+    }
+}
+
+// 残局库集合，按规范的子力组合名缓存已生成或读入的表，搜索的各线程共用
+pub struct Tablebase {
+    pub dir: Option<PathBuf>,
+    // 目录中没有的表也记下来，不必每次都去读文件
+    tables: RwLock<HashMap<String, Option<Arc<Table>>>>,
+    // 可以查询的最多子数（不含将帅），子数更多时直接返回
+    max_count: usize,
+}
+
+impl Tablebase {
+    pub fn new(dir: Option<PathBuf>) -> Self {
+        // 按文件名统计目录中的表最多有几个子
+        let max_count = dir
+            .as_ref()
+            .and_then(|dir| fs::read_dir(dir).ok())
+            .map_or(0, |entries| {
+                entries
+                    .filter_map(|entry| entry.ok())
+                    .filter_map(|entry| {
+                        let name = entry.file_name().into_string().ok()?;
+                        Material::parse(name.strip_suffix(".dtm")?).ok()
+                    })
+                    .map(|material| material.count())
+                    .max()
+                    .unwrap_or(0)
+            });
+        Tablebase {
+            dir,
+            tables: RwLock::new(HashMap::new()),
+            max_count,
+        }
+    }
+    // 取得子力组合的表，内存中没有时从目录中读入
+    pub fn table(&self, material: &Material) -> Option<Arc<Table>> {
+        let name = material.canonical().name();
+        if let Some(table) = self.tables.read().unwrap().get(&name) {
+            return table.clone();
+        }
+        let table = self
+            .dir
+            .as_ref()
+            .and_then(|dir| fs::read(dir.join(format!("{}.dtm", name))).ok())
+            .and_then(|data| Table::from_dtm_bytes(&data).ok())
+            .map(Arc::new);
+        self.tables
+            .write()
+            .unwrap()
+            .insert(name, table.clone());
+        table
+    }
+    // 查询局面，没有对应的表时返回None
+    pub fn probe(&self, board: &Board) -> Option<Outcome> {
+        let material = Material::of_board(board);
+        if material.count() > self.max_count {
+            return None;
+        }
+        self.table(&material)?.probe(board)
+    }
+    // 生成子力组合的表，吃子后的表先生成（目录中已有时直接读入），生成的表保存到目录中
+    pub fn generate(
+        &mut self,
+        material: &Material,
+        threads: usize,
+        progress: &mut dyn FnMut(&Table),
+    ) -> Result<Arc<Table>, String> {
+        let material = material.canonical();
+        if let Some(table) = self.table(&material) {
+            return Ok(table);
+        }
+        for sub in material.captures() {
+            self.generate(&sub, threads, progress)?;
+        }
+        let table = self.build(material, threads.max(1));
+        if let Some(dir) = &self.dir {
+            table.save(dir)?;
+        }
+        progress(&table);
+        let table = Arc::new(table);
+        self.max_count = self.max_count.max(table.material.count());
+        self.tables
+            .write()
+            .unwrap()
+            .insert(table.material.name(), Some(table.clone()));
+        Ok(table)
+    }
+    // 逆向分析：先找出将死和困毙的局面，第k轮找出k个半回合后分出胜负的局面，
+    // 直到没有新的局面并且吃子后的表中也没有更长的杀棋为止，剩下的局面为和棋
+    fn build(&self, material: Material, threads: usize) -> Table {
+        let mut table = Table::new(material);
+        let dtm: Vec<AtomicU16> = (0..table.len())
+            .map(|_| AtomicU16::new(0))
+            .collect();
+        let longest_sub = table
+            .material
+            .captures()
+            .iter()
+            .filter_map(|m| self.table(m))
+            .map(|t| t.longest())
+            .max()
+            .unwrap_or(0);
+        let mut ply = 0;
+        loop {
+            let changed = AtomicBool::new(false);
+            let chunk = table.len().div_ceil(threads);
+            thread::scope(|s| {
+                for start in (0..table.len()).step_by(chunk.max(1)) {
+                    let (table, dtm, changed) = (&table, &dtm, &changed);
+                    s.spawn(move || {
+                        let mut board = Board::empty();
+                        let mut placed = vec![];
+                        for index in start..(start + chunk).min(table.len()) {
+                            if ply > 0 && dtm[index].load(Ordering::Relaxed) != 0 {
+                                continue;
+                            }
+                            if !table.setup(index, &mut board, &mut placed) {
+                                dtm[index].store(ILLEGAL, Ordering::Relaxed);
+                                continue;
+                            }
+                            let code = if ply == 0 {
+                                self.first_pass(&mut board)
+                            } else {
+                                self.resolve(table, dtm, &mut board, ply)
+                            };
+                            if code != 0 {
+                                dtm[index].store(code, Ordering::Relaxed);
+                                changed.store(true, Ordering::Relaxed);
+                            }
+                        }
+                    });
+                }
+            });
+            if ply > 0 && !changed.load(Ordering::Relaxed) && ply > longest_sub {
+                break;
+            }
+            ply += 1;
+        }
+        table.dtm = dtm.into_iter().map(|c| c.into_inner()).collect();
+        table
+    }
+    // 不合法（对方被将军或者对将）的局面和无着可走（将死或困毙）的局面
+    fn first_pass(&self, board: &mut Board) -> u16 {
+        if board.is_checked(board.turn.next()) {
+            ILLEGAL
+        } else if board.legal_moves().is_empty() {
+            Outcome::Loss(0).code()
+        } else {
+            0
+        }
+    }
+    // 第ply轮：有着法走到对方ply-1步后被杀的局面为胜，所有着法都走到对方不超过ply-1步胜的局面为负
+    fn resolve(&self, table: &Table, dtm: &[AtomicU16], board: &mut Board, ply: u16) -> u16 {
+        let mut best_win = u16::MAX;
+        let mut all_lose = true;
+        let mut longest_loss = 0;
+        for m in board.legal_moves() {
+            board.do_move(&m, false);
+            let outcome = if m.capture == Chess::None {
+                table
+                    .index(board, false)
+                    .and_then(|i| Outcome::from_code(dtm[i].load(Ordering::Relaxed)))
+            } else {
+                self.probe(board)
+            };
+            board.undo_move(&m);
+            match outcome {
+                Some(Outcome::Loss(plies)) => best_win = best_win.min(plies + 1),
+                Some(Outcome::Win(plies)) => longest_loss = longest_loss.max(plies + 1),
+                _ => all_lose = false,
+            }
+        }
+        if best_win <= ply {
+            Outcome::Win(best_win).code()
+        } else if all_lose && longest_loss <= ply {
+            Outcome::Loss(longest_loss).code()
+        } else {
+            0
+        }
+    }
+}
+
+impl Board {
+    // 加载或去掉残局库
+    pub fn set_tablebase(&mut self, tablebase: Option<Arc<Tablebase>>) {
+        self.tablebase = tablebase;
+    }
+    // 查询当前局面，揭棋的暗子无法查询
+    pub fn probe_tablebase(&self) -> Option<Outcome> {
+        if self.jieqi {
+            return None;
+        }
+        self.tablebase.as_ref()?.probe(self)
+    }
+    // 根节点按残局库走棋：胜时走最快的杀法，负时拖到最久，所有着法都能查到时才返回
+    pub fn tablebase_move(&mut self) -> Option<(Move, Outcome)> {
+        let outcome = self.probe_tablebase()?;
+        let mut best: Option<(i32, Move)> = None;
+        for m in self.legal_moves() {
+            self.do_move(&m, false);
+            let next = self.probe_tablebase();
+            self.undo_move(&m);
+            let value = -next?.score(0);
+            if best.as_ref().is_none_or(|(v, _)| value > *v) {
+                best = Some((value, m));
+            }
+        }
+        best.map(|(_, m)| (m, outcome))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::board::*;
+    use crate::rules::*;
+    use crate::tablebase::*;
+
+    #[test]
+    fn test_material() {
+        let material = Material::parse("KAANvKR").unwrap();
+        assert_eq!(material.name(), "KNAAvKR");
+        assert!(!material.is_canonical());
+        assert_eq!(material.canonical().name(), "KRvKNAA");
+        let captures: Vec<String> = material
+            .canonical()
+            .captures()
+            .iter()
+            .map(|m| m.name())
+            .collect();
+        assert_eq!(captures, vec!["KNAAvK", "KRvKAA", "KRvKNA"]);
+        let board = Board::from_fen("3ak4/4a4/9/9/9/9/9/9/4n4/3K1R3 w - - 0 1");
+        assert_eq!(Material::of_board(&board).name(), "KRvKNAA");
+        assert!(Material::parse("KRRRvK").is_err());
+        assert!(Material::parse("KRK").is_err());
+    }
+
+    #[test]
+    fn test_rook_wins() {
+        let mut tablebase = Tablebase::new(None);
+        let table = tablebase
+            .generate(&Material::parse("KRvK").unwrap(), 2, &mut |_| {})
+            .unwrap();
+        let (wins, _, _) = table.stats();
+        assert!(wins > 0);
+        // 光将对车，按最短的杀法走下去一定能将死
+        let mut board = Board::from_fen("4k4/9/9/9/9/9/9/9/9/3K4R w - - 0 1");
+        let mut outcome = tablebase.probe(&board).unwrap();
+        assert!(matches!(outcome, Outcome::Win(_)));
+        while outcome != Outcome::Loss(0) {
+            let m = board
+                .legal_moves()
+                .into_iter()
+                .find(|m| {
+                    board.do_move(m, false);
+                    let next = tablebase.probe(&board);
+                    board.undo_move(m);
+                    match (outcome, next) {
+                        (Outcome::Win(n), Some(Outcome::Loss(k))) => k + 1 == n,
+                        (Outcome::Loss(n), Some(Outcome::Win(k))) => k + 1 == n,
+                        _ => false,
+                    }
+                })
+                .unwrap();
+            board.do_move(&m, true);
+            outcome = tablebase.probe(&board).unwrap();
+        }
+        assert_eq!(judge(&board).map(|r| r.0), Some(GameResult::RedWin));
+        // 红黑互换后同一张表也能查
+        let board = Board::from_fen("3k4r/9/9/9/9/9/9/9/9/4K4 b - - 0 1");
+        assert!(matches!(tablebase.probe(&board), Some(Outcome::Win(_))));
+        // 光将对光将是和棋
+        let board = Board::from_fen("4k4/9/9/9/9/9/9/9/9/3K5 w - - 0 1");
+        assert_eq!(tablebase.probe(&board), Some(Outcome::Draw));
+    }
+
+    #[test]
+    fn test_search_probe() {
+        let mut tablebase = Tablebase::new(None);
+        tablebase
+            .generate(&Material::parse("KNvK").unwrap(), 2, &mut |_| {})
+            .unwrap();
+        let mut board = Board::from_fen("4k4/9/9/9/9/9/9/9/9/3K3N1 w - - 0 1");
+        board.set_tablebase(Some(Arc::new(tablebase)));
+        let outcome = board.probe_tablebase().unwrap();
+        let n = match outcome {
+            Outcome::Win(n) => n,
+            _ => panic!("{:?}", outcome),
+        };
+        // 搜索中查到的分数就是准确的杀棋步数
+        let (value, _) = board.iterative_deepening(2);
+        assert_eq!(value, outcome.score(0));
+        // 根节点走最快的杀法
+        let (m, _) = board.tablebase_move().unwrap();
+        board.do_move(&m, false);
+        assert_eq!(board.probe_tablebase(), Some(Outcome::Loss(n - 1)));
+    }
+
+    #[test]
+    fn test_stalemate_and_flying_general() {
+        let mut tablebase = Tablebase::new(None);
+        tablebase
+            .generate(&Material::parse("KPvK").unwrap(), 2, &mut |_| {})
+            .unwrap();
+        // 黑将走e9会对将，走d8会被兵吃，困毙判负
+        let board = Board::from_fen("3k5/9/3P5/9/9/9/9/9/9/4K4 b - - 0 1");
+        assert_eq!(tablebase.probe(&board), Some(Outcome::Loss(0)));
+        // 对将的局面不合法
+        let board = Board::from_fen("4k4/9/9/9/9/9/9/9/P8/4K4 w - - 0 1");
+        assert_eq!(tablebase.probe(&board), None);
+    }
+
+    #[test]
+    fn test_save_load() {
+        let dir = std::env::temp_dir().join(format!("tablebase-test-{}", std::process::id()));
+        let mut tablebase = Tablebase::new(Some(dir.clone()));
+        let material = Material::parse("KPvK").unwrap();
+        let table = tablebase
+            .generate(&material, 1, &mut |_| {})
+            .unwrap();
+        assert!(dir.join("KPvK.wdl").exists());
+        let loaded = Tablebase::new(Some(dir.clone()));
+        let other = loaded.table(&material).unwrap();
+        assert_eq!(other.stats(), table.stats());
+        assert_eq!(other.longest(), table.longest());
+        assert!(Table::from_dtm_bytes(&table.wdl_bytes()).is_err());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,338 @@
+use crate::board::{Board, Chess, ChessType, Player, Position, BOARD_HEIGHT, BOARD_WIDTH};
+use crate::constant::{MATE_BOUND, MAX, MIN};
+use crate::eval::EvalParams;
+use std::thread;
+
+type Squares = [[Chess; BOARD_WIDTH as usize]; BOARD_HEIGHT as usize];
+
+// 静态搜索结束后的平静局面和这盘棋的结果（红胜1，和0.5，黑胜0）
+#[derive(Clone, Debug)]
+pub struct QuietPosition {
+    chesses: Squares,
+    chesses_status: Squares,
+    turn: Player,
+    pub result: f64,
+}
+
+// 可以调整的一个参数
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum Param {
+    InitiativeBonus,
+    Term(usize, bool),                    // 第几个评价项，是否残局权重
+    Table(ChessType, bool, usize, usize), // 棋子，是否残局表，行，列
+}
+
+impl Param {
+    fn value_mut(self, params: &mut EvalParams) -> &mut i32 {
+        match self {
+            Param::InitiativeBonus => &mut params.initiative_bonus,
+            Param::Term(i, eg) => {
+                let (_, term) = params.terms_mut().into_iter().nth(i).unwrap();
+                if eg {
+                    &mut term.eg
+                } else {
+                    &mut term.mg
+                }
+            }
+            Param::Table(ct, eg, row, col) => {
+                let table = params.table_mut(ct);
+                if eg {
+                    &mut table.eg[row][col]
+                } else {
+                    &mut table.mg[row][col]
+                }
+            }
+        }
+    }
+}
+
+// 解析对局结果，支持1-0、0-1、1/2-1/2和0到1之间的小数
+pub fn parse_result(s: &str) -> Result<f64, String> {
+    match s.trim() {
+        "1-0" => Ok(1.0),
+        "0-1" => Ok(0.0),
+        "1/2-1/2" => Ok(0.5),
+        other => match other.parse::<f64>() {
+            Ok(v) if (0.0..=1.0).contains(&v) => Ok(v),
+            _ => Err(format!("invalid result {}", other)),
+        },
+    }
+}
+
+// 读入带对局结果的局面，每行第一个字段为FEN，最后一个字段为结果，用;分隔，
+// 空行和#开头的行跳过
+pub fn parse_positions(data: &str) -> Result<Vec<(String, f64)>, String> {
+    let mut positions = vec![];
+    for (i, line) in data.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        // 中间可以有其他字段，如局面分和最佳着法
+        let (fen, result) = match (line.split_once(';'), line.rsplit_once(';')) {
+            (Some((fen, _)), Some((_, result))) => (fen, result),
+            _ => return Err(format!("line {}: missing result", i + 1)),
+        };
+        let result = parse_result(result).map_err(|e| format!("line {}: {}", i + 1, e))?;
+        positions.push((fen.trim().to_owned(), result));
+    }
+    Ok(positions)
+}
+
+impl QuietPosition {
+    // 从局面出发做静态搜索，取主要变例末端的局面，已被将死的局面返回None
+    pub fn resolve(board: &Board, result: f64) -> Option<Self> {
+        // 缺将或者轮到走棋的一方可以直接吃将（揭棋翻开后的局面可能如此），不是合法局面
+        if board.king_position(Player::Red).is_none()
+            || board.king_position(Player::Black).is_none()
+            || board.is_checked(board.turn.next())
+        {
+            return None;
+        }
+        let mut board = board.clone();
+        board.distance = 0;
+        let mut pv = vec![];
+        // 用搜索中的静态搜索找到局面静止后的叶子
+        let value = board.quies_pv(MIN, MAX, Some(&mut pv));
+        if value.abs() >= MATE_BOUND {
+            return None;
+        }
+        for m in pv.iter() {
+            board.do_move(m, false);
+        }
+        Some(QuietPosition {
+            chesses: board.chesses,
+            chesses_status: board.chesses_status,
+            turn: board.turn,
+            result,
+        })
+    }
+    // 读入局面文件，已被将死或者不合法的局面跳过
+    pub fn parse(data: &str) -> Result<Vec<Self>, String> {
+        Ok(parse_positions(data)?
+            .into_iter()
+            .filter_map(|(fen, result)| QuietPosition::resolve(&Board::from_fen(&fen), result))
+            .collect())
+    }
+    // 红方视角的局面分
+    fn evaluate(&self, board: &mut Board) -> i32 {
+        board.chesses = self.chesses;
+        board.chesses_status = self.chesses_status;
+        board.turn = self.turn;
+        let v = board.evaluate(self.turn);
+        if self.turn == Player::Red {
+            v
+        } else {
+            -v
+        }
+    }
+}
+
+// 局面分转换为红方的预期得分
+pub fn sigmoid(value: i32, k: f64) -> f64 {
+    1.0 / (1.0 + 10f64.powf(-k * value as f64 / 400.0))
+}
+
+// Texel调参：以局面分预测对局结果的均方误差为目标，逐个参数加减step做局部搜索
+pub struct Tuner {
+    pub positions: Vec<QuietPosition>,
+    pub threads: usize,
+    pub k: f64,
+    pub tables: bool, // 是否调整子力位置分表
+}
+
+impl Tuner {
+    pub fn new(positions: Vec<QuietPosition>, threads: usize) -> Self {
+        Tuner {
+            positions,
+            threads: threads.max(1),
+            k: 1.0,
+            tables: true,
+        }
+    }
+
+    pub fn error(&self, params: &EvalParams) -> f64 {
+        if self.positions.is_empty() {
+            return 0.0;
+        }
+        let chunk_size = self.positions.len().div_ceil(self.threads);
+        let total: f64 = thread::scope(|s| {
+            let handles: Vec<_> = self
+                .positions
+                .chunks(chunk_size)
+                .map(|chunk| {
+                    s.spawn(move || {
+                        let mut board = Board::empty();
+                        board.eval_params = params.clone();
+                        chunk
+                            .iter()
+                            .map(|p| (p.result - sigmoid(p.evaluate(&mut board), self.k)).powi(2))
+                            .sum::<f64>()
+                    })
+                })
+                .collect();
+            handles
+                .into_iter()
+                .map(|h| h.join().unwrap())
+                .sum()
+        });
+        total / self.positions.len() as f64
+    }
+
+    // 先找使误差最小的缩放系数k，之后调参时k不变
+    pub fn find_k(&mut self, params: &EvalParams) -> f64 {
+        let mut best = (self.k, f64::MAX);
+        let mut step = 0.1;
+        let (mut low, mut high) = (0.0, 3.0);
+        for _ in 0..3 {
+            let mut k = low;
+            while k <= high {
+                self.k = k;
+                let e = self.error(params);
+                if e < best.1 {
+                    best = (k, e);
+                }
+                k += step;
+            }
+            low = (best.0 - step).max(0.0);
+            high = best.0 + step;
+            step /= 10.0;
+        }
+        self.k = best.0;
+        self.k
+    }
+
+    // 局面中出现过的棋子位置才调整对应的位置分
+    fn params(&self, params: &EvalParams) -> Vec<Param> {
+        let mut result = vec![Param::InitiativeBonus];
+        let mut params = params.clone();
+        for (i, (_, term)) in params.terms_mut().into_iter().enumerate() {
+            if term.enabled {
+                result.push(Param::Term(i, false));
+                result.push(Param::Term(i, true));
+            }
+        }
+        if !self.tables {
+            return result;
+        }
+        let types = [
+            ChessType::King,
+            ChessType::Advisor,
+            ChessType::Bishop,
+            ChessType::Knight,
+            ChessType::Rook,
+            ChessType::Cannon,
+            ChessType::Pawn,
+        ];
+        for ct in types {
+            for i in 0..BOARD_HEIGHT {
+                for j in 0..BOARD_WIDTH {
+                    let pos = Position::new(i, j);
+                    let used = self.positions.iter().any(|p| {
+                        p.chesses[i as usize][j as usize] == Chess::Red(ct)
+                            || p.chesses[pos.flip().row as usize][pos.flip().col as usize] == Chess::Black(ct)
+                    });
+                    if used {
+                        result.push(Param::Table(ct, false, i as usize, j as usize));
+                        result.push(Param::Table(ct, true, i as usize, j as usize));
+                    }
+                }
+            }
+        }
+        result
+    }
+
+    // 局部搜索，每轮结束后调用callback(轮数, 误差)，某一轮没有改进时停止
+    pub fn tune(
+        &self,
+        params: &EvalParams,
+        iterations: usize,
+        step: i32,
+        mut callback: impl FnMut(usize, f64, &EvalParams),
+    ) -> EvalParams {
+        let mut best = params.clone();
+        let mut best_error = self.error(&best);
+        let candidates = self.params(&best);
+        for iteration in 1..=iterations {
+            let mut improved = false;
+            for param in candidates.iter() {
+                for delta in [step, -step] {
+                    let mut params = best.clone();
+                    *param.value_mut(&mut params) += delta;
+                    let e = self.error(&params);
+                    if e < best_error {
+                        best = params;
+                        best_error = e;
+                        improved = true;
+                        break;
+                    }
+                }
+            }
+            callback(iteration, best_error, &best);
+            if !improved {
+                break;
+            }
+        }
+        best
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::board::*;
+    use crate::eval::*;
+    use crate::tune::*;
+
+    #[test]
+    fn test_parse_result() {
+        assert_eq!(parse_result("1-0"), Ok(1.0));
+        assert_eq!(parse_result(" 0-1"), Ok(0.0));
+        assert_eq!(parse_result("1/2-1/2"), Ok(0.5));
+        assert_eq!(parse_result("0.5"), Ok(0.5));
+        assert!(parse_result("2").is_err());
+    }
+
+    #[test]
+    fn test_resolve() {
+        // 红车可以白吃黑炮，静止后的局面少了黑炮
+        let board = Board::from_fen("3k5/9/9/9/c7R/9/9/9/9/5K3 w - - 0 1");
+        let position = QuietPosition::resolve(&board, 1.0).unwrap();
+        assert_eq!(position.turn, Player::Black);
+        assert_eq!(position.chesses[4][0], Chess::Red(ChessType::Rook));
+        // 已被将死的局面跳过
+        let board = Board::from_fen("4k4/9/9/9/9/9/9/9/4pp3/5K3 w - - 0 1");
+        assert!(QuietPosition::resolve(&board, 0.0).is_none());
+        // 红方走棋时黑将已被车将军，不合法
+        let board = Board::from_fen("3k5/9/9/9/9/9/9/9/9/3R1K3 w - - 0 1");
+        assert!(QuietPosition::resolve(&board, 1.0).is_none());
+    }
+
+    #[test]
+    fn test_tune() {
+        let data = "
+            # 红方多一个兵的局面都是红胜
+            3k5/9/9/9/9/9/4P4/9/9/5K3 w - - 0 1;1-0
+            3k5/9/9/9/9/2P6/9/9/9/5K3 b - - 0 1;1-0
+            3k5/9/9/9/9/9/9/9/9/5K3 w - - 0 1;1/2-1/2
+        ";
+        let positions = QuietPosition::parse(data).unwrap();
+        assert_eq!(positions.len(), 3);
+        assert_eq!(
+            parse_positions("3k5/9/9/9/9/9/9/9/9/5K3 w - - 0 1;12;e0e1;0-1"),
+            Ok(vec![("3k5/9/9/9/9/9/9/9/9/5K3 w - - 0 1".to_owned(), 0.0)])
+        );
+        assert!(QuietPosition::parse("3k5/9/9/9/9/9/9/9/9/5K3 w - - 0 1").is_err());
+
+        let mut tuner = Tuner::new(positions, 2);
+        tuner.tables = false;
+        let params = EvalParams::default();
+        tuner.find_k(&params);
+        let before = tuner.error(&params);
+        let tuned = tuner.tune(&params, 3, 5, |_, _, _| {});
+        let after = tuner.error(&tuned);
+        assert!(after <= before);
+        // 多线程计算的误差和单线程一样
+        let single = Tuner { threads: 1, ..tuner };
+        assert!((single.error(&tuned) - after).abs() < 1e-9);
+    }
+}
@@ -0,0 +1,374 @@
+use crate::board::{Board, Chess, ChessType, Move, Position, BOARD_HEIGHT, BOARD_WIDTH};
+use crate::constant::STARTPOS;
+use crate::pgn::{PgnGame, PgnMove};
+use encoding_rs::GBK;
+use std::fs;
+use std::path::Path;
+
+// XQStudio的XQF棋谱：1024字节的文件头（标题、对局信息、32个子的位置），之后是着法树。
+// 每个着法节点有后续着法和右侧兄弟（变着）两个标志，按先序排列。
+// 11版以后的文件加密：棋子位置、着法起点终点和注释长度各有偏移，
+// 文件头之后的每个字节还要减去按文件位置循环的32字节密钥流
+
+const HEADER_SIZE: usize = 1024;
+const KEY_STRING: &[u8; 32] = b"[(C) Copyright Mr. Dong Shiwei.]";
+// 文件头中32个子的顺序，红方在前，黑方在后
+const PIECE_ORDER: [ChessType; 16] = [
+    ChessType::Rook,
+    ChessType::Knight,
+    ChessType::Bishop,
+    ChessType::Advisor,
+    ChessType::King,
+    ChessType::Advisor,
+    ChessType::Bishop,
+    ChessType::Knight,
+    ChessType::Rook,
+    ChessType::Cannon,
+    ChessType::Cannon,
+    ChessType::Pawn,
+    ChessType::Pawn,
+    ChessType::Pawn,
+    ChessType::Pawn,
+    ChessType::Pawn,
+];
+// 文件头中的文本字段：标签名、位置和长度，第一个字节为文本长度，文本为GBK编码
+const TEXT_FIELDS: [(&str, usize, usize); 8] = [
+    ("Title", 80, 64),
+    ("Event", 208, 64),
+    ("Date", 272, 16),
+    ("Site", 288, 16),
+    ("Red", 304, 16),
+    ("Black", 320, 16),
+    ("Opening", 336, 64),
+    ("Annotator", 480, 16),
+];
+
+// 解密用的参数
+struct Keys {
+    version: u8,
+    piece: u8,
+    src: u8,
+    dst: u8,
+    comment: i32,
+    stream: [u8; 32],
+}
+
+impl Keys {
+    fn new(tag: &[u8]) -> Self {
+        let version = tag[2];
+        if version < 11 {
+            return Keys {
+                version,
+                piece: 0,
+                src: 0,
+                dst: 0,
+                comment: 0,
+                stream: [0; 32],
+            };
+        }
+        let square = |x: u8| (x as u32 * x as u32 * 54 + 221) as u8;
+        let piece = square(tag[13]).wrapping_mul(tag[13]);
+        let src = square(tag[14]).wrapping_mul(piece);
+        let dst = square(tag[15]).wrapping_mul(src);
+        let comment = (tag[12] as i32 * 256 + tag[13] as i32) % 32000 + 767;
+        let args: Vec<u8> = (0..4)
+            .map(|i| tag[8 + i] | (tag[12 + i] & tag[3]))
+            .collect();
+        let mut stream = [0; 32];
+        for (i, key) in stream.iter_mut().enumerate() {
+            *key = args[i % 4] & KEY_STRING[i];
+        }
+        Keys {
+            version,
+            piece,
+            src,
+            dst,
+            comment,
+            stream,
+        }
+    }
+}
+
+// 着法树中的一个节点
+struct Record {
+    from: u8,
+    to: u8,
+    has_next: bool,
+    has_right: bool,
+    comment: Option<String>,
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+    keys: Keys,
+}
+
+impl Reader<'_> {
+    fn read(&mut self, len: usize) -> Result<Vec<u8>, String> {
+        let bytes = self
+            .data
+            .get(self.pos..self.pos + len)
+            .ok_or("truncated xqf file")?;
+        let bytes = bytes
+            .iter()
+            .enumerate()
+            .map(|(i, b)| b.wrapping_sub(self.keys.stream[(self.pos + i) % 32]))
+            .collect();
+        self.pos += len;
+        Ok(bytes)
+    }
+    fn read_i32(&mut self) -> Result<i32, String> {
+        let bytes = self.read(4)?;
+        Ok(i32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+    fn read_record(&mut self) -> Result<Record, String> {
+        let bytes = self.read(4)?;
+        let keys = &self.keys;
+        let from = bytes[0].wrapping_sub(24).wrapping_sub(keys.src);
+        let to = bytes[1].wrapping_sub(32).wrapping_sub(keys.dst);
+        // 旧版本每个节点都有注释长度，新版本有注释时才有
+        let (has_next, has_right, comment_len) = if keys.version < 11 {
+            (bytes[2] & 0xf0 != 0, bytes[2] & 0x0f != 0, self.read_i32()?)
+        } else {
+            let comment_len = if bytes[2] & 0x20 != 0 {
+                self.read_i32()? - self.keys.comment
+            } else {
+                0
+            };
+            (bytes[2] & 0x80 != 0, bytes[2] & 0x40 != 0, comment_len)
+        };
+        let comment = if comment_len > 0 {
+            Some(decode_text(&self.read(comment_len as usize)?))
+        } else {
+            None
+        };
+        Ok(Record {
+            from,
+            to,
+            has_next,
+            has_right,
+            comment,
+        })
+    }
+    // 读入一个节点和它的后续着法，返回这条着法序列，以及右侧兄弟节点开始的变着
+    fn read_line(&mut self, board: &mut Board) -> Result<(Vec<PgnMove>, Vec<Vec<PgnMove>>), String> {
+        let record = self.read_record()?;
+        let (from, to) = match (square(record.from), square(record.to)) {
+            (Some(from), Some(to)) => (from, to),
+            _ => return Err(format!("invalid move {} {}", record.from, record.to)),
+        };
+        // 开始局面的走棋方由第一步着法决定
+        let player = board
+            .chess_at(from)
+            .player()
+            .ok_or_else(|| format!("no piece at {}", from.to_string()))?;
+        if board.move_history.is_empty() {
+            board.turn = player;
+        }
+        let m = Move {
+            player,
+            from,
+            to,
+            chess: board.chess_at(from),
+            capture: board.chess_at(to),
+        };
+        if !board.legal_moves().contains(&m) {
+            return Err(format!("illegal move {}{}", from.to_string(), to.to_string()));
+        }
+        let mut node = PgnMove::new(m.clone());
+        node.comment = record.comment;
+        let mut line = vec![node];
+        if record.has_next {
+            board.do_move(&m, false);
+            let (rest, variations) = self.read_line(board)?;
+            board.undo_move(&m);
+            line.extend(rest);
+            line[1].variations = variations;
+        }
+        let mut variations = vec![];
+        if record.has_right {
+            let (sibling, more) = self.read_line(board)?;
+            variations.push(sibling);
+            variations.extend(more);
+        }
+        Ok((line, variations))
+    }
+}
+
+// 坐标为列*10+行，行从红方底线算起
+fn square(xy: u8) -> Option<Position> {
+    let (x, y) = ((xy / 10) as i32, (xy % 10) as i32);
+    if xy < 90 && x < BOARD_WIDTH && y < BOARD_HEIGHT {
+        Some(Position::new(BOARD_HEIGHT - 1 - y, x))
+    } else {
+        None
+    }
+}
+
+fn decode_text(bytes: &[u8]) -> String {
+    let (text, _, _) = GBK.decode(bytes);
+    text.trim_end_matches('\0').trim().to_owned()
+}
+
+// 解析XQF棋谱，对局信息放在标签中，不是标准开局时开始局面放在FEN标签中
+pub fn parse_xqf(data: &[u8]) -> Result<PgnGame, String> {
+    if data.len() < HEADER_SIZE || &data[..2] != b"XQ" {
+        return Err("not a xqf file".to_owned());
+    }
+    let keys = Keys::new(&data[..16]);
+    let mut board = Board::empty();
+    for i in 0..32 {
+        let index = if keys.version < 12 {
+            i
+        } else {
+            (keys.piece as usize + 1 + i) % 32
+        };
+        let chess = if index < 16 {
+            Chess::Red(PIECE_ORDER[index])
+        } else {
+            Chess::Black(PIECE_ORDER[index - 16])
+        };
+        // 位置不在棋盘上的子已经被吃掉
+        if let Some(pos) = square(data[16 + i].wrapping_sub(keys.piece)) {
+            board.set_chess(pos, chess, false);
+        }
+    }
+    // 重新生成局面以计算zobrist值
+    let mut board = Board::from_fen(&board.to_fen());
+
+    let mut game = PgnGame::default();
+    game.set_tag("Game", "Chinese Chess");
+    for (name, offset, size) in TEXT_FIELDS {
+        let len = (data[offset] as usize).min(size - 1);
+        let text = decode_text(&data[offset + 1..offset + 1 + len]);
+        if !text.is_empty() {
+            game.set_tag(name, &text);
+        }
+    }
+    let result = match data[51] {
+        1 => "1-0",
+        2 => "0-1",
+        3 => "1/2-1/2",
+        _ => "*",
+    };
+    game.set_tag("Result", result);
+
+    // 第一个节点没有着法，只有开局前的注释
+    let mut reader = Reader {
+        data,
+        pos: HEADER_SIZE,
+        keys,
+    };
+    let root = reader.read_record()?;
+    game.comment = root.comment;
+    if root.has_next {
+        let (moves, variations) = reader.read_line(&mut board)?;
+        game.moves = moves;
+        game.moves[0].variations = variations;
+    }
+    let fen = board.to_fen();
+    if fen != STARTPOS {
+        game.set_tag("FEN", &fen);
+    }
+    Ok(game)
+}
+
+pub fn load_xqf(path: impl AsRef<Path>) -> Result<PgnGame, String> {
+    let path = path.as_ref();
+    let data = fs::read(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+    parse_xqf(&data)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::board::*;
+    use crate::xqf::*;
+
+    // 测试棋谱由resources/xqf/make_fixtures.py生成
+    const PLAIN_V10: &[u8] = include_bytes!("../resources/xqf/plain_v10.xqf");
+    const ENCRYPTED_V11: &[u8] = include_bytes!("../resources/xqf/encrypted_v11.xqf");
+    const ENCRYPTED_V18: &[u8] = include_bytes!("../resources/xqf/encrypted_v18.xqf");
+
+    fn line(moves: &[PgnMove]) -> Vec<String> {
+        moves
+            .iter()
+            .map(|node| format!("{}{}", node.m.from.to_string(), node.m.to.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn test_plain_file() {
+        let game = parse_xqf(PLAIN_V10).unwrap();
+        assert_eq!(game.tag("Title"), Some("中炮对屏风马"));
+        assert_eq!(game.tag("Event"), Some("测试赛"));
+        assert_eq!(game.tag("Date"), Some("2024.05.01"));
+        assert_eq!(game.tag("Site"), Some("北京"));
+        assert_eq!(game.tag("Red"), Some("张三"));
+        assert_eq!(game.tag("Black"), Some("李四"));
+        assert_eq!(game.tag("Annotator"), None);
+        assert_eq!(game.tag("Result"), Some("1-0"));
+        assert_eq!(game.tag("FEN"), None);
+        assert_eq!(game.comment.as_deref(), Some("中炮对屏风马的常见开局"));
+        assert_eq!(line(&game.moves), ["h2e2", "h9g7", "h0g2", "i9h9", "i0h0", "b9c7"]);
+        assert_eq!(game.moves[0].comment.as_deref(), Some("中炮"));
+        assert_eq!(game.moves[5].comment.as_deref(), Some("屏风马\n双方布局完成"));
+        assert_eq!(game.moves[1].variations.len(), 1);
+        assert_eq!(line(&game.moves[1].variations[0]), ["h7e7", "h0g2"]);
+        assert_eq!(game.moves[1].variations[0][0].comment.as_deref(), Some("顺炮"));
+        assert_eq!(game.to_board().move_history.len(), 6);
+    }
+
+    #[test]
+    fn test_encrypted_file() {
+        // 11版加密，棋子位置不循环移动
+        let game = parse_xqf(ENCRYPTED_V11).unwrap();
+        assert_eq!(game.tag("Title"), Some("仙人指路对卒底炮"));
+        assert_eq!(game.tag("Red"), Some("王五"));
+        assert_eq!(game.tag("Black"), Some("赵六"));
+        assert_eq!(game.tag("Result"), Some("1/2-1/2"));
+        assert_eq!(game.tag("FEN"), None);
+        assert_eq!(game.comment, None);
+        assert_eq!(line(&game.moves), ["c3c4", "h7c7", "h0g2"]);
+        assert_eq!(game.moves[0].comment.as_deref(), Some("仙人指路"));
+        assert_eq!(game.moves[1].comment.as_deref(), Some("卒底炮"));
+        assert_eq!(game.moves[2].comment, None);
+        assert_eq!(line(&game.moves[1].variations[0]), ["g6g5"]);
+        assert_eq!(game.moves[1].variations[0][0].comment.as_deref(), Some("对兵局"));
+
+        // 18版加密，黑先的残局
+        let game = parse_xqf(ENCRYPTED_V18).unwrap();
+        assert_eq!(game.tag("Title"), Some("单车胜单卒"));
+        assert_eq!(game.tag("Event"), Some("残局练习"));
+        assert_eq!(game.tag("Annotator"), Some("测试"));
+        assert_eq!(game.tag("FEN"), Some("4k4/9/9/9/9/9/4p4/9/9/R2K5 b - - 0 1"));
+        assert_eq!(game.comment.as_deref(), Some("黑先，红胜"));
+        assert_eq!(line(&game.moves), ["e3d3", "a0a9", "e9e8", "a9a8", "e8e7"]);
+        assert_eq!(game.moves[0].m.player, Player::Black);
+        assert_eq!(game.moves[1].comment.as_deref(), Some("将军"));
+        assert_eq!(game.moves[4].comment.as_deref(), Some("黑将只能上来"));
+        let variations: Vec<Vec<String>> = game.moves[1]
+            .variations
+            .iter()
+            .map(|v| line(v))
+            .collect();
+        assert_eq!(variations, [["a0a8"], ["d0d1"]]);
+        assert_eq!(game.moves[1].variations[1][0].comment.as_deref(), Some("变着：出帅"));
+
+        let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("resources/xqf/encrypted_v18.xqf");
+        assert_eq!(load_xqf(path).unwrap().moves, game.moves);
+    }
+
+    #[test]
+    fn test_invalid_file() {
+        assert!(parse_xqf(b"PK\x03\x04").is_err());
+        // 文件被截断
+        assert!(parse_xqf(&PLAIN_V10[..PLAIN_V10.len() - 2]).is_err());
+        assert!(parse_xqf(&ENCRYPTED_V18[..HEADER_SIZE + 2]).is_err());
+        // 红炮不在棋盘上，第一步没有棋子可走
+        let mut data = PLAIN_V10.to_vec();
+        data[16 + 10] = 0xff;
+        assert!(parse_xqf(&data).is_err());
+        assert!(load_xqf("/nonexistent/game.xqf").is_err());
+    }
+}
@@ -71,13 +71,16 @@ mod test {
 
     #[test]
     fn test_zobrist() {
-        println!("{}", Zobristable::new().calc_chesses(&Board::init().chesses));
+        println!(
+            "{}",
+            Zobristable::new().calc_chesses(&Board::init(false, false).chesses)
+        );
     }
 
     #[test]
     fn test_zobrist_move() {
         let zorbis_table = Zobristable::new();
-        let hash = zorbis_table.calc_chesses(&Board::init().chesses);
+        let hash = zorbis_table.calc_chesses(&Board::init(false, false).chesses);
         let m = Move {
             player: crate::board::Player::Red,
             from: Position::new(0, 0),
@@ -1,7 +1,12 @@
-use engine::board::{Board, Player, BOARD_HEIGHT, BOARD_WIDTH};
+use engine::board::{Board, Chess, Player, Position, BOARD_HEIGHT, BOARD_WIDTH};
+use engine::notation::{move_list, Notation};
+use engine::pgn::PgnGame;
+use engine::rules::{judge, GameResult, Reason};
 use fltk::{
     app,
+    browser::HoldBrowser,
     button::Button,
+    dialog,
     enums::*,
     frame::Frame,
     group::*,
@@ -9,25 +14,153 @@ use fltk::{
     prelude::*,
     window::*,
 };
+use std::cell::{Cell, RefCell};
+use std::rc::Rc;
+use std::time::Instant;
 
 const CHESS_SIZE: usize = 57;
 const CHESS_BOARD_WIDTH: i32 = 521;
 const CHESS_BOARD_HEIGHT: i32 = 577;
+const PANEL_WIDTH: i32 = 120;
+// 每方的用时，以秒为单位
+const GAME_TIME: u32 = 10 * 60;
 
-pub fn ui(mut game: Board) -> anyhow::Result<()> {
+// 走棋后需要刷新的控件
+#[derive(Clone)]
+struct View {
+    window: Window,
+    group: Group,
+    moves: HoldBrowser,
+    captured: Frame,
+    clock: Frame,
+    // 双方剩余的秒数，按红黑的顺序
+    clocks: Rc<Cell<[u32; 2]>>,
+    // 第n项为走完第n步时双方的钟，悔棋和复盘时恢复
+    clock_history: Rc<RefCell<Vec<[u32; 2]>>>,
+    notation: Rc<Cell<Notation>>,
+    // 认输等棋盘上看不出来的结果
+    result: Rc<Cell<Option<(GameResult, Reason)>>>,
+}
+
+// 对局结果，没有结束时为None
+fn game_result(view: &View, game: &Board) -> Option<(GameResult, Reason)> {
+    if view.result.get().is_some() {
+        return view.result.get();
+    }
+    // 打开的棋谱可能没有将帅
+    if game.king_position(Player::Red).is_none() || game.king_position(Player::Black).is_none() {
+        return None;
+    }
+    judge(game)
+}
+
+fn result_text((result, reason): (GameResult, Reason)) -> String {
+    let winner = match result {
+        GameResult::RedWin => "红方胜",
+        GameResult::BlackWin => "黑方胜",
+        GameResult::Draw => "和棋",
+    };
+    format!("{}（{}）", winner, reason)
+}
+
+fn clock_text(clocks: [u32; 2]) -> String {
+    format!(
+        "红 {}:{:02}  黑 {}:{:02}",
+        clocks[0] / 60,
+        clocks[0] % 60,
+        clocks[1] / 60,
+        clocks[1] % 60
+    )
+}
+
+// 双方的钟回到开局的时间，plies为棋谱的步数
+fn reset_clocks(view: &View, plies: usize) {
+    view.clocks.set([GAME_TIME; 2]);
+    *view.clock_history.borrow_mut() = vec![[GAME_TIME; 2]; plies + 1];
+}
+
+// 走完一步后记下双方的钟，复盘时走了别的着法就丢掉后面的记录
+fn record_clocks(view: &View, game: &Board) {
+    let mut history = view.clock_history.borrow_mut();
+    history.truncate(game.move_history.len());
+    history.push(view.clocks.get());
+}
+
+// 悔棋和复盘后恢复当前局面时双方的钟
+fn restore_clocks(view: &View, game: &Board) {
+    let history = view.clock_history.borrow();
+    if let Some(clocks) = history.get(game.move_history.len()) {
+        view.clocks.set(*clocks);
+    }
+}
+
+// 电脑思考时事件循环停着，钟不会走，走完按用掉的时间扣电脑的钟
+fn robot_move(view: &View, game: &mut Board) {
+    let side = game.robot_side.value() as usize;
+    let start = Instant::now();
+    if game.robot_move() {
+        let mut clocks = view.clocks.get();
+        clocks[side] = clocks[side].saturating_sub(start.elapsed().as_secs_f64().round() as u32);
+        view.clocks.set(clocks);
+        record_clocks(view, game);
+    }
+}
+
+// 开始新局：0为同样的设置，1为交换双方，2为切换揭棋和标准象棋
+fn new_game(game: &mut Board, choice: i32) {
+    let (mut jieqi, mut robot_side) = (game.jieqi, game.robot_side);
+    match choice {
+        1 => robot_side = robot_side.next(),
+        2 => jieqi = !jieqi,
+        _ => {}
+    }
+    *game = Board::init(jieqi, game.robot);
+    game.robot_side = robot_side;
+}
+
+// 棋盘格子的位置和大小，padding为四周缩进的像素
+fn cell_rect(pos: Position, padding: usize) -> (i32, i32, i32, i32) {
+    let x = (pos.col as usize + 1) * CHESS_SIZE - CHESS_SIZE / 2 - 24 + padding;
+    let y = (pos.row as usize + 1) * CHESS_SIZE - CHESS_SIZE / 2 - 24 + padding;
+    let size = CHESS_SIZE - 2 * padding;
+    (x as i32, y as i32, size as i32, size as i32)
+}
+
+// 在格子上画一个标记，要在棋子之前加入才会被棋子盖住
+fn mark(group: &mut Group, pos: Position, padding: usize, frame: FrameType, color: Color) {
+    let (x, y, w, h) = cell_rect(pos, padding);
+    let mut marker = Frame::new(x, y, w, h, "");
+    marker.set_frame(frame);
+    marker.set_color(color);
+    group.add(&marker);
+}
+
+// 被吃掉的棋子的名字
+fn chess_names(chesses: &[Chess]) -> String {
+    chesses
+        .iter()
+        .filter_map(|chess| {
+            chess
+                .chess_type()
+                .map(|t| t.name_value(Chess::None, chess.player()))
+        })
+        .collect()
+}
+
+pub fn ui(game: Board) -> anyhow::Result<()> {
     let app = app::App::default();
     let pand = 1;
     let mut top_window = Window::new(
         100,
         100,
-        CHESS_BOARD_WIDTH + 120,
+        CHESS_BOARD_WIDTH + PANEL_WIDTH,
         CHESS_BOARD_HEIGHT + pand * 2,
         "中国象棋",
     );
 
     let mut chess_window = Window::default()
         .with_pos(pand, pand)
-        .with_size(CHESS_BOARD_WIDTH + 120, CHESS_BOARD_HEIGHT);
+        .with_size(CHESS_BOARD_WIDTH + PANEL_WIDTH, CHESS_BOARD_HEIGHT);
 
     {
         // 画棋盘
@@ -37,12 +170,56 @@ pub fn ui(mut game: Board) -> anyhow::Result<()> {
             .draw(move |f| background.draw(f.x(), f.y(), f.width(), f.height()));
     }
 
-    let mut flex = Flex::default_fill();
+    let mut flex = Flex::default_fill().row();
 
     let mut group = Group::default_fill();
     flex.fixed(&group, CHESS_BOARD_WIDTH);
+    group.end();
+
+    // 右侧的按钮、着法列表和双方被吃的子
+    let mut vpack = Pack::default_fill().with_type(PackType::Vertical);
+    vpack.set_spacing(5);
+    let mut undo_button = Button::default()
+        .with_size(0, 30)
+        .with_label("悔棋");
+    let mut redo_button = Button::default()
+        .with_size(0, 30)
+        .with_label("还原");
+    let mut notation_button = Button::default()
+        .with_size(0, 30)
+        .with_label("记法：中文");
+    let mut resign_button = Button::default()
+        .with_size(0, 30)
+        .with_label("认输");
+    let mut new_game_button = Button::default()
+        .with_size(0, 30)
+        .with_label("新局");
+    let mut clock = Frame::default().with_size(0, 30);
+    clock.set_label_size(14);
+    let mut moves = HoldBrowser::default().with_size(0, CHESS_BOARD_HEIGHT - 6 * 30 - 7 * 5 - 80);
+    moves.set_text_size(14);
+    let mut captured = Frame::default().with_size(0, 80);
+    captured.set_align(Align::Left | Align::Top | Align::Inside | Align::Wrap);
+    captured.set_label_size(14);
+    vpack.end();
+    flex.end();
 
     fn redrawn(group: &mut Group, game: &Board) {
+        // 上一步的起点和终点
+        if let Some(m) = game.move_history.last() {
+            for pos in [m.from, m.to] {
+                mark(group, pos, 1, FrameType::FlatBox, Color::from_rgb(120, 170, 230));
+            }
+        }
+        // 选中的棋子可以走到的位置：能吃子的格子画底色，空位画圆点
+        let targets = &game.select_targets;
+        for pos in targets.iter() {
+            if game.chess_at(*pos) != Chess::None {
+                mark(group, *pos, 1, FrameType::FlatBox, Color::from_rgb(90, 190, 90));
+            }
+        }
+        let checked_king = game.checked_king();
+
         for x in 0..BOARD_WIDTH as usize {
             for y in 0..BOARD_HEIGHT as usize {
                 let chess = game.chesses[y][x];
@@ -53,18 +230,11 @@ pub fn ui(mut game: Board) -> anyhow::Result<()> {
                     None => continue,
                 };
 
-                let selected_chess = game.select_pos == (x as i32, y as i32).into();
-
-                let x = (x + 1) * CHESS_SIZE - CHESS_SIZE / 2 - 24;
-                let y = (y + 1) * CHESS_SIZE - CHESS_SIZE / 2 - 24;
-                let padding = 4;
-                let mut button = Button::new(
-                    (x + padding) as i32,
-                    (y + padding) as i32,
-                    (CHESS_SIZE - 2 * padding) as i32,
-                    (CHESS_SIZE - 2 * padding) as i32,
-                    title,
-                );
+                let pos: Position = (x as i32, y as i32).into();
+                let selected_chess = game.select_pos == Some(pos);
+
+                let (x, y, w, h) = cell_rect(pos, 4);
+                let mut button = Button::new(x, y, w, h, title);
                 button.set_label_color(if let Some(Player::Red) = chess.player() {
                     Color::Red
                 } else {
@@ -78,40 +248,268 @@ pub fn ui(mut game: Board) -> anyhow::Result<()> {
                 if selected_chess {
                     button.set_color(Color::Black);
                 }
+                // 被将军的将帅
+                if checked_king == Some(pos) {
+                    button.set_color(Color::Red);
+                    button.set_label_color(Color::White);
+                }
                 group.add(&button);
             }
         }
+
+        for pos in targets.iter() {
+            if game.chess_at(*pos) == Chess::None {
+                mark(group, *pos, 22, FrameType::OvalBox, Color::from_rgb(90, 190, 90));
+            }
+        }
+    }
+
+    fn refresh(view: &mut View, game: &Board) {
+        view.group.clear();
+        view.window.redraw();
+        redrawn(&mut view.group, game);
+
+        // 第一行为开局，第n+1行为第n步，黑方的着法缩进
+        view.moves.clear();
+        view.moves.add("开局");
+        let mut number = 1;
+        for (m, text) in game
+            .game_moves()
+            .iter()
+            .zip(move_list(game, view.notation.get()))
+        {
+            if m.player == Player::Red {
+                view.moves.add(&format!("{}. {}", number, text));
+            } else {
+                view.moves.add(&format!("    {}", text));
+                number += 1;
+            }
+        }
+        let current = game.move_history.len() as i32 + 1;
+        view.moves.select(current);
+        view.moves.make_visible(current);
+
+        view.clock
+            .set_label(&clock_text(view.clocks.get()));
+
+        let captured = game.captured_chesses();
+        view.captured.set_label(&format!(
+            "红方失子\n{}\n黑方失子\n{}",
+            chess_names(&captured[0]),
+            chess_names(&captured[1])
+        ));
+        if let Some(result) = game_result(view, game) {
+            view.captured
+                .set_label(&format!("{}\n{}", view.captured.label(), result_text(result)));
+        }
     }
 
-    redrawn(&mut group, &game);
-    chess_window.handle(move |w, event| {
+    // 对局结束或者点了新局时询问怎样开始新局，关掉对话框可以继续复盘
+    fn ask_new_game(view: &mut View, game: &mut Board, message: &str) {
+        let mode = if game.jieqi { "改下象棋" } else { "改下揭棋" };
+        if let Some(choice) = dialog::choice2_default(message, "新局", "换边", mode) {
+            new_game(game, choice);
+            view.result.set(None);
+            reset_clocks(view, 0);
+            // 电脑执红时先走
+            robot_move(view, game);
+            refresh(view, game);
+        }
+    }
+
+    let game = Rc::new(RefCell::new(game));
+    let mut view = View {
+        window: chess_window.clone(),
+        group,
+        moves,
+        captured,
+        clock,
+        clocks: Rc::new(Cell::new([GAME_TIME; 2])),
+        clock_history: Rc::new(RefCell::new(vec![[GAME_TIME; 2]])),
+        notation: Rc::new(Cell::new(Notation::Chinese)),
+        result: Rc::new(Cell::new(None)),
+    };
+    refresh(&mut view, &game.borrow());
+
+    // 悔棋和还原，人机对战时电脑的着法一起退回
+    undo_button.set_shortcut(Shortcut::Ctrl | 'z');
+    undo_button.set_callback({
+        let game = game.clone();
+        let mut view = view.clone();
+        move |_| {
+            let mut game = game.borrow_mut();
+            // 从正在下的局面离开前记下双方的钟，还原到这里时接着走
+            if game.redo_moves.is_empty() {
+                record_clocks(&view, &game);
+            }
+            if game.undo() {
+                view.result.set(None);
+                restore_clocks(&view, &game);
+                refresh(&mut view, &game);
+            }
+        }
+    });
+    redo_button.set_shortcut(Shortcut::Ctrl | 'y');
+    redo_button.set_callback({
+        let game = game.clone();
+        let mut view = view.clone();
+        move |_| {
+            let mut game = game.borrow_mut();
+            if game.redo() {
+                restore_clocks(&view, &game);
+                refresh(&mut view, &game);
+            }
+        }
+    });
+
+    // 切换中文和WXF记法
+    notation_button.set_callback({
+        let game = game.clone();
+        let mut view = view.clone();
+        move |b| {
+            let (notation, label) = match view.notation.get() {
+                Notation::Chinese => (Notation::Wxf, "记法：WXF"),
+                _ => (Notation::Chinese, "记法：中文"),
+            };
+            view.notation.set(notation);
+            b.set_label(label);
+            refresh(&mut view, &game.borrow());
+        }
+    });
+    // 认输，人机对战时由人认输，否则由走棋方认输
+    resign_button.set_callback({
+        let game = game.clone();
+        let mut view = view.clone();
+        move |_| {
+            let mut game = game.borrow_mut();
+            if game_result(&view, &game).is_some() {
+                return;
+            }
+            let player = if game.robot { game.robot_side.next() } else { game.turn };
+            let result = (GameResult::loss(player), Reason::Resignation);
+            view.result.set(Some(result));
+            refresh(&mut view, &game);
+            ask_new_game(&mut view, &mut game, &result_text(result));
+        }
+    });
+    new_game_button.set_callback({
+        let game = game.clone();
+        let mut view = view.clone();
+        move |_| ask_new_game(&mut view, &mut game.borrow_mut(), "开始新局")
+    });
+    // 走棋方的钟从开局起每秒走一下，用完判超时负。复盘和对局结束后停钟
+    app::add_timeout3(1.0, {
+        let game = game.clone();
+        let mut view = view.clone();
+        move |handle| {
+            app::repeat_timeout3(1.0, &handle);
+            // 弹出对话框时棋盘正被占用
+            let Ok(mut game) = game.try_borrow_mut() else {
+                return;
+            };
+            if !game.redo_moves.is_empty() || game_result(&view, &game).is_some() {
+                return;
+            }
+            let mut clocks = view.clocks.get();
+            let side = game.turn.value() as usize;
+            clocks[side] = clocks[side].saturating_sub(1);
+            view.clocks.set(clocks);
+            view.clock.set_label(&clock_text(clocks));
+            // 电脑思考的时间走完才扣，也可能是电脑超时
+            if let Some(player) = [Player::Red, Player::Black]
+                .into_iter()
+                .find(|player| clocks[player.value() as usize] == 0)
+            {
+                let result = (GameResult::loss(player), Reason::Timeout);
+                view.result.set(Some(result));
+                refresh(&mut view, &game);
+                ask_new_game(&mut view, &mut game, &result_text(result));
+            }
+        }
+    });
+    // 点击着法列表跳到那一步之后的局面
+    view.moves.set_callback({
+        let game = game.clone();
+        let mut view = view.clone();
+        move |b| {
+            let line = b.value();
+            if line > 0 {
+                let mut game = game.borrow_mut();
+                if game.redo_moves.is_empty() {
+                    record_clocks(&view, &game);
+                }
+                game.goto_ply(line as usize - 1);
+                restore_clocks(&view, &game);
+                refresh(&mut view, &game);
+            }
+        }
+    });
+
+    chess_window.handle(move |_, event| {
         if let Event::Push = event {
             let (click_x, click_y) = app::event_coords();
+            // 右侧按钮的点击交给按钮处理
+            if click_x >= CHESS_BOARD_WIDTH {
+                return false;
+            }
             let (x, y) = (click_x / CHESS_SIZE as i32, click_y / CHESS_SIZE as i32);
             // dbg!(x, y);
             // 点击棋盘
+            let mut game = game.borrow_mut();
+            // 对局结束后不能再走棋，可以悔棋、复盘或者开始新局
+            if game_result(&view, &game).is_some() {
+                return true;
+            }
+            // 复盘到轮到电脑走的局面时，点击棋盘让电脑接着走
+            let plies = game.move_history.len();
             game.click((x, y));
-            group.clear();
-
-            game.robot_move();
-            w.redraw();
-            redrawn(&mut group, &game);
+            if game.move_history.len() != plies {
+                record_clocks(&view, &game);
+            }
+            if game_result(&view, &game).is_none() {
+                robot_move(&view, &mut game);
+            }
+            refresh(&mut view, &game);
+            if let Some(result) = game_result(&view, &game) {
+                ask_new_game(&mut view, &mut game, &result_text(result));
+            }
             return true;
         }
+        // Ctrl+S保存棋谱，Ctrl+O打开棋谱
+        if matches!(event, Event::KeyDown | Event::Shortcut) && app::event_state().contains(EventState::Ctrl) {
+            let key = app::event_key();
+            if key == Key::from_char('s') {
+                if let Some(path) = dialog::file_chooser("保存棋谱", "*.pgn", ".", false) {
+                    // 复盘时悔掉的着法也要保存，走到最后一步再导出
+                    let mut board = game.borrow().clone();
+                    board.goto_ply(board.game_moves().len());
+                    if let Err(e) = PgnGame::from_board(&board).save(&path, Notation::Chinese) {
+                        dialog::alert_default(&e);
+                    }
+                }
+                return true;
+            }
+            if key == Key::from_char('o') {
+                if let Some(path) = dialog::file_chooser("打开棋谱", "*.pgn", ".", false) {
+                    match PgnGame::load(&path) {
+                        Ok(pgn) => {
+                            let mut game = game.borrow_mut();
+                            let (robot, robot_side) = (game.robot, game.robot_side);
+                            *game = pgn.to_board();
+                            game.robot = robot;
+                            game.robot_side = robot_side;
+                            view.result.set(None);
+                            reset_clocks(&view, game.game_moves().len());
+                            refresh(&mut view, &game);
+                        }
+                        Err(e) => dialog::alert_default(&e),
+                    }
+                }
+                return true;
+            }
+        }
         false
     });
-    // let mut vpack = Pack::default_fill().with_type(PackType::Vertical);
-    // vpack.set_spacing(10);
-    // flex.add(&vpack);
-    // Button::default().with_label("悔棋");
-    // Button::default().with_label("功能");
-    // Button::default().with_label("功能");
-    // Button::default().with_label("功能");
-    // Button::default().with_label("功能");
-    // vpack.end();
-    // vpack.auto_layout();
-    // flex.fixed(&Group::default().with_size(10, 10), 10);
-    // flex.end();
     top_window.end();
     top_window.show();
     app.run().unwrap();